@@ -89,6 +89,10 @@ class Linker:
         self.flags.append("--nmagic")
         return self
 
+    def emit_relocs(self):
+        self.flags.append("--emit-relocs")
+        return self
+
     def link(self, path):
         if self._files:
             cmd = f'{" ".join(self.flags)} {" ".join(self._files)} -o {path if path else "temp_obj"}'
@@ -105,14 +109,20 @@ class UefiBuilder:
 
     def __init__(self):
         self._release = False
+        self._kaslr = True
 
     def release(self):
         self._release = True
         return self
 
+    def no_kaslr(self):
+        self._kaslr = False
+        return self
+
     def build(self):
         mode = 'release' if self._release else 'debug'
-        cmd = f'cargo xbuild  --target "x86_64-unknown-uefi" --{"release" if self._release else ""}'
+        features = '' if self._kaslr else '--no-default-features --features "efi"'
+        cmd = f'cargo xbuild  --target "x86_64-unknown-uefi" {features} --{"release" if self._release else ""}'
         os.chdir(UEFI_PATH)
         cmd = popen(cmd)
         context = cmd.read()
@@ -160,6 +170,9 @@ class KernelBuilder:
             cmd = f"cargo xbuild --features \"{' '.join(self.kind)}\" --{'release' if self._release else ''}"
         else:
             cmd = f"cargo xbuild {'--release' if self._release else ''}"
+        # heap_debug walks the rbp chain, keep frame pointers only for that build
+        if "heap_debug" in self.kind:
+            cmd = f'RUSTFLAGS="-C force-frame-pointers=yes" {cmd}'
         os.chdir(KERNEL_PATH)
         cmd = popen(cmd)
         context = cmd.read()
@@ -248,6 +261,7 @@ class UefiArea(Area):
         if self.field("release"):
             p.release()
         kernel_file = Linker() \
+            .emit_relocs() \
             .script_file(self.link_file()) \
             .add_file(p.build()) \
             .link(join(WORK_PATH, "kernel_file"))
@@ -255,6 +269,8 @@ class UefiArea(Area):
         efi = UefiBuilder()
         if self.field("release"):
             efi.release()
+        if not self.field("kaslr"):
+            efi.no_kaslr()
         efi.build()
         copyfile(join(UEFI_PATH,
                       f"target/x86_64-unknown-uefi/{'release' if self.field('release') else 'debug'}/droll_os.efi"),
@@ -17,6 +17,10 @@ MAGIC_CHECK_FAILED  equ     'M'
 CPUID_CHECK_FAILED  equ     'C'
 NO_LONG_MODE        equ     'L'
 STACK_SIZE          equ     0x1000 * 4
+RECURSIVE_INDEX     equ     510
+KERNEL_P4_INDEX     equ     511
+KERNEL_P5_INDEX     equ     511
+KERNEL_P3_INDEX     equ     510
 
 ;==========================================
 ;               entry point
@@ -28,15 +32,24 @@ extern long_mode_entry
 ;==========================================
 ;               bss section
 ;==========================================
-section .bss
+; the boot code runs before paging, everything here lives at physical addresses
+section .boot.bss nobits alloc write align=4096
 bits 32
 align 0x1000
+p5_table:
+    resb 0x1000
 p4_table:
     resb 0x1000
 p3_table:
     resb 0x1000
 p2_table:
 	resb 0x1000
+p4_high_table:
+    resb 0x1000
+p3_high_table:
+    resb 0x1000
+p2_high_table:
+    resb 0x1000
 stack_end:
 	resb STACK_SIZE
 stack_start:
@@ -44,7 +57,7 @@ stack_start:
 ;==========================================
 ;               read only section
 ;==========================================
-section .rodata
+section .boot.rodata progbits alloc noexec nowrite
 gdt:
 	dq 0
 .code_segment: equ $ - gdt
@@ -56,7 +69,7 @@ gdt:
 ;==========================================
 ;               code section
 ;==========================================
-section .text
+section .boot.text progbits alloc exec nowrite
 bits 32
 _start:
 	mov esp,stack_start
@@ -91,6 +104,21 @@ set_up_page_tables:
     or eax, 0b11 ; present + writable
     mov [p3_table], eax
 
+    ; map the kernel area (-2GiB) to the higher half P3 table,
+    ; its P2 table maps the same first 1GiB so the kernel can run at KERNEL_OFFSET
+    mov eax, p3_high_table
+    or eax, 0b11
+    mov [p4_table + KERNEL_P4_INDEX * 8], eax
+
+    mov eax, p2_high_table
+    or eax, 0b11
+    mov [p3_high_table + KERNEL_P3_INDEX * 8], eax
+
+    ; recursive map, the kernel accesses page tables through this P4 entry
+    mov eax, p4_table
+    or eax, 0b11
+    mov [p4_table + RECURSIVE_INDEX * 8], eax
+
     ; map each P2 entry to a huge 2MiB page
     mov ecx, 0         ; counter variable
 
@@ -100,15 +128,49 @@ set_up_page_tables:
     mul ecx            ; start address of ecx-th page
     or eax, 0b10000011 ; present + writable + huge
     mov [p2_table + ecx * 8], eax ; map ecx-th entry
+    ; low identity map is removed by the kernel, so the higher half uses its own P2 table
+    mov [p2_high_table + ecx * 8], eax
 
     inc ecx            ; increase counter
     cmp ecx, 512       ; if counter == 512, the whole P2 table is mapped
     jne .map_p2_table  ; else map the next entry
+
+    ; with 5-level paging the kernel half gets its own P4 table,
+    ; otherwise the kernel P3 would also show up at the top of the low half
+    call check_la57
+    jz .done
+    mov eax, p4_table
+    or eax, 0b11
+    mov [p5_table], eax
+
+    mov dword [p4_table + KERNEL_P4_INDEX * 8], 0
+    mov eax, p3_high_table
+    or eax, 0b11
+    mov [p4_high_table + KERNEL_P4_INDEX * 8], eax
+    mov eax, p4_high_table
+    or eax, 0b11
+    mov [p5_table + KERNEL_P5_INDEX * 8], eax
+
+    ; the recursive entry has to live in the top level table
+    mov dword [p4_table + RECURSIVE_INDEX * 8], 0
+    mov eax, p5_table
+    or eax, 0b11
+    mov [p5_table + RECURSIVE_INDEX * 8], eax
+.done:
     ret
 
 enable_paging:
-    ; load P4 to cr3 register (cpu uses this to access the P4 table)
+    ; load the top level table to cr3 register, LA57 can only be changed while paging is disabled
+    call check_la57
+    jz .four_level
+    mov eax, cr4
+    or eax, 1 << 12
+    mov cr4, eax
+    mov eax, p5_table
+    jmp .load_cr3
+.four_level:
     mov eax, p4_table
+.load_cr3:
     mov cr3, eax
 
     ; enable PAE-flag in cr4 (Physical Address Extension)
@@ -175,6 +237,21 @@ check_cpuid:
     mov al,CPUID_CHECK_FAILED
     jmp error
 
+; sets ZF when the cpu does not support 5-level paging, CPUID.(EAX=07H,ECX=0):ECX[16]
+check_la57:
+    mov eax, 0
+    cpuid
+    cmp eax, 7
+    jb .unsupported
+    mov eax, 7
+    xor ecx, ecx
+    cpuid
+    test ecx, 1 << 16
+    ret
+.unsupported:
+    xor eax, eax
+    ret
+
 check_long_mode:
     ; test if extended processor info in available
     mov eax, 0x80000000    ; implicit argument for cpuid
@@ -7,10 +7,19 @@
 
 
 
+KERNEL_OFFSET       equ     0xFFFFFFFF80000000
+STACK_SIZE          equ     0x1000 * 4
+
 global long_mode_entry
 extern kmain
 
-section .text
+section .bss
+align 0x1000
+kernel_stack_end:
+    resb STACK_SIZE
+kernel_stack_start:
+
+section .boot.text progbits alloc exec nowrite
 bits 64
 long_mode_entry:
 	mov ax, 0
@@ -19,4 +28,12 @@ long_mode_entry:
     mov es, ax
     mov fs, ax
     mov gs, ax
-	call kmain
+    ; jump to the higher half, multiboot information is accessed through it too
+    mov edi, edi
+    mov rax, KERNEL_OFFSET
+    add rdi, rax
+    mov rsp, kernel_stack_start
+    ; terminate the frame pointer chain for stack walkers
+    xor rbp, rbp
+    mov rax, kmain
+	call rax
@@ -1,4 +1,6 @@
-use system::ia_32e::descriptor::{Descriptor, GlobalDescriptorTable, SegmentSelector, TaskStateSegment};
+use core::ops::Range;
+
+use system::ia_32e::descriptor::{Descriptor, GlobalDescriptorTable, SegmentSelector, TssWithIoBitmap};
 use system::ia_32e::VirtAddr;
 
 use lazy_static::lazy_static;
@@ -14,16 +16,14 @@ pub struct Selectors {
     pub tss_selector: SegmentSelector,
 }
 
-lazy_static! {
-   pub static ref TSS: TaskStateSegment = load_tss();
-}
+/// TSS之后紧跟I/O许可位图，进程切换时需要修改位图，所以这里不能使用`lazy_static`
+pub static mut TSS: TssWithIoBitmap = TssWithIoBitmap::new();
 
 lazy_static! {
    pub static ref GDT:(GlobalDescriptorTable,Selectors) = load_gdt();
 }
 
-fn load_tss() -> TaskStateSegment {
-    let mut tss = TaskStateSegment::new();
+fn load_tss(tss: &mut TssWithIoBitmap) {
     let double_fault_stack = {
         // 这里STACK_SIZE需要显示写出是usize的，因为我们的VirtAddr只完成了对usize的加法操作
         const STACK_SIZE: usize = 4096;
@@ -35,8 +35,7 @@ fn load_tss() -> TaskStateSegment {
         let stack_end = stack_start + STACK_SIZE;
         stack_end
     };
-    tss.interrupt_stack_table[DOUBLE_FAULT_LIST_INDEX] = double_fault_stack;
-    tss
+    tss.tss.interrupt_stack_table[DOUBLE_FAULT_LIST_INDEX] = double_fault_stack;
 }
 
 fn load_gdt() -> (GlobalDescriptorTable, Selectors) {
@@ -51,7 +50,7 @@ fn load_gdt() -> (GlobalDescriptorTable, Selectors) {
     // user data
     let user_data_selector = gdt.add_descriptor(Descriptor::user_data_segment());
     // tss
-    let tss_selector = gdt.add_descriptor(Descriptor::tss_segment(&TSS));
+    let tss_selector = gdt.add_descriptor(Descriptor::tss_segment_with_io_bitmap(unsafe { &TSS }));
     (gdt, Selectors {
         kernel_data_selector,
         kernel_code_selector,
@@ -62,10 +61,22 @@ fn load_gdt() -> (GlobalDescriptorTable, Selectors) {
 }
 
 pub fn init_tss() {
-    use system::ia_32e::instructions::tables::load_tss;
     let selector: &Selectors = &GDT.1;
     unsafe {
-        load_tss(selector.tss_selector);
+        load_tss(&mut TSS);
+        system::ia_32e::instructions::tables::load_tss(selector.tss_selector);
+    }
+}
+
+/// 切换进程时更新I/O许可位图，收回`prev`的端口并授予`next`的端口
+pub fn switch_io_ports(prev: &[Range<u32>], next: &[Range<u32>]) {
+    unsafe {
+        for range in prev {
+            TSS.set_io_ports(range.start as u16, range.end - range.start, false);
+        }
+        for range in next {
+            TSS.set_io_ports(range.start as u16, range.end - range.start, true);
+        }
     }
 }
 
@@ -4,27 +4,46 @@ use system::ia_32e::ApicInfo;
 #[cfg(feature = "pic")]
 use system::ia_32e::controller::PIC;
 use system::ia_32e::controller::ProgrammableController;
-#[cfg(feature = "xapic")]
-use system::ia_32e::controller::X2APIC;
 #[cfg(feature = "x2apic")]
+use system::ia_32e::controller::X2APIC;
+#[cfg(feature = "xapic")]
 use system::ia_32e::controller::XPAIC;
 use system::ia_32e::cpu::ChainedPics;
 use system::ia_32e::descriptor::InterruptDescriptorTable;
 #[cfg(feature = "x2apic")]
 use system::ia_32e::x2apic::local_apic::LocalApic;
 #[cfg(feature = "xapic")]
-use system::ia_32e::xapic::consts::LAPIC_ADDR;
+use system::bits::IrqFlags;
+#[cfg(feature = "xapic")]
+use system::ia_32e::PhysAddr;
+#[cfg(feature = "xapic")]
+use system::ia_32e::x2apic::io_apic::{IoApic, IrqMode};
+#[cfg(feature = "xapic")]
+use system::ia_32e::xapic::consts::{IOAPIC_ADDR, LAPIC_ADDR};
 #[cfg(feature = "xapic")]
 use system::ia_32e::xapic::xApic;
 
 use lazy_static::lazy_static;
 
-use crate::interrupt::{exceptions, irq};
+use crate::interrupt::{exceptions, ipi, irq};
+#[cfg(feature = "xapic")]
+use crate::devices::acpi::acpi;
+use crate::devices::hpet::HPET_VECTOR;
+#[cfg(feature = "xapic")]
+use crate::memory::map_device;
 use crate::println;
 
 pub const PIC_MAIN: u8 = 32;
 pub const PIC_SLAVE: u8 = PIC_MAIN + 8;
-pub const TICKS: AtomicUsize = AtomicUsize::new(0);
+/// x2APIC的错误中断和伪中断向量，与xAPIC固定使用的向量相同
+#[cfg(feature = "x2apic")]
+pub const APIC_ERROR_VECTOR: usize = PIC_MAIN as usize + 19;
+#[cfg(feature = "x2apic")]
+pub const APIC_SPURIOUS_VECTOR: usize = PIC_MAIN as usize + 31;
+/// 当前进程的时间片已经经过的时钟中断次数，切换进程时清零
+pub static TICKS: AtomicUsize = AtomicUsize::new(0);
+/// 系统启动后经过的时钟中断次数，不会被调度器清零
+pub static UPTIME_TICKS: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
@@ -124,12 +143,40 @@ pub fn init_apic(info: ApicInfo) {
 
 #[cfg(feature = "xapic")]
 pub fn init_apic(info: ApicInfo) {
+    // 8259A上电后的向量与异常重叠，重新设置向量后全部屏蔽，中断改由I/O APIC转发
+    let mut pic = unsafe { ChainedPics::new(PIC_MAIN, PIC_SLAVE) };
+    unsafe {
+        pic.initialize();
+        pic.disable_8259a();
+    }
+    // 优先使用MADT中的地址，没有ACPI表时使用默认地址
+    let madt = acpi().and_then(|acpi| acpi.madt.as_ref());
+    let lapic_addr = madt.map_or(LAPIC_ADDR as u64, |madt| madt.local_apic_address);
+    let io_apic_entry = madt.and_then(|madt| madt.io_apics.first());
+    let io_apic_addr = io_apic_entry.map_or(IOAPIC_ADDR as u64, |entry| entry.address);
+    let lapic = map_device(PhysAddr::new(lapic_addr), 0x1000);
+    let io_apic = map_device(PhysAddr::new(io_apic_addr), 0x1000);
+
     let mut lock = CONTROLLER.lock();
-    lock.set_xapic(xApic::new(LAPIC_ADDR));
+    lock.set_xapic(xApic::new(lapic.as_u64() as usize));
+    lock.set_io_apic(unsafe { IoApic::new(io_apic.as_u64()) });
     unsafe {
-        lock.init(info)
+        lock.init(info);
+        // 时钟中断由local APIC的定时器产生，I/O APIC只转发键盘中断。ISA中断可能被MADT重定向到其他引脚
+        let irq = InterruptIndex::KeyBoard as u8 - PIC_MAIN;
+        let (gsi, flags) = madt.map_or((u32::from(irq), IrqFlags::empty()), |madt| madt.isa_irq(irq));
+        // 只使用第一个I/O APIC，重定向到其他I/O APIC的中断不开启
+        let max_entry = u32::from(lock.io_apic_max_table_entry());
+        match gsi.checked_sub(io_apic_entry.map_or(0, |entry| entry.gsi_base)).filter(|&pin| pin <= max_entry) {
+            Some(pin) => {
+                let id = lock.id();
+                lock.io_apic_set_vector(pin as u8, InterruptIndex::KeyBoard as u8);
+                lock.enable_irq(pin as u8, id, IrqMode::Fixed, flags);
+            }
+            None => println!("xapic: keyboard gsi {} is not on the first io apic", gsi),
+        }
     }
-    println!("xapic init done!");
+    println!("xapic init done! {}", unsafe { lock.version() });
 }
 
 #[cfg(feature = "x2apic")]
@@ -166,10 +213,11 @@ pub fn init() -> InterruptDescriptorTable {
     // irq
     idt[InterruptIndex::Timer.into()].set_handler_fn(irq::timer);
     idt[InterruptIndex::KeyBoard.into()].set_handler_fn(irq::keyboard);
+    idt[HPET_VECTOR as usize].set_handler_fn(irq::hpet_timer);
     // no support yet
     // idt[ipi::IpiKind::WakeUp.into()].set_handler_fn(ipi::ipi_wakeup);
     // idt[ipi::IpiKind::Switch.into()].set_handler_fn(ipi::ipi_switch);
-    // idt[ipi::IpiKind::Tlb.into()].set_handler_fn(ipi::ipi_tlb);
+    idt[ipi::IpiKind::Tlb.into()].set_handler_fn(ipi::ipi_tlb);
     // idt[ipi::IpiKind::Pit.into()].set_handler_fn(ipi::ipi_pit);
     // idt[SystemCall::Base].set_handler_fn();
     // idt[SystemCall::Base].set_flags(IdtFlags::PRESENT | IdtFlags::RING_3 | IdtFlags::INTERRUPT);
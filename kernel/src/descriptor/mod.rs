@@ -1,5 +1,7 @@
-pub use gdt::{GDT, init_gdt, init_tss, Selectors, TSS};
-pub use idt::{CONTROLLER, disable_8259a, init_apic, init_idt, InterruptIndex, PIC_MAIN, PIC_SLAVE, TICKS};
+pub use gdt::{GDT, init_gdt, init_tss, Selectors, switch_io_ports, TSS};
+#[cfg(feature = "x2apic")]
+pub use idt::{APIC_ERROR_VECTOR, APIC_SPURIOUS_VECTOR};
+pub use idt::{CONTROLLER, disable_8259a, init_apic, init_idt, InterruptIndex, PIC_MAIN, PIC_SLAVE, TICKS, UPTIME_TICKS};
 
 mod gdt;
 mod idt;
@@ -0,0 +1,37 @@
+use spin::Once;
+use system::ia_32e::acpi::{Acpi, AcpiError, Rsdp};
+use system::ia_32e::{PhysAddr, VirtAddr};
+use system::SystemInformation;
+
+use crate::memory::map_firmware;
+
+static ACPI: Once<Acpi> = Once::new();
+
+/// 查找并解析ACPI表，需要在建立线性映射之后调用，线性映射没有覆盖的表会被补充映射。UEFI引导时使用配置表中的RSDP，否则在BIOS内存中查找
+pub fn init(info: &SystemInformation) -> Result<(), AcpiError> {
+    let rsdp = info.acpi_rsdp()
+        .or_else(|| unsafe { Rsdp::search(map_table) })
+        .ok_or(AcpiError::RsdpNotFound)?;
+    let acpi = unsafe { Acpi::parse(rsdp, map_table)? };
+    let signatures = acpi.tables.iter()
+        .map(|(signature, _)| core::str::from_utf8(signature).unwrap_or("????"));
+    print!("acpi: revision {}, tables:", acpi.revision);
+    for signature in signatures {
+        print!(" {}", signature);
+    }
+    println!();
+    if let Some(madt) = &acpi.madt {
+        println!("acpi: {} processors, {} io apics, local apic at {:#x}", madt.processors.len(), madt.io_apics.len(), madt.local_apic_address);
+    }
+    ACPI.call_once(|| acpi);
+    Ok(())
+}
+
+fn map_table(phys: PhysAddr, len: usize) -> VirtAddr {
+    map_firmware(phys, len as u64)
+}
+
+/// 解析得到的ACPI信息，没有找到ACPI表或者还没有初始化时返回`None`
+pub fn acpi() -> Option<&'static Acpi> {
+    ACPI.r#try()
+}
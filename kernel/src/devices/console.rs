@@ -0,0 +1,87 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::layouts::Us104Key;
+use spin::Mutex;
+use system::syscall::data::Stat;
+use system::syscall::flag::MODE_CHR;
+use system::syscall::result::{EAGAIN, Error, Result};
+
+use crate::devices::keyboard::scan_queue_mut;
+use crate::process::file::{FileDescription, FileObject, OpenFile};
+
+/// 控制台，写入的数据输出到VGA，读取时从键盘扫描码队列中解码字符
+pub struct Console {
+    input: Mutex<ConsoleInput>,
+}
+
+struct ConsoleInput {
+    keyboard: Keyboard<Us104Key, ScancodeSet1>,
+    /// 已经解码但上次读取时放不进缓冲区的字节
+    pending: VecDeque<u8>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            input: Mutex::new(ConsoleInput {
+                keyboard: Keyboard::new(Us104Key, ScancodeSet1, HandleControl::Ignore),
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+impl FileObject for Console {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut input = self.input.lock();
+        let input = &mut *input;
+        let queue = scan_queue_mut();
+        let mut count = 0;
+        while count < buf.len() {
+            // 先取出上次剩下的字节，缓冲区写满时多字节字符的其余部分留到下次读取
+            if let Some(byte) = input.pending.pop_front() {
+                buf[count] = byte;
+                count += 1;
+                continue;
+            }
+            let code = match queue.pop() {
+                Ok(code) => code,
+                Err(_) => break,
+            };
+            if let Ok(Some(event)) = input.keyboard.add_byte(code) {
+                if let Some(DecodedKey::Unicode(character)) = input.keyboard.process_keyevent(event) {
+                    let mut encoded = [0_u8; 4];
+                    input.pending.extend(character.encode_utf8(&mut encoded).as_bytes());
+                }
+            }
+        }
+        if count == 0 && !buf.is_empty() {
+            return Err(Error::new(EAGAIN));
+        }
+        Ok(count)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        match core::str::from_utf8(buf) {
+            Ok(s) => print!("{}", s),
+            Err(_) => {
+                for &byte in buf {
+                    print!("{}", byte as char);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn fstat(&self, stat: &mut Stat) -> Result<()> {
+        stat.st_mode = MODE_CHR | 0o620;
+        Ok(())
+    }
+}
+
+/// 创建一个指向控制台的文件描述符，用于标准输入输出
+pub fn console_file(flags: usize) -> OpenFile {
+    OpenFile::new(FileDescription::new(Arc::new(Console::new()), flags), false)
+}
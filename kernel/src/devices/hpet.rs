@@ -0,0 +1,112 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::{Mutex, Once};
+use system::ia_32e::cpu::hpet::Hpet;
+#[cfg(feature = "xapic")]
+use system::bits::IrqFlags;
+#[cfg(feature = "xapic")]
+use system::ia_32e::cpu::hpet::HpetError;
+use system::ia_32e::PhysAddr;
+#[cfg(feature = "xapic")]
+use system::ia_32e::x2apic::io_apic::IrqMode;
+
+#[cfg(feature = "xapic")]
+use crate::descriptor::CONTROLLER;
+use crate::devices::acpi::acpi;
+use crate::memory::map_device;
+
+/// 比较器中断使用的向量，位于8259A的16个向量之后
+pub const HPET_VECTOR: u8 = 0x30;
+
+static HPET: Once<Mutex<Hpet>> = Once::new();
+/// 已经收到的比较器中断次数
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 根据ACPI中的HPET表映射寄存器并启动主计数器，没有HPET或者主计数器只有32位时什么也不做
+pub fn init() {
+    let base = match acpi().and_then(|acpi| acpi.hpet.as_ref()) {
+        Some(info) => info.base_address,
+        None => {
+            println!("hpet not found");
+            return;
+        }
+    };
+    let virt = map_device(PhysAddr::new(base), 0x400);
+    let mut hpet = unsafe { Hpet::new(virt.as_usize()) };
+    // 32位的主计数器在14.318MHz下约5分钟回绕一次，`nanos`无法保证及时发现回绕，不使用这样的HPET
+    if !unsafe { hpet.counter_64bit() } {
+        println!("hpet: 32-bit main counter is not supported");
+        return;
+    }
+    unsafe { hpet.enable() };
+    println!("hpet: {} timers, {} Hz", hpet.timers(), hpet.frequency());
+    HPET.call_once(|| Mutex::new(hpet));
+}
+
+/// 没有HPET或者还没有初始化时返回`None`
+pub fn hpet() -> Option<&'static Mutex<Hpet>> {
+    HPET.r#try()
+}
+
+/// 主计数器启动以来经过的纳秒数
+pub fn nanos() -> Option<u64> {
+    hpet().map(|hpet| {
+        let hpet = hpet.lock();
+        hpet.ticks_to_nanos(unsafe { hpet.counter() })
+    })
+}
+
+/// 比较器`timer`在`nanos`纳秒后（`periodic`为true时每隔`nanos`纳秒）产生向量为`HPET_VECTOR`的中断，
+/// 中断经由I/O APIC发送到当前处理器，优先使用不与ISA中断共享的16号及以上的引脚
+#[cfg(feature = "xapic")]
+pub fn start_timer(timer: u8, nanos: u64, periodic: bool) -> Result<(), HpetError> {
+    let mut hpet = hpet().expect("hpet not init").lock();
+    let routes = unsafe { hpet.routes(timer)? };
+    let pin = {
+        let mut controller = CONTROLLER.lock();
+        unsafe {
+            // 比较器可以连接的引脚不一定都存在于I/O APIC的重定向表中
+            let max_entry = controller.io_apic_max_table_entry();
+            let pin = (16..24).chain(0..16)
+                .find(|&pin| pin <= max_entry && routes & (1 << pin) != 0)
+                .ok_or(HpetError::RouteNotSupported(timer))?;
+            let id = controller.id();
+            controller.io_apic_set_vector(pin, HPET_VECTOR);
+            controller.enable_irq(pin, id, IrqMode::Fixed, IrqFlags::empty());
+            pin
+        }
+    };
+    let ticks = hpet.nanos_to_ticks(nanos);
+    unsafe {
+        if periodic {
+            hpet.set_periodic(timer, pin, ticks)
+        } else {
+            hpet.set_one_shot(timer, pin, ticks)
+        }
+    }
+}
+
+/// 比较器中断的处理函数调用，中断是边沿触发的，不需要清除中断状态
+pub fn handle_interrupt() {
+    EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 启动一次1毫秒后的单次中断并等待其到达，检查比较器中断的路由是否正确，需要在开启中断之后调用
+#[cfg(feature = "xapic")]
+pub fn check_timer() {
+    if hpet().is_none() {
+        return;
+    }
+    let events = EVENTS.load(Ordering::Relaxed);
+    if let Err(e) = start_timer(0, 1_000_000, false) {
+        println!("hpet: start timer failed: {:?}", e);
+        return;
+    }
+    let begin = nanos().unwrap_or(0);
+    while EVENTS.load(Ordering::Relaxed) == events && nanos().unwrap_or(u64::max_value()) - begin < 10_000_000 {}
+    if EVENTS.load(Ordering::Relaxed) == events {
+        println!("hpet: timer interrupt not received");
+    } else {
+        println!("hpet: timer interrupt... done");
+    }
+}
@@ -1,4 +1,8 @@
+pub mod acpi;
+pub mod console;
+pub mod hpet;
 pub mod keyboard;
+pub mod timer;
 pub mod vga;
 
 pub fn device_init() {
@@ -0,0 +1,160 @@
+//! local APIC定时器。启动时以HPET（没有HPET时使用8253）为基准校准定时器的频率，
+//! 之后以单次或者TSC deadline模式只在下一次调度时产生中断，空闲时不再每秒唤醒100次。
+//! 校准失败或者不使用xAPIC时保留周期模式
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use system::ia_32e::cpu::msr::IA32_TSC_DEADLINE;
+use system::ia_32e::cpu::timer::IRQ_FREQUENCY;
+use system::ia_32e::instructions::interrupt::without_interrupts;
+use system::ia_32e::instructions::register::{rdtsc, wrmsr};
+#[cfg(feature = "xapic")]
+use system::ia_32e::x2apic::register::{TimerDivide, TimerMode};
+
+use crate::descriptor::{CONTROLLER, TICKS};
+use crate::process::scheduler::has_other_runnable;
+#[cfg(feature = "xapic")]
+use crate::time::calibration_wait;
+use crate::time::tsc_frequency;
+#[cfg(feature = "xapic")]
+use crate::utils::SystemFunctionalCheck;
+
+/// 一个时钟节拍的纳秒数，与周期模式下两次中断的间隔相同
+pub const TICK_NANOS: u64 = 1_000_000_000 / IRQ_FREQUENCY as u64;
+/// 调度器在经过该数量的节拍后切换进程
+pub const QUANTUM_TICKS: usize = 10;
+/// 没有其他可运行的进程时两次中断的最大间隔
+const IDLE_NANOS: u64 = 1_000_000_000;
+/// 校准时等待的微秒数
+const CALIBRATE_MICROS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TimerKind {
+    /// 固定频率的时钟中断
+    Periodic,
+    /// 每次中断后重新写入初始计数
+    OneShot,
+    /// TSC达到`IA32_TSC_DEADLINE`时产生中断
+    TscDeadline,
+}
+
+static KIND: AtomicU8 = AtomicU8::new(TimerKind::Periodic as u8);
+/// 分频系数为2时local APIC定时器每秒的计数
+static APIC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// 已经计入节拍的时间对应的TSC
+static LAST_TSC: AtomicU64 = AtomicU64::new(0);
+/// 下一次中断按空闲间隔设置
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+pub fn kind() -> TimerKind {
+    match KIND.load(Ordering::Relaxed) {
+        1 => TimerKind::OneShot,
+        2 => TimerKind::TscDeadline,
+        _ => TimerKind::Periodic,
+    }
+}
+
+/// 在初始化APIC和校准TSC之后、开启中断之前调用，校准频率后切换到单次模式，支持时使用TSC deadline模式
+#[cfg(feature = "xapic")]
+pub fn init() {
+    let apic = calibrate();
+    // 两次中断之间经过的节拍数通过TSC计算
+    if apic == 0 || tsc_frequency() == 0 {
+        println!("apic timer: calibration failed, keep periodic mode");
+        return;
+    }
+    APIC_FREQUENCY.store(apic, Ordering::Relaxed);
+    let (kind, mode) = if SystemFunctionalCheck::get_check_result().support_tsc_deadline() {
+        (TimerKind::TscDeadline, TimerMode::TscDeadline)
+    } else {
+        (TimerKind::OneShot, TimerMode::OneShot)
+    };
+    {
+        let mut controller = CONTROLLER.lock();
+        unsafe {
+            controller.set_timer_mode(mode);
+            controller.enable_timer();
+        }
+    }
+    KIND.store(kind as u8, Ordering::SeqCst);
+    LAST_TSC.store(rdtsc(), Ordering::SeqCst);
+    set_next_event(TICK_NANOS * QUANTUM_TICKS as u64);
+    println!("apic timer: {:?} mode, {} Hz", kind, apic);
+}
+
+/// 只有xAPIC支持读取定时器的当前计数，其他情况保留周期模式
+#[cfg(not(feature = "xapic"))]
+pub fn init() {
+    println!("apic timer: periodic mode");
+}
+
+/// 以单次模式从最大值开始倒数，返回等待`CALIBRATE_MICROS`后换算得到的定时器每秒的计数
+#[cfg(feature = "xapic")]
+fn calibrate() -> u64 {
+    let mut controller = CONTROLLER.lock();
+    unsafe {
+        controller.disable_timer();
+        controller.set_timer_mode(TimerMode::OneShot);
+        controller.set_timer_divide(TimerDivide::Div2);
+        controller.set_timer_initial(u32::max_value());
+    }
+    calibration_wait(CALIBRATE_MICROS);
+    let current = unsafe { controller.timer_current() };
+    unsafe { controller.set_timer_initial(0) };
+    u64::from(u32::max_value() - current) * (1_000_000 / CALIBRATE_MICROS)
+}
+
+/// 在`nanos`纳秒后产生下一次时钟中断，周期模式下什么也不做
+pub fn set_next_event(nanos: u64) {
+    match kind() {
+        TimerKind::Periodic => {}
+        TimerKind::OneShot => {
+            let count = scale(nanos, APIC_FREQUENCY.load(Ordering::Relaxed)).min(u64::from(u32::max_value())).max(1);
+            unsafe { CONTROLLER.lock().set_timer_initial(count as u32) }
+        }
+        TimerKind::TscDeadline => unsafe {
+            wrmsr(IA32_TSC_DEADLINE, rdtsc() + scale(nanos, tsc_frequency()).max(1))
+        },
+    }
+}
+
+/// 在时钟中断中调用，返回上一次调用以来经过的节拍数，周期模式下总是1
+pub fn elapsed_ticks() -> usize {
+    if kind() == TimerKind::Periodic {
+        return 1;
+    }
+    let per_tick = scale(TICK_NANOS, tsc_frequency());
+    let last = LAST_TSC.load(Ordering::Relaxed);
+    let ticks = rdtsc().saturating_sub(last) / per_tick;
+    LAST_TSC.store(last + ticks * per_tick, Ordering::Relaxed);
+    ticks as usize
+}
+
+/// 在时钟中断中调用，有其他可运行的进程时在当前时间片结束时产生中断，否则空闲一段时间
+pub fn schedule_next(ticks: usize) {
+    if kind() == TimerKind::Periodic {
+        return;
+    }
+    if has_other_runnable() {
+        IDLE.store(false, Ordering::SeqCst);
+        set_next_event(TICK_NANOS * QUANTUM_TICKS.saturating_sub(ticks).max(1) as u64);
+    } else {
+        IDLE.store(true, Ordering::SeqCst);
+        set_next_event(IDLE_NANOS);
+    }
+}
+
+/// 进程被唤醒后调用。空闲时下一次中断可能在很久之后，结束当前进程的时间片并在下一个节拍产生中断
+pub fn wake() {
+    if kind() == TimerKind::Periodic || !IDLE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    TICKS.store(QUANTUM_TICKS, Ordering::SeqCst);
+    // 中断处理程序也会获取`CONTROLLER`
+    without_interrupts(|| set_next_event(TICK_NANOS));
+}
+
+/// 频率为`frequency`的计数器在`nanos`纳秒内的计数
+fn scale(nanos: u64, frequency: u64) -> u64 {
+    (u128::from(nanos) * u128::from(frequency) / 1_000_000_000) as u64
+}
@@ -1,5 +1,7 @@
 use core::fmt;
 
+#[cfg(feature = "mutiboot")]
+use system::KERNEL_OFFSET;
 use system::Mutex;
 use volatile::Volatile;
 
@@ -7,6 +9,11 @@ use lazy_static::lazy_static;
 
 pub const BUFFER_HEIGHT: usize = 25;
 pub const BUFFER_WIDTH: usize = 80;
+/// VGA文本缓冲区，启动后低地址的恒等映射会被解除，因此通过高半部分的映射访问
+#[cfg(feature = "mutiboot")]
+const VGA_BUFFER: u64 = KERNEL_OFFSET + 0xb8000;
+#[cfg(not(feature = "mutiboot"))]
+const VGA_BUFFER: u64 = 0xb8000;
 
 
 // static 懒加载无需在编译时计算其值，而是在首次访问时进行初始化
@@ -18,7 +25,7 @@ lazy_static! {
         row_position: 0,
         column_position: 0,
         color_code: ColorCode::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        buffer: unsafe { &mut *(VGA_BUFFER as *mut Buffer) },
     });
 }
 
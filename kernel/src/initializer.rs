@@ -1,14 +1,19 @@
 use system::ia_32e::ApicInfo;
 use system::ia_32e::instructions::interrupt::{disable_interrupt, enable_interrupt};
-use system::ia_32e::paging::frame_allocator::MemoryAreaManagement;
+use system::ia_32e::paging::{FrameAllocator, paging_levels};
 use system::SystemInformation;
 
+#[cfg(not(feature = "pic"))]
+use crate::descriptor::PIC_MAIN;
+#[cfg(feature = "x2apic")]
+use crate::descriptor::{APIC_ERROR_VECTOR, APIC_SPURIOUS_VECTOR, InterruptIndex};
 use crate::descriptor::{init_gdt, init_idt, init_tss};
-use crate::devices::device_init;
+use crate::devices::{acpi, device_init, hpet, timer};
 use crate::interrupt::syscall;
-use crate::memory::{add_to_heap, FRAME_ALLOCATOR, init_frame_allocator, RECU_PAGE_TABLE};
+use crate::memory::{FRAME_ALLOCATOR, init_device_memory, init_direct_map, init_frame_allocator, init_heap, init_pat, init_pcid, init_tlb_shootdown, PAGE_TABLE, remap_kernel};
 use crate::process::{init_process, process_mut};
-use crate::utils::initialize_apic;
+use crate::time;
+use crate::utils::{init_kaslr, initialize_apic};
 
 pub struct Initializer(SystemInformation);
 
@@ -17,6 +22,8 @@ impl Initializer {
         Self(info)
     }
     pub fn initialize(&self) {
+        println!("{}-level paging", paging_levels());
+        init_kaslr(&self.0);
         // init
         init_idt();
         println!("set up idt... done");
@@ -25,40 +32,64 @@ impl Initializer {
         println!("set up gdt... done");
         init_tss();
         println!("set up tss... done");
+        init_pat();
         // init heap
-        // todo:
-        unsafe {
-            add_to_heap(1048576, 134086656)
-        }
+        init_heap();
         println!("set up buddy system allocator... done");
+        init_tlb_shootdown();
+        println!("set up tlb shootdown... done");
+        init_pcid();
         device_init();
         println!("devices init... done");
         // init apic
-        disable_interrupt();
         #[cfg(feature = "pic")]
-            initialize_apic(ApicInfo::default()).expect("init apic failed");
-        #[cfg(feature = "xapic")]
-            initialize_apic(ApicInfo::new().set_io_apic_offset(8).build().unwrap()).expect("init apic failed");
-        //todo: x2apic build
-        #[cfg(feature = "x2apic")]
-            initialize_apic(ApicInfo::new()
-            .build().unwrap()
-        ).expect("init apic failed");
-        println!("enable apic or pic... done");
-        enable_interrupt();
-        println!("enable interrupt... done");
+            {
+                disable_interrupt();
+                initialize_apic(ApicInfo::default()).expect("init apic failed");
+                println!("enable apic or pic... done");
+                timer::init();
+                enable_interrupt();
+                println!("enable interrupt... done");
+            }
         {
-            RECU_PAGE_TABLE.lock();
+            PAGE_TABLE.lock();
         }
-        init_frame_allocator(self.0.kernel_start(), self.0.kernel_end());
+        init_frame_allocator(&self.0);
         {
-            let mut allocator = FRAME_ALLOCATOR.lock();
-            println!("add memory area");
-            for area in self.0.mem_area_iter() {
-                let adder = allocator.as_mut().unwrap();
-                adder.add_area(area.start_addr, area.end_addr, area.ty, area.length);
-            }
+            let allocator = FRAME_ALLOCATOR.lock();
+            let allocator = allocator.as_ref().unwrap();
+            println!("physical frames: {} free, {} used", allocator.free_frames(), allocator.used_frames());
+        }
+        remap_kernel(&self.0);
+        init_direct_map(&self.0);
+        init_device_memory(&self.0);
+        match acpi::init(&self.0) {
+            Ok(()) => println!("parse acpi tables... done"),
+            Err(e) => println!("parse acpi tables failed: {:?}", e),
         }
+        hpet::init();
+        time::init();
+        // APIC的寄存器通过线性映射访问，需要在建立设备内存映射之后初始化
+        #[cfg(not(feature = "pic"))]
+            {
+                disable_interrupt();
+                #[cfg(feature = "xapic")]
+                    initialize_apic(ApicInfo::new().set_io_apic_offset(PIC_MAIN).build().unwrap()).expect("init apic failed");
+                #[cfg(feature = "x2apic")]
+                    initialize_apic(ApicInfo::new()
+                    .set_io_apic_offset(PIC_MAIN)
+                    .set_timer_vector(InterruptIndex::Timer as usize)
+                    .set_error_vector(APIC_ERROR_VECTOR)
+                    .set_spurious_vector(APIC_SPURIOUS_VECTOR)
+                    .build().unwrap()
+                ).expect("init apic failed");
+                println!("enable apic or pic... done");
+                timer::init();
+                enable_interrupt();
+                println!("enable interrupt... done");
+            }
+        #[cfg(feature = "xapic")]
+            hpet::check_timer();
         println!("init syscall feature");
         unsafe {
             syscall::init()
@@ -1,7 +1,9 @@
-use system::interrupt_frame;
+use system::{interrupt_error, interrupt_frame};
+use system::bits::flags::PageFaultErrorCode;
+use system::ia_32e::cpu::control::CR2;
 
 use crate::println;
-use crate::utils::loop_hlt;
+use crate::process::fault::{kill_current, resolve_fault};
 
 ////////////////////// Exceptions /////////////////////////////
 interrupt_frame!(divide_by_zero,stack,{
@@ -24,9 +26,17 @@ interrupt_frame!(invalid_opcode, stack, {
     println!("Invalid opcode fault: {:?}",stack.dump());
 });
 
-interrupt_frame!(page_fault, stack, {
-    println!("Page_fault: {:?}",stack.dump());
-    loop_hlt();
+interrupt_error!(page_fault, stack, {
+    let addr = CR2::read();
+    let code = PageFaultErrorCode::from_bits_truncate(stack.code as u64);
+    if resolve_fault(addr, code) {
+        return;
+    }
+    if code.contains(PageFaultErrorCode::USER_MODE) {
+        println!("segmentation fault: address={:?} code={:?} rip={:?}", addr, code, stack.iret.rip());
+        kill_current();
+    }
+    panic!("kernel page fault: address={:?} code={:?} rip={:?} {}", addr, code, stack.iret.rip(), stack.dump());
 });
 
 interrupt_frame!(double_fault, stack, {
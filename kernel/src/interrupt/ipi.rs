@@ -1,6 +1,7 @@
-use system::ia_32e::instructions::page_table::flush_all;
 use system::interrupt;
 
+use crate::descriptor::CONTROLLER;
+use crate::memory::handle_tlb_requests;
 use crate::process::scheduler::switch;
 
 #[derive(Clone, Copy, Debug)]
@@ -35,5 +36,6 @@ interrupt!(ipi_pit,{
 });
 
 interrupt!(ipi_tlb,{
-    flush_all();
-});
\ No newline at end of file
+    handle_tlb_requests();
+    CONTROLLER.lock().eoi(Some(IpiKind::Tlb as u8));
+});
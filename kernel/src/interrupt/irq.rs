@@ -1,72 +1,106 @@
 use bitflags::_core::sync::atomic::Ordering;
 use system::interrupt;
 
-use crate::descriptor::{CONTROLLER, InterruptIndex, TICKS};
+use crate::descriptor::{CONTROLLER, InterruptIndex, TICKS, UPTIME_TICKS};
+use crate::devices::hpet::{self, HPET_VECTOR};
 use crate::devices::keyboard::add_scan_code;
+use crate::devices::timer::{elapsed_ticks, QUANTUM_TICKS, schedule_next};
 use crate::process::scheduler::switch;
+use crate::scheme::irq::{irq_subscribed, irq_trigger};
 
 interrupt!(timer,{
-    if TICKS.fetch_add(1,Ordering::Relaxed) >= 10{
+    // 单次和TSC deadline模式下两次中断之间可能经过了多个节拍
+    let ticks = elapsed_ticks();
+    UPTIME_TICKS.fetch_add(ticks, Ordering::Relaxed);
+    let slice = TICKS.fetch_add(ticks, Ordering::Relaxed) + ticks;
+    // 切换进程之前设置下一次中断，切换后的进程从完整的时间片开始
+    if slice >= QUANTUM_TICKS {
+        schedule_next(0);
         switch();
+    } else {
+        schedule_next(slice);
     }
+    irq_trigger(InterruptIndex::Timer);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Timer.into()))
 });
 
 interrupt!(keyboard,{
     use system::ia_32e::cpu::Port;
-    let mut port = Port::new(0x60);
-    let scan_code: u8 = port.read();
-    add_scan_code(scan_code);
+    // 用户态驱动订阅后由其自己读取扫描码
+    if !irq_subscribed(InterruptIndex::KeyBoard) {
+        let mut port = Port::new(0x60);
+        let scan_code: u8 = port.read();
+        add_scan_code(scan_code);
+    }
+    irq_trigger(InterruptIndex::KeyBoard);
     CONTROLLER.lock().eoi(Some(InterruptIndex::KeyBoard.into()))
 });
 
+interrupt!(hpet_timer,{
+    hpet::handle_interrupt();
+    CONTROLLER.lock().eoi(Some(HPET_VECTOR))
+});
+
 interrupt!(com2,{
+    irq_trigger(InterruptIndex::Com2);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Com2.into()))
 });
 interrupt!(com1,{
+    irq_trigger(InterruptIndex::Com1);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Com1.into()))
 });
 
 interrupt!(lpt2, {
+    irq_trigger(InterruptIndex::Lpt2);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Lpt2.into()));
 });
 
 interrupt!(floppy, {
+    irq_trigger(InterruptIndex::Floppy);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Floppy.into()));
 });
 
 interrupt!(lpt1, {
+    irq_trigger(InterruptIndex::Lpt1);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Lpt1.into()));
 });
 
 interrupt!(rtc, {
+    irq_trigger(InterruptIndex::Rtc);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Rtc.into()));
 });
 
 interrupt!(pci1, {
+    irq_trigger(InterruptIndex::Pci1);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Pci1.into()));
 });
 
 interrupt!(pci2, {
+    irq_trigger(InterruptIndex::Pci2);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Pci2.into()));
 });
 
 interrupt!(pci3, {
+    irq_trigger(InterruptIndex::Pci3);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Pci3.into()));
 });
 
 interrupt!(mouse, {
+    irq_trigger(InterruptIndex::Mouse);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Mouse.into()));
 });
 
 interrupt!(fpu, {
+    irq_trigger(InterruptIndex::Fpu);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Fpu.into()));
 });
 
 interrupt!(ata1, {
+    irq_trigger(InterruptIndex::Ata1);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Ata1.into()));
 });
 
 interrupt!(ata2, {
+    irq_trigger(InterruptIndex::Ata2);
     CONTROLLER.lock().eoi(Some(InterruptIndex::Ata2.into()));
 });
\ No newline at end of file
@@ -11,5 +11,5 @@ pub unsafe fn init() {
     MSR::write_msr(IA32_FMASK, 0x0300); // Clear trap flag and interrupt enable
     MSR::write_msr(IA32_KERNEL_GS_BASE, &TSS as *const _ as u64);
     MSR::write_msr(IA32_KERNEL_GS_BASE, &TSS as *const _ as u64);
-    Efer::write(EferFlags::SYSTEM_CALL_EXTENSIONS);
+    Efer::write(Efer::read() | EferFlags::SYSTEM_CALL_EXTENSIONS);
 }
\ No newline at end of file
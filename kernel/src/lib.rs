@@ -19,6 +19,7 @@ use core::panic::PanicInfo;
 
 #[cfg(feature = "efi")]
 use system::KernelArgs;
+use system::ia_32e::paging::init_paging_mode;
 use system::SystemInformation;
 #[cfg(feature = "efi")]
 use uefi::table::boot::{AllocateType, MemoryMapIter, MemoryMapKey, MemoryType};
@@ -27,7 +28,7 @@ use crate::async_process::Executor;
 use crate::devices::keyboard::print_scan_code;
 use crate::devices::vga::clear_screen;
 use crate::initializer::Initializer;
-use crate::utils::loop_hlt;
+use crate::utils::{kernel_slide, loop_hlt};
 
 #[macro_use]
 mod macros;
@@ -42,12 +43,16 @@ mod async_process;
 mod devices;
 mod interrupt;
 mod syscall;
+mod scheme;
+mod time;
 mod tests;
 
 
 #[cfg(feature = "efi")]
 #[no_mangle]
 extern "C" fn kmain(info_addr: usize) -> ! {
+    // 虚拟地址的规范形式取决于分页模式，必须在构造任何`VirtAddr`之前记录
+    init_paging_mode();
     println!("uefi entry");
     let info = SystemInformation::new(info_addr);
     Initializer::new(info).initialize();
@@ -58,6 +63,7 @@ extern "C" fn kmain(info_addr: usize) -> ! {
 #[cfg(feature = "mutiboot")]
 #[no_mangle]
 extern "C" fn kmain(info_addr: usize) -> ! {
+    init_paging_mode();
     println!("entry kernel");
     clear_screen();
     let info = SystemInformation::new(info_addr);
@@ -71,6 +77,9 @@ extern "C" fn kmain(info_addr: usize) -> ! {
 #[panic_handler]
 pub fn panic_handler(info: &PanicInfo) -> ! {
     println!("{:?}", info);
+    if kernel_slide() != 0 {
+        println!("kernel slide: {:#x}", kernel_slide());
+    }
     loop_hlt()
 }
 
@@ -11,4 +11,18 @@ macro_rules! println {
     ()=>($crate::print!("\n"));
     ($fmt:expr) => ($crate::print!(concat!($fmt,"\n")));
     ($fmt:expr, $($args:tt)*) => ($crate::print!(concat!($fmt,"\n"),$($args)*));
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    ()=>($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt,"\n")));
+    ($fmt:expr, $($args:tt)*) => ($crate::serial_print!(concat!($fmt,"\n"),$($args)*));
 }
\ No newline at end of file
@@ -1,30 +1,263 @@
 use alloc::boxed::Box;
-use core::alloc::Layout;
+use alloc::string::String;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use bitflags::_core::ptr::slice_from_raw_parts_mut;
 use spin::Mutex;
-use system::buddy_system_allocator::LockedHeap;
-use system::ia_32e::paging::frame_allocator::{AdaptationAllocator, BumpAllocator};
-use system::result::Result;
+use system::bits::PageTableFlags;
+use system::buddy_system_allocator::{Heap, LockedHeapWithRescue};
+#[cfg(feature = "heap_debug")]
+use system::debug_allocator::{CALLER_DEPTH, DebugAllocator};
+use system::ia_32e::{align_up, VirtAddr};
+use system::ia_32e::paging::{FrameAllocator, Page, Page4KB, PageSize};
+use system::ia_32e::paging::frame_allocator::{BuddyFrameAllocator, MemoryAreaManagement};
+use system::ia_32e::paging::mapper::Mapper;
+use system::result::{Error, MemErrorKind, Result};
+use system::slab_allocator::SlabAllocator;
+use system::SystemInformation;
 
 use lazy_static::lazy_static;
 
-use crate::utils::loop_hlt;
+use crate::memory::{KernelPageTable, PAGE_TABLE};
+use crate::utils::{kaslr_random, loop_hlt};
+
+/// 内核堆的虚拟地址区域，按需映射
+pub const KERNEL_HEAP_OFFSET: usize = 0xFFFF_FE00_0000_0000;
+pub const KERNEL_HEAP_SIZE: usize = 0x0000_0080_0000_0000;
+/// 启用地址随机化时，堆的起始地址在区域前一半中以1GiB为单位随机选择
+const HEAP_RANDOM_SLOTS: u64 = 256;
+const HEAP_RANDOM_ALIGN: usize = 0x4000_0000;
+/// 每次扩展堆时至少映射的大小
+const HEAP_GROW_SIZE: usize = 0x10_0000;
+/// 帧分配器初始化之前使用的堆空间，位于内核的.bss段中
+const INITIAL_HEAP_SIZE: usize = 0x20_0000;
+/// 预先映射但还没有加入堆的空间，扩展堆时只从这里取用
+const HEAP_RESERVE_SIZE: usize = 0x40_0000;
+/// 低1MiB内存包含BIOS数据，EBDA以及显存等，不参与分配
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+#[repr(align(4096))]
+struct InitialHeap([u8; INITIAL_HEAP_SIZE]);
+
+static mut INITIAL_HEAP: InitialHeap = InitialHeap([0; INITIAL_HEAP_SIZE]);
+
+pub static HEAP: LockedHeapWithRescue = LockedHeapWithRescue::new(grow_heap);
+/// 堆区域的起始地址，以及已经加入堆的部分的结束地址
+static HEAP_BASE: AtomicUsize = AtomicUsize::new(KERNEL_HEAP_OFFSET);
+static HEAP_TOP: AtomicUsize = AtomicUsize::new(KERNEL_HEAP_OFFSET);
+/// 堆区域中已经映射部分的结束地址，`[HEAP_TOP, MAPPED_TOP)`是预留的空间
+static MAPPED_TOP: AtomicUsize = AtomicUsize::new(KERNEL_HEAP_OFFSET);
 
 #[global_allocator]
-pub static HEAP: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// 在`HEAP`加锁之前补充预留的堆空间，再将分配请求转发给slab分配器
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        reserve_heap(&layout);
+        general().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        general().dealloc(ptr, layout)
+    }
+}
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: Mutex<Option<AdaptationAllocator<BumpAllocator>>> = Mutex::new(None);
+    pub static ref FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+    /// 不超过4KiB的对象从slab中分配，其余的直接使用`HEAP`
+    pub static ref SLAB: SlabAllocator = SlabAllocator::new(&HEAP, boot_cpu);
+}
+
+#[cfg(feature = "heap_debug")]
+lazy_static! {
+    /// 启用`heap_debug`特性后所有分配都经过调试分配器，检查越界写入和释放后使用
+    static ref DEBUG_HEAP: DebugAllocator = DebugAllocator::new(&*SLAB, backtrace);
+}
+
+#[cfg(not(feature = "heap_debug"))]
+fn general() -> &'static SlabAllocator {
+    &SLAB
+}
+
+#[cfg(feature = "heap_debug")]
+fn general() -> &'static DebugAllocator {
+    &DEBUG_HEAP
+}
+
+/// 沿着rbp链记录返回地址，构建时需要`-C force-frame-pointers`（见`build.py`）。
+/// 跳过本函数以及分配器自身的栈帧
+#[cfg(feature = "heap_debug")]
+fn backtrace(callers: &mut [usize; CALLER_DEPTH]) {
+    const SKIP_FRAMES: usize = 2;
+    let mut rbp: usize;
+    unsafe { llvm_asm!("mov $0, rbp" : "=r"(rbp) : : : "intel") };
+    let mut depth = 0;
+    while depth < SKIP_FRAMES + CALLER_DEPTH {
+        // 进程切换时rbp被清零，栈都位于高半部分
+        if rbp == 0 || rbp % 8 != 0 || rbp < 0xFFFF_8000_0000_0000 {
+            break;
+        }
+        let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if depth >= SKIP_FRAMES {
+            callers[depth - SKIP_FRAMES] = ret;
+        }
+        // 调用者的栈帧位于更高的地址
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+        depth += 1;
+    }
+}
+
+/// 返回当前的分配序号，配合`report_leaks`找出之后分配但没有释放的内存
+#[cfg(feature = "heap_debug")]
+pub fn heap_checkpoint() -> u64 {
+    DEBUG_HEAP.checkpoint()
+}
+
+/// 打印`checkpoint`之后分配并且仍然存活的内存，返回地址减去内核滑动偏移后可以直接对照符号表
+#[cfg(feature = "heap_debug")]
+pub fn report_leaks(checkpoint: u64) {
+    let slide = crate::utils::kernel_slide() as usize;
+    let mut count = 0;
+    let mut bytes = 0;
+    DEBUG_HEAP.for_each_leak(checkpoint, |allocation| {
+        count += 1;
+        bytes += allocation.size;
+        print!("leak #{}: {:#x} size={} align={} callers:", allocation.seq, allocation.ptr, allocation.size, allocation.align);
+        for &caller in allocation.callers.iter().take_while(|&&caller| caller != 0) {
+            print!(" {:#x}", caller - slide);
+        }
+        println!();
+    });
+    let stats = DEBUG_HEAP.stats();
+    println!("heap: {} leaks ({} bytes) since #{}, live={} ({} bytes), quarantined={} ({} bytes)",
+             count, bytes, checkpoint, stats.live, stats.live_bytes, stats.quarantined, stats.quarantined_bytes);
+}
+
+/// 目前只启动了BSP
+fn boot_cpu() -> usize {
+    0
+}
+
+/// 使用内核.bss段中的空间初始化堆，之后堆空间不足时从帧分配器中扩展
+pub fn init_heap() {
+    let base = KERNEL_HEAP_OFFSET + kaslr_random(HEAP_RANDOM_SLOTS) as usize * HEAP_RANDOM_ALIGN;
+    HEAP_BASE.store(base, Ordering::SeqCst);
+    HEAP_TOP.store(base, Ordering::SeqCst);
+    MAPPED_TOP.store(base, Ordering::SeqCst);
+    unsafe {
+        let start = INITIAL_HEAP.0.as_ptr() as usize;
+        HEAP.lock().add_to_heap(start, start + INITIAL_HEAP_SIZE);
+    }
 }
 
-pub fn init_frame_allocator(start: u64, end: u64) {
-    *FRAME_ALLOCATOR.lock() = Some(AdaptationAllocator::new(BumpAllocator::new(start, end)))
+/// 使用引导程序提供的内存布局初始化物理帧分配器，
+/// 内核映像，引导信息以及低端内存都不会被分配
+pub fn init_frame_allocator(info: &SystemInformation) {
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.reserve(0, LOW_MEMORY_END);
+    allocator.reserve(info.kernel_start(), info.kernel_end());
+    allocator.reserve(info.boot_start(), info.boot_end());
+    for area in info.mem_area_iter() {
+        allocator.add_area(area.start_addr, area.end_addr, area.ty, area.length);
+    }
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+}
+
+/// 满足`layout`时扩展堆的大小，伙伴系统中的块按自身大小对齐
+fn grow_size(layout: &Layout) -> usize {
+    max(HEAP_GROW_SIZE, max(layout.size(), layout.align()).next_power_of_two())
+}
+
+/// 堆空间不足时由`HEAP`调用，此时`HEAP`处于加锁状态，只能使用已经映射好的预留空间，
+/// 不能访问帧分配器（其内部的`BTreeSet`位于堆上）
+fn grow_heap(heap: &mut Heap, layout: &Layout) {
+    let size = grow_size(layout);
+    let top = HEAP_TOP.load(Ordering::SeqCst);
+    let end = align_up(top as u64, size as u64) as usize + size;
+    if end <= MAPPED_TOP.load(Ordering::SeqCst) {
+        // 对齐留下的空隙也一起加入堆
+        unsafe { heap.add_to_heap(top, end) };
+        HEAP_TOP.store(end, Ordering::SeqCst);
+    }
+}
+
+/// 在`HEAP`加锁之前调用，保证预留空间足够满足`layout`并且至少还有`HEAP_RESERVE_SIZE`字节。
+/// 帧分配器内部的分配也会经过这里，持有页表或帧分配器锁时跳过，由已有的预留空间满足
+fn reserve_heap(layout: &Layout) {
+    let size = grow_size(layout);
+    let top = HEAP_TOP.load(Ordering::SeqCst);
+    let target = max(align_up(top as u64, size as u64) as usize + size, top + HEAP_RESERVE_SIZE)
+        .min(KERNEL_HEAP_OFFSET + KERNEL_HEAP_SIZE);
+    if MAPPED_TOP.load(Ordering::SeqCst) >= target {
+        return;
+    }
+    let mut table = match PAGE_TABLE.try_lock() {
+        Some(table) => table,
+        None => return,
+    };
+    let mut allocator = match FRAME_ALLOCATOR.try_lock() {
+        Some(allocator) => allocator,
+        None => return,
+    };
+    let allocator = match allocator.as_mut() {
+        Some(allocator) => allocator,
+        None => return,
+    };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    // 帧分配器内部的分配可能在这期间从预留空间中扩展堆，每映射一页就更新一次结束地址
+    let mut mapped = MAPPED_TOP.load(Ordering::SeqCst);
+    while mapped < target {
+        let page: Page<Page4KB> = Page::include_address(VirtAddr::new(mapped as u64));
+        if !map_page(&mut table, allocator, page, flags) {
+            return;
+        }
+        mapped += Page4KB::P_SIZE as usize;
+        MAPPED_TOP.store(mapped, Ordering::SeqCst);
+    }
+}
+
+fn map_page(table: &mut KernelPageTable, allocator: &mut BuddyFrameAllocator, page: Page<Page4KB>, flags: PageTableFlags) -> bool {
+    let frame = match allocator.alloc() {
+        Some(frame) => frame,
+        None => return false,
+    };
+    match unsafe { table.map_to(page, frame.frame(), flags, allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            allocator.dealloc(frame);
+            false
+        }
+    }
 }
 
 #[alloc_error_handler]
 fn handler(layout: Layout) -> ! {
     println!("allocate memory Error: align={} ,size={}", layout.align(), layout.size());
+    // 出错时可能仍然持有堆的锁
+    if let Some(heap) = HEAP.try_lock() {
+        println!("heap: total={} allocated={} requested={} mapped={:#x} reserved={:#x}",
+                 heap.stats_total_bytes(),
+                 heap.stats_alloc_actual(),
+                 heap.stats_alloc_user(),
+                 MAPPED_TOP.load(Ordering::SeqCst) - HEAP_BASE.load(Ordering::SeqCst),
+                 MAPPED_TOP.load(Ordering::SeqCst) - HEAP_TOP.load(Ordering::SeqCst));
+    }
+    if let Some(allocator) = FRAME_ALLOCATOR.try_lock() {
+        if let Some(allocator) = allocator.as_ref() {
+            println!("frames: free={} used={}", allocator.free_frames(), allocator.used_frames());
+        }
+    }
     loop_hlt()
 }
 
@@ -33,14 +266,13 @@ pub unsafe fn add_to_heap(start: usize, end: usize) {
 }
 
 
+/// 分配清零的内存，按16字节对齐（fxsave要求）。
+/// 必须经过全局分配器，`Box`释放时才能找到对应的缓存
 pub unsafe fn alloc_memory(size: usize) -> Result<Box<[u8]>> {
-    let ptr = HEAP.lock().alloc(Layout::from_size_align_unchecked(size, 16))?;
-    let slice = slice_from_raw_parts_mut(ptr.as_ptr(), size);
-    let mut box_ptr = Box::from_raw(slice);
-
-    for i in box_ptr.iter_mut() {
-        *i = 0;
+    let ptr = alloc::alloc::alloc_zeroed(Layout::from_size_align_unchecked(size, 16));
+    if ptr.is_null() {
+        return Err(Error::new_memory(MemErrorKind::AllocateFiled, String::from("memory allocation failed")));
     }
-    Ok(box_ptr)
+    let slice = slice_from_raw_parts_mut(ptr, size);
+    Ok(Box::from_raw(slice))
 }
-
@@ -0,0 +1,66 @@
+use alloc::string::String;
+use core::alloc::Layout;
+use core::{ptr, slice};
+
+use system::ia_32e::{PhysAddr, VirtAddr};
+use system::ia_32e::paging::{Frame, FrameAllocator, Page4KB, PageSize};
+use system::result::{Error, MemErrorKind, Result};
+
+use crate::memory::{FRAME_ALLOCATOR, phys_to_virt};
+
+/// 物理地址连续的缓冲区，供设备DMA使用，内核通过物理内存的线性映射访问
+#[derive(Debug)]
+pub struct DmaBuffer {
+    frame: Frame,
+    size: usize,
+    count: usize,
+}
+
+impl DmaBuffer {
+    /// 分配至少`size`字节清零的缓冲区，按页对齐
+    pub fn new(size: usize) -> Result<Self> {
+        let layout = Layout::from_size_align(size, Page4KB::P_SIZE as usize)
+            .map_err(|_| Error::new_memory(MemErrorKind::AllocateFiled, String::from("invalid dma buffer size")))?;
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        let frame = allocator.alloc_size(layout)
+            .ok_or_else(|| Error::new_memory(MemErrorKind::AllocateFiled, String::from("out of physical memory")))?
+            .frame();
+        let buffer = Self {
+            frame,
+            size,
+            count: (size + Page4KB::P_SIZE as usize - 1) / Page4KB::P_SIZE as usize,
+        };
+        unsafe { ptr::write_bytes(buffer.virtual_address().as_mut_ptr::<u8>(), 0, size) };
+        Ok(buffer)
+    }
+
+    /// 交给设备的物理地址
+    pub fn physical_address(&self) -> PhysAddr {
+        self.frame.start_address()
+    }
+
+    pub fn virtual_address(&self) -> VirtAddr {
+        phys_to_virt(self.frame.start_address())
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virtual_address().as_ptr(), self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virtual_address().as_mut_ptr(), self.size) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            allocator.dealloc_size(self.frame, self.count);
+        }
+    }
+}
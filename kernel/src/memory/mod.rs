@@ -1,6 +1,14 @@
-pub use allocator::{add_to_heap, alloc_memory, FRAME_ALLOCATOR, HEAP, init_frame_allocator};
-pub use page_table::{init_page, PML4T, RECU_PAGE_TABLE};
+pub use allocator::{add_to_heap, alloc_memory, FRAME_ALLOCATOR, HEAP, init_frame_allocator, init_heap, KERNEL_HEAP_OFFSET, KERNEL_HEAP_SIZE, SLAB};
+#[cfg(feature = "heap_debug")]
+pub use allocator::{heap_checkpoint, report_leaks};
+pub use dma::DmaBuffer;
+pub use page_table::{GIGABYTE_PAGE, init_device_memory, init_direct_map, init_page, init_pat, KernelPageTable, map_device, map_firmware, map_physical, PAGE_TABLE, phys_offset, phys_to_virt, remap_kernel, set_cache_type};
+pub use pcid::{init_pcid, pcid_enabled, release_pcid, switch_address_space};
+pub use tlb::{cpu_online, flush_address_space, flush_range, FlushTarget, handle_tlb_requests, init_tlb_shootdown, set_active_address_space};
 
 mod allocator;
+mod dma;
 mod page_table;
+mod pcid;
+mod tlb;
 
@@ -1,46 +1,374 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 use spin::Mutex;
-use system::ia_32e::paging::{PageIndex, PageTable};
-use system::ia_32e::paging::mapper::RecursivePageTable;
+use system::{KernelArea, KERNEL_OFFSET, SystemInformation};
+use system::bits::{CR0Flags, EferFlags, KernelSectionFlags, PageTableFlags};
+use system::ia_32e::{align_up, PhysAddr, VirtAddr};
+use system::ia_32e::cpu::apic::Efer;
+use system::ia_32e::cpu::control::{CR0, CR3};
+use system::ia_32e::cpu::pat::{CacheType, Pat};
+use system::ia_32e::instructions::page_table::flush_all;
+use system::ia_32e::paging::{ENTRY_COUNT, Frame, FrameAllocator, Page, Page2MB, Page4KB, PageIndex, PageRangeInclude, PageSize, PageTable};
+use system::ia_32e::paging::mapper::{map_range, MapAllSize, Mapper, MapperFlush, PageTableOffset, RecursivePageTable};
+use system::ia_32e::paging::result::{FlagUpdateError, MapToError, TranslateError, TranslationResult, UnmapError};
 
 use lazy_static::lazy_static;
 
-pub const PML4T: usize = 0xffffffff_fffff000;
+use crate::memory::FRAME_ALLOCATOR;
+use crate::utils::{kaslr_random, SystemFunctionalCheck};
+
+/// 最后一个PML4项留给高半部分的内核，递归项使用倒数第二项，5级分页时递归项位于5级页表中
+const RECURSIVE_INDEX: u16 = 510;
+/// 物理内存线性映射（direct map）区域的起始地址
+const DIRECT_MAP_OFFSET: u64 = 0xFFFF_8000_0000_0000;
+/// 启用地址随机化时，线性映射的起始地址在区域前64TiB中以1GiB为单位随机选择，对齐后仍然可以使用1GB的大页
+const DIRECT_MAP_RANDOM_SLOTS: u64 = 0x1_0000;
+const DIRECT_MAP_RANDOM_ALIGN: u64 = 0x4000_0000;
+/// 线性映射的起始地址，物理地址`p`映射到`phys_offset() + p`
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(DIRECT_MAP_OFFSET);
+
+/// 是否已经建立物理内存的线性映射
+static DIRECT_MAP: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
-    pub static ref RECU_PAGE_TABLE:Mutex<RecursivePageTable<'static>> = init_page();
+    pub static ref PAGE_TABLE: Mutex<KernelPageTable> = init_page();
+    /// CPU是否支持1GB的大页
+    pub static ref GIGABYTE_PAGE: bool = SystemFunctionalCheck::get_check_result().support_1gb_page();
+}
+
+/// 内核使用的页表映射器。
+/// 启动时通过最高级页表中的递归项访问页表，建立物理内存的线性映射后可以切换为通过线性映射访问
+#[derive(Debug)]
+pub enum KernelPageTable {
+    Recursive(RecursivePageTable<'static>),
+    Offset(PageTableOffset<'static>),
+}
+
+impl KernelPageTable {
+    /// 返回映射`page`的页表项的flags，大页返回大页页表项的flags
+    pub fn page_flags(&self, page: Page<Page4KB>) -> Option<PageTableFlags> {
+        match self {
+            KernelPageTable::Recursive(table) => table.page_flags(page),
+            KernelPageTable::Offset(table) => table.page_flags(page),
+        }
+    }
+
+    /// 将包含`addr`的大页拆分为下一级的页面
+    pub fn split_huge_page<A>(&mut self, addr: VirtAddr, allocator: &mut A) -> Result<bool, MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB> {
+        match self {
+            KernelPageTable::Recursive(table) => table.split_huge_page(addr, allocator),
+            KernelPageTable::Offset(table) => table.split_huge_page(addr, allocator),
+        }
+    }
+
+    pub fn is_offset(&self) -> bool {
+        match self {
+            KernelPageTable::Recursive(_) => false,
+            KernelPageTable::Offset(_) => true,
+        }
+    }
+}
+
+impl<S: PageSize> Mapper<S> for KernelPageTable
+    where RecursivePageTable<'static>: Mapper<S>, PageTableOffset<'static>: Mapper<S> {
+    unsafe fn map_to<A>(&mut self, page: Page<S>, frame: Frame<S>, flags: PageTableFlags, allocator: &mut A)
+                        -> Result<MapperFlush<S>, MapToError<S>> where A: FrameAllocator<Page4KB>, Self: Sized {
+        match self {
+            KernelPageTable::Recursive(table) => Mapper::<S>::map_to(table, page, frame, flags, allocator),
+            KernelPageTable::Offset(table) => Mapper::<S>::map_to(table, page, frame, flags, allocator),
+        }
+    }
+
+    fn unmap(&mut self, page: Page<S>) -> Result<(Frame<S>, MapperFlush<S>), UnmapError> {
+        match self {
+            KernelPageTable::Recursive(table) => Mapper::<S>::unmap(table, page),
+            KernelPageTable::Offset(table) => Mapper::<S>::unmap(table, page),
+        }
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<S>, flags: PageTableFlags) -> Result<MapperFlush<S>, FlagUpdateError> {
+        match self {
+            KernelPageTable::Recursive(table) => Mapper::<S>::update_flags(table, page, flags),
+            KernelPageTable::Offset(table) => Mapper::<S>::update_flags(table, page, flags),
+        }
+    }
+
+    fn translate_page(&mut self, page: Page<S>) -> Result<Frame<S>, TranslateError> {
+        match self {
+            KernelPageTable::Recursive(table) => Mapper::<S>::translate_page(table, page),
+            KernelPageTable::Offset(table) => Mapper::<S>::translate_page(table, page),
+        }
+    }
+}
+
+impl MapAllSize for KernelPageTable {
+    fn translate(&self, addr: VirtAddr) -> TranslationResult {
+        match self {
+            KernelPageTable::Recursive(table) => table.translate(addr),
+            KernelPageTable::Offset(table) => table.translate(addr),
+        }
+    }
 }
-pub fn init_page() -> Mutex<RecursivePageTable<'static>> {
-    let res = Mutex::new(unsafe { RecursivePageTable::new_unchecked(&mut *(PML4T as *mut PageTable), PageIndex::new(511)) });
+
+pub fn init_page() -> Mutex<KernelPageTable> {
+    let index = PageIndex::new(RECURSIVE_INDEX);
+    let top = RecursivePageTable::table_address(index);
+    let table = unsafe { RecursivePageTable::new_unchecked(&mut *top.as_mut_ptr::<PageTable>(), index) };
+    let res = Mutex::new(KernelPageTable::Recursive(table));
     println!("enable paging... done");
     res
 }
 
-// pub fn map_kernel(info: &SystemInformation) {
-//     let mut table = RECU_PAGE_TABLE.lock();
-//
-//     for area in info.kernel_area_iter() {
-//
-//         match area.section_ty{
-//             KernelSectionType::ProcessorSpecific=> {
-//                 let start: Page = Page::include_address(VirtAddr::new(area.start_addr));
-//                 let end: Page = Page::include_address(VirtAddr::new(area.end_addr));
-//                 let start_frame:Frame = Frame::include_address(PhysAddr::new(area.start_addr));
-//                 let end_frame = Frame::include_address(PhysAddr::new(area.start_addr + area.size - 1));
-//                 let flag = match area.flags {
-//                     KernelSectionFlags::WRITABLE => PageTableFlags::WRITABLE,
-//                     KernelSectionFlags::EXECUTABLE => PageTableFlags::ACCESSED,
-//                     KernelSectionFlags::ALLOCATED => PageTableFlags::NO_EXECUTE
-//                 } | PageTableFlags::PRESENT;
-//                 for frame in Frame::frame_range_include(start_frame,end_frame){
-//                     let offset = frame - start_frame;
-//                     let page = start + offset;
-//                     unsafe{
-//                         let allocator = FRAME_ALLOCATOR.lock().as_mut().unwrap();
-//                         table.map_to(page,frame,flag,allocator).unwrap().flush();
-//                     }
-//                 }
-//             }
-//             _ => {println!("no support yet")}
-//         }
-//     }
-// }
+/// 将所有物理内存映射到线性映射区域中随机选择的起始地址，需要在`remap_kernel`之后调用。
+/// 选择了offset模式时（见`offset_mode`），之后的页表操作都通过线性映射完成，不再依赖递归映射
+pub fn init_direct_map(info: &SystemInformation) {
+    let end = info.mem_area_iter().map(|area| area.end_addr).max().unwrap_or(0);
+    let size = align_up(end, Page2MB::P_SIZE);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    PHYS_OFFSET.store(DIRECT_MAP_OFFSET + kaslr_random(DIRECT_MAP_RANDOM_SLOTS) * DIRECT_MAP_RANDOM_ALIGN, Ordering::SeqCst);
+    map_physical(VirtAddr::new(phys_offset()), PhysAddr::new(0), size, flags, CacheType::WriteBack).expect("map physical memory failed");
+    protect_kernel_alias(info);
+    DIRECT_MAP.store(true, Ordering::SeqCst);
+    println!("direct map: {:#x} bytes at {:#x}", size, phys_offset());
+
+    if offset_mode(info) {
+        let mut table = PAGE_TABLE.lock();
+        let (frame, _) = CR3::read();
+        let p4: &'static mut PageTable = unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() };
+        *table = KernelPageTable::Offset(unsafe { PageTableOffset::new(p4, VirtAddr::new(phys_offset())) });
+        println!("page table: offset mode");
+    }
+}
+
+/// 内核的代码和只读数据在线性映射中的别名也设为只读。
+/// 可写的section保持可写，启动时的页表位于.bss中，offset模式下需要通过线性映射修改
+fn protect_kernel_alias(info: &SystemInformation) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    let mut table = PAGE_TABLE.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    let areas = info.kernel_area_iter().filter(|area| {
+        area.flags.contains(KernelSectionFlags::ALLOCATED) && !area.flags.contains(KernelSectionFlags::WRITABLE) && area.size > 0
+    });
+    for area in areas {
+        for page in area_pages(area) {
+            let phys = match table.translate_addr(page.start_address()) {
+                Some(phys) => phys,
+                None => continue,
+            };
+            let alias = VirtAddr::new(phys_offset() + phys.as_u64());
+            // 1GB的大页需要拆分两次
+            while table.split_huge_page(alias, allocator).expect("split direct map failed") {}
+            unsafe { Mapper::<Page4KB>::update_flags(&mut *table, Page::include_address(alias), flags) }.expect("protect kernel alias failed").flush();
+        }
+    }
+}
+
+/// 页表操作是否通过线性映射完成。内核命令行中的`paging=offset`或`paging=recursive`优先，
+/// 没有指定时（例如UEFI引导）由`offset_paging`特性决定
+fn offset_mode(info: &SystemInformation) -> bool {
+    let option = info.command_line().and_then(|line| line.split_whitespace().find_map(|arg| match arg {
+        "paging=offset" => Some(true),
+        "paging=recursive" => Some(false),
+        _ => None,
+    }));
+    option.unwrap_or(cfg!(feature = "offset_paging"))
+}
+
+/// 返回物理地址在线性映射中的虚拟地址
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    assert!(DIRECT_MAP.load(Ordering::SeqCst), "direct map not initialized");
+    VirtAddr::new(phys_offset() + addr.as_u64())
+}
+
+/// 线性映射的起始地址，建立线性映射之前为区域的起始地址
+pub fn phys_offset() -> u64 {
+    PHYS_OFFSET.load(Ordering::Relaxed)
+}
+
+/// 将物理内存`[phys, phys + size)`以`cache`类型映射到从`virt`开始的虚拟地址，对齐允许时使用大页
+pub fn map_physical(virt: VirtAddr, phys: PhysAddr, size: u64, flags: PageTableFlags, cache: CacheType) -> Result<(), MapToError<Page4KB>> {
+    let mut table = PAGE_TABLE.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    unsafe { map_range(&mut *table, virt, phys, size, flags, cache, *GIGABYTE_PAGE, allocator) }
+}
+
+/// 写入PAT的布局，之后才能使用写合并等内存类型。不支持PAT时写合并的映射退化为写穿
+pub fn init_pat() {
+    if !SystemFunctionalCheck::get_check_result().support_pat() {
+        println!("pat not supported");
+        return;
+    }
+    unsafe { Pat::init() };
+    println!("pat: {:#018x}", Pat::read());
+}
+
+/// 修改`[start, start + size)`中已经映射的页面的内存类型，没有映射的页面被跳过，大页会被拆分为4KB页面
+pub fn set_cache_type(start: VirtAddr, size: u64, cache: CacheType) -> Result<(), MapToError<Page4KB>> {
+    if size == 0 {
+        return Ok(());
+    }
+    let mut table = PAGE_TABLE.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    let first = Page::<Page4KB>::include_address(start);
+    let last = Page::<Page4KB>::include_address(start + (size - 1));
+    for page in Page::range_include(first, last) {
+        // 1GB的大页需要拆分两次
+        while table.split_huge_page(page.start_address(), allocator)? {}
+        if let Some(flags) = table.page_flags(page) {
+            let flags = (flags - CacheType::mask()) | cache.flags();
+            unsafe { Mapper::<Page4KB>::update_flags(&mut *table, page, flags) }.expect("set cache type failed").flush();
+        }
+    }
+    Ok(())
+}
+
+/// 帧缓冲区使用写合并，需要在`init_direct_map`之后调用。
+/// 低地址的恒等映射已经解除，帧缓冲区只通过线性映射访问，位于内存区域之外时在这里补上映射
+pub fn init_device_memory(info: &SystemInformation) {
+    let set = |phys: u64, size: u64, cache: CacheType| {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        map_missing(PhysAddr::new(phys), size, flags, cache);
+        set_cache_type(phys_to_virt(PhysAddr::new(phys)), size, cache).expect("set device memory type failed");
+    };
+    if let Some((addr, size)) = info.frame_buffer() {
+        // 写合并需要PAT位，不支持PAT时该位是保留位
+        if SystemFunctionalCheck::get_check_result().support_pat() {
+            set(addr, size as u64, CacheType::WriteCombining);
+            println!("frame buffer: {:#x} bytes at {:#x}, write combining", size, addr);
+        } else {
+            set(addr, size as u64, CacheType::WriteThrough);
+            println!("frame buffer: {:#x} bytes at {:#x}, write through", size, addr);
+        }
+    }
+}
+
+/// 在线性映射中以不可缓存的类型映射设备寄存器`[phys, phys + size)`，返回对应的虚拟地址。
+/// 线性映射只覆盖到最后一块内存区域，之后的设备寄存器所在的页面在这里补上
+pub fn map_device(phys: PhysAddr, size: u64) -> VirtAddr {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let (first, last) = map_missing(phys, size, flags, CacheType::Uncacheable);
+    let size = last.start_address() - first.start_address() + Page4KB::P_SIZE;
+    set_cache_type(first.start_address(), size, CacheType::Uncacheable).expect("set device memory type failed");
+    phys_to_virt(phys)
+}
+
+/// 确保固件数据（例如ACPI表）`[phys, phys + size)`在线性映射中可以读取，返回对应的虚拟地址。
+/// 线性映射没有覆盖的页面以只读的写回类型补上，已经映射的页面保持不变
+pub fn map_firmware(phys: PhysAddr, size: u64) -> VirtAddr {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    map_missing(phys, size, flags, CacheType::WriteBack);
+    phys_to_virt(phys)
+}
+
+/// 映射线性映射中`[phys, phys + size)`所在的页面里还没有映射的部分，返回第一个和最后一个页面
+fn map_missing(phys: PhysAddr, size: u64, flags: PageTableFlags, cache: CacheType) -> (Page<Page4KB>, Page<Page4KB>) {
+    let virt = phys_to_virt(phys);
+    let first = Page::<Page4KB>::include_address(virt);
+    let last = Page::<Page4KB>::include_address(virt + (size.max(1) - 1));
+    for page in Page::range_include(first, last) {
+        let unmapped = match PAGE_TABLE.lock().translate(page.start_address()) {
+            TranslationResult::PageNotMapped => true,
+            _ => false,
+        };
+        if unmapped {
+            let frame = PhysAddr::new(page.start_address().as_u64() - phys_offset());
+            map_physical(page.start_address(), frame, Page4KB::P_SIZE, flags, cache).expect("map physical memory failed");
+        }
+    }
+    (first, last)
+}
+
+/// 按照内核各section的flags重新映射内核映像：代码只读可执行，只读数据只读不可执行，数据可写不可执行。
+/// 内核运行在高半部分时，随后解除启动时建立的低地址恒等映射，并启用CR0.WP使内核写入只读页面时触发缺页
+pub fn remap_kernel(info: &SystemInformation) {
+    // 启用NXE之前NO_EXECUTE是页表项的保留位
+    unsafe { Efer::write(Efer::read() | EferFlags::NO_EXECUTE_ENABLE) };
+    let higher_half = info.kernel_area_iter().any(|area| area.start_addr >= KERNEL_OFFSET);
+    // 高半部分内核中位于低地址的只有引导代码，会和恒等映射一起解除
+    let sections = || info.kernel_area_iter().filter(move |area| {
+        area.flags.contains(KernelSectionFlags::ALLOCATED) && area.size > 0
+            && (!higher_half || area.start_addr >= KERNEL_OFFSET)
+    });
+
+    let mut table = PAGE_TABLE.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    for area in sections() {
+        let flags = section_flags(area.flags);
+        for page in area_pages(area) {
+            // 1GB的大页需要先拆分为2MB，再拆分为4KB
+            while table.split_huge_page(page.start_address(), allocator).expect("split kernel page failed") {}
+            unsafe { Mapper::<Page4KB>::update_flags(&mut *table, page, flags) }.expect("remap kernel failed").flush();
+        }
+    }
+
+    if higher_half {
+        // 高半部分中内核映像以外的内存只用于访问引导信息和VGA缓冲区，不允许执行
+        let data = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let in_kernel = |page: Page<Page4KB>| {
+            let addr = page.start_address().as_u64();
+            sections().any(|area| addr + Page4KB::P_SIZE > area.start_addr && addr < area.end_addr)
+        };
+        let start = Page::<Page2MB>::include_address(VirtAddr::new(KERNEL_OFFSET));
+        for huge in Page::range_page(start, start + BOOT_MAP_PAGES) {
+            match table.translate(huge.start_address()) {
+                TranslationResult::Frame2MB { .. } => {
+                    unsafe { Mapper::<Page2MB>::update_flags(&mut *table, huge, data) }.expect("remap kernel failed").flush();
+                }
+                TranslationResult::Frame4KB { .. } => {
+                    let first = Page::<Page4KB>::include_address(huge.start_address());
+                    for page in Page::range_page(first, first + ENTRY_COUNT as u64) {
+                        // UEFI引导时内核所在的2MB中可能有未映射的页面
+                        if in_kernel(page) || table.page_flags(page).is_none() {
+                            continue;
+                        }
+                        unsafe { Mapper::<Page4KB>::update_flags(&mut *table, page, data) }.expect("remap kernel failed").flush();
+                    }
+                }
+                _ => {}
+            }
+        }
+        unmap_identity(&table);
+    }
+    unsafe { CR0::write(CR0::read() | CR0Flags::WRITE_PROTECT) };
+    println!("remap kernel: higher half {}", higher_half);
+}
+
+/// 启动时用2MB大页映射的物理内存大小（1GB）
+const BOOT_MAP_PAGES: u64 = ENTRY_COUNT as u64;
+
+/// 解除启动时建立的低地址恒等映射。
+/// 固件建立的恒等映射可能使用任意大小的页面并且覆盖1GB以上的内存，
+/// 因此直接清空最高级页表中低半部分的页表项，下级页表属于引导程序或固件，不需要释放。
+/// 需要在切换为通过线性映射访问页表之前调用
+fn unmap_identity(table: &KernelPageTable) {
+    assert!(!table.is_offset(), "unmap identity after switching to direct map");
+    let top = RecursivePageTable::table_address(PageIndex::new(RECURSIVE_INDEX));
+    let top = unsafe { &mut *top.as_mut_ptr::<PageTable>() };
+    for entry in top.iter_mut().take(ENTRY_COUNT / 2) {
+        entry.set_unused();
+    }
+    unsafe { flush_all() };
+}
+
+/// 根据ELF section的flags计算页表项的flags
+fn section_flags(flags: KernelSectionFlags) -> PageTableFlags {
+    let mut page_flags = PageTableFlags::PRESENT;
+    if flags.contains(KernelSectionFlags::WRITABLE) {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    if !flags.contains(KernelSectionFlags::EXECUTABLE) {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    page_flags
+}
+
+fn area_pages(area: &KernelArea) -> PageRangeInclude<Page4KB> {
+    let start = Page::include_address(VirtAddr::new(area.start_addr));
+    let end = Page::include_address(VirtAddr::new(area.end_addr - 1));
+    Page::range_include(start, end)
+}
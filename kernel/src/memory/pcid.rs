@@ -0,0 +1,148 @@
+//! 进程上下文标识符（PCID）
+//!
+//! 启用后TLB中的缓存带有PCID标记，切换地址空间时不需要清除TLB。
+//! 每个页表分配一个PCID，切换时写入CR3的低12位，PCID用完后按顺序回收。
+//! 被回收的PCID在所有CPU上标记为过期，CPU下次切换到过期的PCID时清除它的缓存；
+//! 修改页表时没有运行该地址空间的CPU也会被标记为过期，而不是发送中断。
+//! 内核页面没有设置GLOBAL，修改内核页面时用INVPCID逐个清除所有PCID中该地址的缓存。
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+use system::bits::{CR3Flags, CR4Flags};
+use system::ia_32e::cpu::control::{CR3, CR4};
+use system::ia_32e::instructions::page_table::{invpcid, InvpcidKind};
+use system::ia_32e::paging::Frame;
+use system::ia_32e::{PhysAddr, VirtAddr};
+
+use lazy_static::lazy_static;
+
+use crate::memory::tlb::MAX_CPUS;
+use crate::process::cpu_id;
+use crate::utils::SystemFunctionalCheck;
+
+const PCID_COUNT: usize = 4096;
+/// 0号PCID保留给启用PCID时正在使用的页表
+const FIRST_PCID: usize = 1;
+const CR3_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const WORDS_PER_CPU: usize = PCID_COUNT / 64;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// 下一个分配的PCID，分配和回收PCID时持有
+static NEXT: Mutex<usize> = Mutex::new(FIRST_PCID);
+/// 分配过的最大PCID加1，刷新内核页面时只需要遍历它之前的PCID
+static HIGH: AtomicUsize = AtomicUsize::new(FIRST_PCID);
+
+lazy_static! {
+    /// 每个PCID对应的页表物理地址，0表示空闲。中断中刷新TLB时不加锁读取
+    static ref OWNERS: Vec<AtomicU64> = (0..PCID_COUNT).map(|_| AtomicU64::new(0)).collect();
+    /// 每个CPU一个位图，第n位表示该CPU上n号PCID的缓存已经过期
+    static ref STALE: Vec<AtomicU64> = (0..MAX_CPUS * WORDS_PER_CPU).map(|_| AtomicU64::new(0)).collect();
+}
+
+/// 需要在`init_tlb_shootdown`之后调用，回收PCID和刷新内核页面都依赖INVPCID
+pub fn init_pcid() {
+    let check = SystemFunctionalCheck::get_check_result();
+    if !check.support_pcid() || !check.support_invpcid() {
+        println!("pcid or invpcid not supported");
+        return;
+    }
+    lazy_static::initialize(&OWNERS);
+    lazy_static::initialize(&STALE);
+    // 当前CR3的低12位为0，可以直接启用
+    unsafe { CR4::write(CR4::read() | CR4Flags::PCID) };
+    ENABLED.store(true, Ordering::SeqCst);
+    println!("pcid enabled");
+}
+
+pub fn pcid_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+fn find(addr: u64) -> Option<usize> {
+    (FIRST_PCID..HIGH.load(Ordering::SeqCst)).find(|&pcid| OWNERS[pcid].load(Ordering::SeqCst) == addr)
+}
+
+/// 为页表分配PCID，没有空闲的PCID时回收下一个
+fn assign(addr: u64) -> usize {
+    let mut next = NEXT.lock();
+    if let Some(pcid) = find(addr) {
+        return pcid;
+    }
+    let pcid = *next;
+    *next = if pcid + 1 == PCID_COUNT { FIRST_PCID } else { pcid + 1 };
+    // 之前的页表可能在任意CPU上留下了缓存
+    mark_stale(pcid, !0);
+    OWNERS[pcid].store(addr, Ordering::SeqCst);
+    if pcid + 1 > HIGH.load(Ordering::SeqCst) {
+        HIGH.store(pcid + 1, Ordering::SeqCst);
+    }
+    pcid
+}
+
+/// 切换到进程上下文中保存的页表`cr3`，页表还没有PCID或者它的PCID已经被回收时重新分配，
+/// 缓存没有过期时保留TLB。返回应当保存回上下文的CR3值，需要在关中断时调用
+pub unsafe fn switch_address_space(cr3: u64) -> u64 {
+    let addr = cr3 & CR3_ADDR_MASK;
+    let frame = Frame::include_address(PhysAddr::new(addr));
+    if !pcid_enabled() {
+        if CR3::read().0 != frame {
+            CR3::write(frame, CR3Flags::empty());
+        }
+        return addr;
+    }
+    let mut pcid = (cr3 & CR3::PCID_MASK) as usize;
+    if pcid < FIRST_PCID || OWNERS[pcid].load(Ordering::SeqCst) != addr {
+        pcid = assign(addr);
+    }
+    let bit = 1 << (pcid % 64);
+    let word = &STALE[cpu_id() * WORDS_PER_CPU + pcid / 64];
+    let stale = word.fetch_and(!bit, Ordering::SeqCst) & bit != 0;
+    if CR3::read_pcid() != (frame, pcid as u16) || stale {
+        CR3::write_pcid(frame, pcid as u16, !stale);
+    }
+    addr | pcid as u64
+}
+
+/// 页表`cr3`被修改后调用，`cpus`中的CPU下次切换到它时清除缓存
+pub fn invalidate_address_space(cr3: u64, cpus: u64) {
+    if !pcid_enabled() {
+        return;
+    }
+    let addr = cr3 & CR3_ADDR_MASK;
+    let pcid = (cr3 & CR3::PCID_MASK) as usize;
+    let pcid = if pcid >= FIRST_PCID && OWNERS[pcid].load(Ordering::SeqCst) == addr { Some(pcid) } else { find(addr) };
+    if let Some(pcid) = pcid {
+        mark_stale(pcid, cpus);
+    }
+}
+
+/// 清除当前CPU上所有PCID中内核页面`addr`的缓存
+pub unsafe fn flush_kernel_page(addr: VirtAddr) {
+    // 0号PCID是启用PCID之前的页表使用的
+    invpcid(InvpcidKind::Address, 0, addr);
+    for pcid in FIRST_PCID..HIGH.load(Ordering::SeqCst) {
+        if OWNERS[pcid].load(Ordering::SeqCst) != 0 {
+            invpcid(InvpcidKind::Address, pcid as u16, addr);
+        }
+    }
+}
+
+/// 页表不再被任何进程使用时调用，之后同一个物理页作为新的页表时不会继承旧的PCID
+pub fn release_pcid(cr3: u64) {
+    if !pcid_enabled() {
+        return;
+    }
+    let addr = cr3 & CR3_ADDR_MASK;
+    let _next = NEXT.lock();
+    if let Some(pcid) = find(addr) {
+        OWNERS[pcid].store(0, Ordering::SeqCst);
+        mark_stale(pcid, !0);
+    }
+}
+
+fn mark_stale(pcid: usize, cpus: u64) {
+    for cpu in (0..MAX_CPUS).filter(|cpu| cpus & (1 << cpu) != 0) {
+        STALE[cpu * WORDS_PER_CPU + pcid / 64].fetch_or(1 << (pcid % 64), Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,203 @@
+//! 多处理器之间的TLB同步
+//!
+//! 修改页表的CPU将刷新请求放入目标CPU的队列，再发送`IpiKind::Tlb`中断，
+//! 目标CPU处理完请求后递减请求中的计数，发起者等待计数归零后才返回。
+//! 只有正在使用对应地址空间的CPU会被通知，内核地址对所有CPU都有效。
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering, spin_loop_hint};
+
+use spin::Mutex;
+use system::ia_32e::cpu::control::CR3;
+use system::ia_32e::instructions::page_table::{flush, flush_all, invpcid, InvpcidKind, set_shootdown_handler};
+use system::ia_32e::VirtAddr;
+use system::ia_32e::paging::{Page4KB, PageSize};
+
+use lazy_static::lazy_static;
+
+use crate::descriptor::CONTROLLER;
+use crate::interrupt::ipi::IpiKind;
+use crate::memory::pcid::{flush_kernel_page, invalidate_address_space, pcid_enabled};
+use crate::process::cpu_id;
+
+/// 在线CPU使用一个u64的位图记录
+pub const MAX_CPUS: usize = 64;
+/// 每个CPU最多同时等待处理的请求数
+const QUEUE_LEN: usize = 16;
+/// 超过该页数时直接刷新整个TLB
+const FLUSH_ALL_THRESHOLD: u64 = 32;
+/// 表示内核地址空间，内核的映射在所有页表中共享
+const KERNEL_SPACE: u64 = 0;
+const KERNEL_SPACE_START: u64 = 0xFFFF_8000_0000_0000;
+const CR3_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FlushTarget {
+    /// 从`start`开始的`count`个大小为`page_size`的页面
+    Pages { start: u64, page_size: u64, count: u64 },
+    All,
+}
+
+#[derive(Clone, Copy)]
+struct Request {
+    /// 页表的物理地址，为`KERNEL_SPACE`时所有CPU都需要刷新
+    address_space: u64,
+    target: FlushTarget,
+    /// 位于发起者的栈上，发起者在计数归零之前不会返回
+    pending: *const AtomicUsize,
+}
+
+unsafe impl Send for Request {}
+
+struct CpuState {
+    /// 当前使用的页表的物理地址
+    address_space: AtomicU64,
+    queue: Mutex<[Option<Request>; QUEUE_LEN]>,
+}
+
+/// 已经启动的CPU，第n位对应`cpu_id`为n的CPU
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref CPUS: Vec<CpuState> = (0..MAX_CPUS).map(|_| CpuState {
+        address_space: AtomicU64::new(0),
+        queue: Mutex::new([None; QUEUE_LEN]),
+    }).collect();
+}
+
+/// 需要在堆初始化之后调用，之后`MapperFlush::flush`会通知其他CPU
+pub fn init_tlb_shootdown() {
+    lazy_static::initialize(&CPUS);
+    cpu_online();
+    set_shootdown_handler(flush_hook);
+}
+
+/// CPU启动后调用，开始接收刷新请求
+pub fn cpu_online() {
+    let cpu = cpu_id();
+    set_active_address_space(CR3::read().0.start_address().as_u64());
+    ONLINE_CPUS.fetch_or(1 << cpu, Ordering::SeqCst);
+}
+
+/// 切换页表之前调用，记录当前CPU正在使用的地址空间
+pub fn set_active_address_space(cr3: u64) {
+    CPUS[cpu_id()].address_space.store(cr3 & CR3_ADDR_MASK, Ordering::SeqCst);
+}
+
+/// 内核地址属于`KERNEL_SPACE`，用户地址属于当前页表
+fn address_space_of(addr: u64) -> u64 {
+    if addr >= KERNEL_SPACE_START {
+        KERNEL_SPACE
+    } else {
+        CR3::read().0.start_address().as_u64()
+    }
+}
+
+/// 注册给`system`，本地当前PCID的TLB已经刷新
+fn flush_hook(addr: VirtAddr, page_size: u64) {
+    let address_space = address_space_of(addr.as_u64());
+    let target = FlushTarget::Pages { start: addr.as_u64(), page_size, count: 1 };
+    if address_space == KERNEL_SPACE && pcid_enabled() {
+        unsafe { invalidate(address_space, target) };
+    }
+    shootdown(address_space, target);
+}
+
+/// 刷新`[start, end)`中的所有页面，当前CPU直接刷新，其他CPU合并为一次请求。
+/// 逐页修改页表时使用`MapperFlush::ignore`，改完后调用一次，页数超过`FLUSH_ALL_THRESHOLD`时刷新整个TLB
+pub fn flush_range(start: VirtAddr, end: VirtAddr) {
+    if start >= end {
+        return;
+    }
+    let count = (end.as_u64() - start.as_u64() + Page4KB::P_SIZE - 1) / Page4KB::P_SIZE;
+    let target = FlushTarget::Pages { start: start.as_u64(), page_size: Page4KB::P_SIZE, count };
+    let address_space = address_space_of(start.as_u64());
+    unsafe { invalidate(address_space, target) };
+    shootdown(address_space, target);
+}
+
+/// 通知正在使用页表`cr3`的其他CPU刷新TLB，修改的不是当前页表时也可以使用
+pub fn flush_address_space(cr3: u64, target: FlushTarget) {
+    shootdown(cr3 & CR3_ADDR_MASK, target)
+}
+
+fn shootdown(address_space: u64, target: FlushTarget) {
+    let online = ONLINE_CPUS.load(Ordering::SeqCst);
+    let mut running = online;
+    if address_space != KERNEL_SPACE {
+        for cpu in 0..MAX_CPUS {
+            if CPUS[cpu].address_space.load(Ordering::SeqCst) != address_space {
+                running &= !(1 << cpu);
+            }
+        }
+        // 其他CPU中该地址空间的缓存在下次切换到它时清除
+        invalidate_address_space(address_space, online & !running);
+    }
+    let targets = running & !(1 << cpu_id());
+    if targets == 0 {
+        return;
+    }
+
+    let pending = AtomicUsize::new(targets.count_ones() as usize);
+    let request = Request { address_space, target, pending: &pending };
+    for cpu in (0..MAX_CPUS).filter(|cpu| targets & (1 << cpu) != 0) {
+        push_request(cpu, request);
+        // 目前假定cpu_id与local APIC ID相同
+        unsafe { CONTROLLER.lock().send_ipi(IpiKind::Tlb as u8, cpu as u32) };
+    }
+    // 等待期间处理发给自己的请求，避免两个CPU互相等待
+    while pending.load(Ordering::SeqCst) != 0 {
+        handle_tlb_requests();
+        spin_loop_hint();
+    }
+}
+
+fn push_request(cpu: usize, request: Request) {
+    loop {
+        {
+            let mut queue = CPUS[cpu].queue.lock();
+            if let Some(slot) = queue.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(request);
+                return;
+            }
+        }
+        handle_tlb_requests();
+        spin_loop_hint();
+    }
+}
+
+/// 处理当前CPU队列中的所有请求，由`ipi_tlb`调用
+pub fn handle_tlb_requests() {
+    let requests = {
+        let mut queue = CPUS[cpu_id()].queue.lock();
+        let requests = *queue;
+        *queue = [None; QUEUE_LEN];
+        requests
+    };
+    let active = CR3::read().0.start_address().as_u64();
+    for request in requests.iter().filter_map(|request| *request) {
+        if request.address_space == KERNEL_SPACE || request.address_space == active {
+            unsafe { invalidate(request.address_space, request.target) };
+        }
+        unsafe { (*request.pending).fetch_sub(1, Ordering::SeqCst) };
+    }
+}
+
+unsafe fn invalidate(address_space: u64, target: FlushTarget) {
+    // 内核页面没有设置GLOBAL，启用PCID后需要清除所有PCID中的缓存
+    let kernel = address_space == KERNEL_SPACE && pcid_enabled();
+    match target {
+        FlushTarget::Pages { start, page_size, count } if count <= FLUSH_ALL_THRESHOLD => {
+            for i in 0..count {
+                let addr = VirtAddr::new(start + i * page_size);
+                if kernel {
+                    flush_kernel_page(addr);
+                } else {
+                    flush(addr);
+                }
+            }
+        }
+        _ if kernel => invpcid(InvpcidKind::AllExcludingGlobal, 0, VirtAddr::new(0)),
+        // 重新加载CR3只刷新当前PCID的缓存
+        _ => flush_all(),
+    }
+}
@@ -0,0 +1,129 @@
+//! 缺页处理
+//!
+//! 延迟映射的内存在第一次访问时分配清零的页面，写保护的共享页面在写入时复制，
+//! 访问栈底以下不远处的地址时向下扩展栈。
+
+use core::mem;
+
+use system::bits::flags::{PageFaultErrorCode, PageTableFlags};
+use system::ia_32e::VirtAddr;
+use system::ia_32e::paging::Page;
+use system::ia_32e::paging::stack::grow_start;
+use system::syscall::flag::SIGSEGV;
+
+use crate::memory::PAGE_TABLE;
+use crate::process::memory::{Memory, USER_END};
+use crate::process::process;
+use crate::process::process::Status;
+use crate::process::scheduler::switch;
+use crate::utils::loop_hlt;
+
+/// 栈的最大大小
+pub const MAX_STACK_SIZE: usize = 0x80_0000;
+/// 访问栈底以下该范围内的地址时扩展栈，超出范围视为非法访问
+const STACK_GROW_GAP: u64 = 0x1_0000;
+
+/// 尝试解决当前进程在`addr`处的缺页，无法解决时返回false
+pub fn resolve_fault(addr: VirtAddr, code: PageFaultErrorCode) -> bool {
+    if addr.as_u64() >= USER_END {
+        return false;
+    }
+    let list = process();
+    let lock = match list.current() {
+        Some(lock) => lock,
+        None => return false,
+    };
+    // 各内存区域有自己的锁，这里只需要读锁，系统调用持有进程的读锁时访问用户内存也能处理缺页。
+    // 持有写锁的代码只能访问已经通过`validate`检查的用户内存
+    let current = lock.read();
+
+    if let Some(region) = current.vmas.find(addr) {
+        return region.memory().with(|memory| resolve(memory, addr, code));
+    }
+    if let Some(ref heap) = current.heap {
+        if heap.with(|memory| memory.contains(addr)) {
+            return heap.with(|memory| resolve(memory, addr, code));
+        }
+    }
+    if let Some(ref stack) = current.stack {
+        let vmas = &current.vmas;
+        return stack.with(|memory| {
+            if !memory.contains(addr) && !grow_stack(memory, addr, |start, size| vmas.is_free(start, size)) {
+                return false;
+            }
+            resolve(memory, addr, code)
+        });
+    }
+    false
+}
+
+/// 在栈底以下不远处访问时向下扩展栈，`is_free`用于检查新的范围是否与其他映射重叠
+fn grow_stack<F>(memory: &mut Memory, addr: VirtAddr, is_free: F) -> bool where F: Fn(VirtAddr, usize) -> bool {
+    if !memory.is_lazy() {
+        return false;
+    }
+    let start = memory.start_address();
+    let new_start = match grow_start(start, memory.end_address(), addr, STACK_GROW_GAP, MAX_STACK_SIZE as u64) {
+        Some(new_start) => new_start,
+        None => return false,
+    };
+    if !is_free(new_start, (start - new_start) as usize) {
+        return false;
+    }
+    memory.grow_down(new_start);
+    true
+}
+
+/// 检查访问权限后分配页面或者处理写时复制
+fn resolve(memory: &Memory, addr: VirtAddr, code: PageFaultErrorCode) -> bool {
+    let flags = memory.flags();
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return false;
+    }
+    if code.contains(PageFaultErrorCode::USER_MODE) && !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+        return false;
+    }
+    if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) && flags.contains(PageTableFlags::NO_EXECUTE) {
+        return false;
+    }
+    let write = code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+    if write && !flags.contains(PageTableFlags::WRITABLE) {
+        return false;
+    }
+    if !code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return memory.is_lazy() && memory.populate(addr);
+    }
+    // 页面存在但不可写，说明是写时复制的页面
+    let mapped = PAGE_TABLE.lock().page_flags(Page::include_address(addr));
+    match mapped {
+        Some(mapped) if write && !mapped.contains(PageTableFlags::WRITABLE) => memory.copy_on_write(addr),
+        _ => false,
+    }
+}
+
+/// 以`SIGSEGV`结束当前进程并切换到其他进程，不会返回
+pub fn kill_current() -> ! {
+    {
+        let list = process();
+        if let Some(lock) = list.current() {
+            let mut current = lock.write();
+            current.status = Status::Exited(SIGSEGV);
+            // 在释放进程锁之后再解除映射
+            let vmas = mem::take(&mut current.vmas);
+            let heap = current.heap.take();
+            let stack = current.stack.take();
+            let files = mem::take(&mut current.files);
+            drop(current);
+            drop(list);
+            drop(vmas);
+            drop(heap);
+            drop(stack);
+            drop(files);
+        }
+    }
+    loop {
+        if !switch() {
+            loop_hlt();
+        }
+    }
+}
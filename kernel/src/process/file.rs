@@ -0,0 +1,164 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+use system::syscall::data::Stat;
+use system::syscall::result::{EBADF, EMFILE, Error, ESPIPE, Result};
+
+use crate::process::types::{FileDescriptor, MAX_FILES};
+
+/// 所有可以通过文件描述符访问的对象(控制台，管道，文件，套接字等)都需要实现该trait
+pub trait FileObject: Send + Sync {
+    /// 读取数据到`buf`中，返回读取的字节数
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+
+    /// 将`buf`中的数据写入，返回写入的字节数
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+
+    /// 移动读写位置，不支持随机访问的对象返回`ESPIPE`
+    fn seek(&self, _pos: usize, _whence: usize) -> Result<usize> {
+        Err(Error::new(ESPIPE))
+    }
+
+    /// 获取文件状态
+    fn fstat(&self, _stat: &mut Stat) -> Result<()> {
+        Ok(())
+    }
+
+    /// 最后一个引用该对象的文件描述被释放时调用
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// 打开的文件描述，fork和dup后的文件描述符共享同一个文件描述
+pub struct FileDescription {
+    pub object: Arc<dyn FileObject>,
+    /// open时传入的标志位
+    pub flags: usize,
+}
+
+impl FileDescription {
+    pub fn new(object: Arc<dyn FileObject>, flags: usize) -> Self {
+        Self {
+            object,
+            flags,
+        }
+    }
+}
+
+impl Drop for FileDescription {
+    fn drop(&mut self) {
+        if let Err(err) = self.object.close() {
+            println!("close file description failed: {}", err);
+        }
+    }
+}
+
+/// 进程文件表中的一项
+#[derive(Clone)]
+pub struct OpenFile {
+    pub description: Arc<RwLock<FileDescription>>,
+    /// 执行exec时关闭该文件描述符
+    pub cloexec: bool,
+}
+
+impl OpenFile {
+    pub fn new(description: FileDescription, cloexec: bool) -> Self {
+        Self {
+            description: Arc::new(RwLock::new(description)),
+            cloexec,
+        }
+    }
+}
+
+/// 进程的文件描述符表，fork时复制该表，表中的文件描述依然共享
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<OpenFile>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+        }
+    }
+
+    /// 使用最小的空闲文件描述符保存`file`
+    pub fn add(&mut self, file: OpenFile) -> Result<FileDescriptor> {
+        self.add_from(file, 0)
+    }
+
+    /// 使用不小于`min`的最小空闲文件描述符保存`file`
+    pub fn add_from(&mut self, file: OpenFile, min: usize) -> Result<FileDescriptor> {
+        let free = self.files.iter().enumerate().skip(min).find(|(_, f)| f.is_none()).map(|(i, _)| i);
+        let index = match free {
+            Some(index) => index,
+            None => core::cmp::max(self.files.len(), min),
+        };
+        self.insert(FileDescriptor::from(index), file)?;
+        Ok(FileDescriptor::from(index))
+    }
+
+    /// 在指定位置保存`file`，返回该位置原有的文件描述符
+    pub fn insert(&mut self, fd: FileDescriptor, file: OpenFile) -> Result<Option<OpenFile>> {
+        let index = fd.into();
+        if index >= MAX_FILES {
+            return Err(Error::new(EMFILE));
+        }
+        if index >= self.files.len() {
+            self.files.resize(index + 1, None);
+        }
+        Ok(self.files[index].replace(file))
+    }
+
+    pub fn get(&self, fd: FileDescriptor) -> Result<&OpenFile> {
+        self.files.get(fd.into()).and_then(|f| f.as_ref()).ok_or(Error::new(EBADF))
+    }
+
+    pub fn get_mut(&mut self, fd: FileDescriptor) -> Result<&mut OpenFile> {
+        self.files.get_mut(fd.into()).and_then(|f| f.as_mut()).ok_or(Error::new(EBADF))
+    }
+
+    pub fn remove(&mut self, fd: FileDescriptor) -> Result<OpenFile> {
+        let file = self.files.get_mut(fd.into()).and_then(|f| f.take()).ok_or(Error::new(EBADF))?;
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+        Ok(file)
+    }
+
+    /// 复制文件描述符到最小的空闲位置，新的文件描述符不会继承close-on-exec标志
+    pub fn dup(&mut self, fd: FileDescriptor) -> Result<FileDescriptor> {
+        let mut file = self.get(fd)?.clone();
+        file.cloexec = false;
+        self.add(file)
+    }
+
+    /// 复制文件描述符到`new`，返回`new`原有的文件描述符，由调用者负责释放
+    pub fn dup2(&mut self, old: FileDescriptor, new: FileDescriptor) -> Result<Option<OpenFile>> {
+        let mut file = self.get(old)?.clone();
+        if old == new {
+            return Ok(None);
+        }
+        file.cloexec = false;
+        self.insert(new, file)
+    }
+
+    /// 执行exec时关闭所有设置了close-on-exec标志的文件描述符
+    pub fn close_on_exec(&mut self) {
+        for file in self.files.iter_mut() {
+            if file.as_ref().map_or(false, |f| f.cloexec) {
+                *file = None;
+            }
+        }
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(FileDescriptor, &OpenFile)> + '_ {
+        self.files.iter().enumerate().filter_map(|(i, f)| f.as_ref().map(|f| (FileDescriptor::from(i), f)))
+    }
+}
@@ -1,20 +1,72 @@
+use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
+use core::alloc::Layout;
 use core::intrinsics;
 
-use bitflags::_core::alloc::Layout;
 use spin::Mutex;
 use system::bits::flags::PageTableFlags;
-use system::ia_32e::{PhysAddr, VirtAddr};
-use system::ia_32e::paging::{Frame, Page, Page4KB, PageRangeInclude};
-use system::ia_32e::paging::mapper::Mapper;
+use system::ia_32e::{align_up, PhysAddr, VirtAddr};
+use system::ia_32e::paging::{Frame, FrameAllocator, Page, Page1GB, Page2MB, Page4KB, PageRangeInclude, PageSize, UnusedFrame};
+use system::ia_32e::paging::frame_allocator::BuddyFrameAllocator;
+use system::ia_32e::paging::mapper::{MapAllSize, Mapper};
+use system::ia_32e::paging::result::TranslationResult;
 
-use crate::memory::{FRAME_ALLOCATOR, HEAP};
+use lazy_static::lazy_static;
+
+use crate::memory::{flush_range, FRAME_ALLOCATOR, GIGABYTE_PAGE, KernelPageTable};
+
+/// 用户堆的起始地址，由`brk`扩展
+pub const USER_HEAP_OFFSET: u64 = 0x0000_1000_0000_0000;
+/// 用户堆的最大大小
+pub const USER_HEAP_SIZE: u64 = 0x0000_0100_0000_0000;
+/// 由内核选择地址时`mmap`使用的区域
+pub const USER_MMAP_OFFSET: u64 = 0x0000_2000_0000_0000;
+/// `mmap`区域的大小
+pub const USER_MMAP_SIZE: u64 = 0x0000_1000_0000_0000;
+/// 用户栈区域的起始地址。所有进程目前共享一个页表，每个进程的栈位于按进程ID划分的槽中
+pub const USER_STACK_OFFSET: u64 = 0x0000_6000_0000_0000;
+/// 每个用户栈槽的大小，包括栈可以扩展到的最大大小和槽之间的间隙
+pub const USER_STACK_SLOT: u64 = 0x0000_0000_0100_0000;
+/// 创建进程时用户栈的大小，之后在缺页时向下扩展
+pub const USER_STACK_SIZE: usize = 0x1_0000;
+/// 用户空间的结束地址（不包含）
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+lazy_static! {
+    /// 被多个映射共享的物理帧（写时复制），值为额外的引用数
+    static ref SHARED_FRAMES: Mutex<BTreeMap<PhysAddr, usize>> = Mutex::new(BTreeMap::new());
+}
+
+/// 增加物理帧的引用，将同一个帧以只读方式映射到另一处（例如fork）之前调用
+pub fn share_frame(frame: Frame) {
+    *SHARED_FRAMES.lock().entry(frame.start_address()).or_insert(0) += 1;
+}
+
+/// 物理帧是否被多个映射共享
+fn is_shared(frame: Frame) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame.start_address())
+}
+
+/// 释放对物理帧的引用，最后一个引用被释放时归还给帧分配器
+pub fn release_frame(allocator: &mut BuddyFrameAllocator, frame: Frame) {
+    let mut shared = SHARED_FRAMES.lock();
+    if let Some(count) = shared.get_mut(&frame.start_address()) {
+        *count -= 1;
+        if *count == 0 {
+            shared.remove(&frame.start_address());
+        }
+        return;
+    }
+    allocator.dealloc(unsafe { UnusedFrame::new(frame) });
+}
 
 #[derive(Debug)]
 pub struct Memory {
     start: VirtAddr,
     size: usize,
-    flags: PageTableFlags
+    flags: PageTableFlags,
+    /// 延迟映射，页面在第一次访问时才分配
+    lazy: bool,
 }
 
 impl Memory {
@@ -23,11 +75,59 @@ impl Memory {
             start,
             size,
             flags,
+            lazy: false,
         };
         memory.map(clear);
         memory
     }
 
+    /// 创建延迟映射的内存，不会预先映射页面，
+    /// 访问未映射的页面时由缺页处理程序分配清零的物理帧
+    pub fn new_lazy(start: VirtAddr, size: usize, flags: PageTableFlags) -> Self {
+        Memory {
+            start,
+            size,
+            flags,
+            lazy: true,
+        }
+    }
+
+    /// 将已有的物理帧依次映射到`start`处，用于共享内存对象。
+    /// 每个帧增加一次引用，解除映射时只减少引用，地址已被占用时撤销已经建立的映射并返回None
+    pub fn new_shared(start: VirtAddr, frames: &[Frame], flags: PageTableFlags) -> Option<Self> {
+        use crate::memory::PAGE_TABLE;
+        let mut table = PAGE_TABLE.lock();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        for (index, &frame) in frames.iter().enumerate() {
+            let addr = start + index * Page4KB::P_SIZE as usize;
+            match unsafe { table.map_to(Page::<Page4KB>::include_address(addr), frame, flags, allocator) } {
+                Ok(flush) => {
+                    flush.flush();
+                    share_frame(frame);
+                }
+                Err(_) => {
+                    unmap_range(&mut table, allocator, start, addr);
+                    return None;
+                }
+            }
+        }
+        Some(Memory {
+            start,
+            size: frames.len() * Page4KB::P_SIZE as usize,
+            flags,
+            lazy: false,
+        })
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end_address()
+    }
+
     pub fn start_address(&self) -> VirtAddr {
         self.start
     }
@@ -36,6 +136,11 @@ impl Memory {
         self.size
     }
 
+    /// 返回结束地址（不包含）
+    pub fn end_address(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
     pub fn flags(&self) -> PageTableFlags {
         self.flags
     }
@@ -47,39 +152,180 @@ impl Memory {
     }
 
     fn map(&self, clear: bool) {
-        use crate::memory::RECU_PAGE_TABLE;
-        let mut table = RECU_PAGE_TABLE.lock();
-        for page in self.pages() {
-            //todo: it should work
-            let frame = HEAP.lock().alloc(Layout::from_size_align(page.size() as usize, page.size() as usize).expect("no aligned")).expect("allocate memory failed");
-            let frame = Frame::include_address(PhysAddr::new(frame.as_ptr() as u64));
-            unsafe {
-                table.map_to(page, frame, self.flags, FRAME_ALLOCATOR.lock().as_mut().expect("frame allocator not init")).expect("map memory err").flush();
+        use crate::memory::PAGE_TABLE;
+        let mut table = PAGE_TABLE.lock();
+        // 清零时需要先以可写方式映射，清零后再恢复原有的flags
+        let flags = if clear { self.flags | PageTableFlags::WRITABLE } else { self.flags };
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        let mut offset = 0;
+        while offset < self.size {
+            let addr = self.start + offset;
+            let remain = (self.size - offset) as u64;
+            // 地址和剩余大小允许时优先使用大页，没有连续的物理内存时退回到4KB页面
+            let mut mapped = None;
+            if *GIGABYTE_PAGE && fits(addr, remain, Page1GB::P_SIZE) {
+                mapped = map_huge::<Page1GB>(&mut table, allocator, addr, flags);
             }
+            if mapped.is_none() && fits(addr, remain, Page2MB::P_SIZE) {
+                mapped = map_huge::<Page2MB>(&mut table, allocator, addr, flags);
+            }
+            offset += match mapped {
+                Some(size) => size as usize,
+                None => {
+                    let frame = allocator.alloc().expect("out of physical memory");
+                    unsafe {
+                        table.map_to(Page::<Page4KB>::include_address(addr), frame.frame(), flags, allocator).expect("map memory err").flush();
+                    }
+                    Page4KB::P_SIZE as usize
+                }
+            };
         }
         if clear {
-            assert!(self.flags.contains(PageTableFlags::WRITABLE));
             unsafe {
-                intrinsics::write_bytes(self.start_address().as_mut_ptr::<*mut u8>(), 0, self.size);
+                intrinsics::write_bytes(self.start_address().as_mut_ptr::<u8>(), 0, self.size);
+            }
+            if flags != self.flags {
+                update_range(&mut table, self.start, self.end_address(), self.flags);
             }
         }
     }
 
     fn unmap(&self) {
-        use crate::memory::RECU_PAGE_TABLE;
-        let mut table = RECU_PAGE_TABLE.lock();
-        for page in self.pages() {
+        use crate::memory::PAGE_TABLE;
+        if self.size == 0 {
+            return;
+        }
+        let mut table = PAGE_TABLE.lock();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        unmap_range(&mut table, allocator, self.start, self.end_address());
+    }
+
+    /// 使用新的flags重新映射所有页面
+    pub fn remap(&mut self, new_flags: PageTableFlags) {
+        use crate::memory::PAGE_TABLE;
+        let mut table = PAGE_TABLE.lock();
+        update_range(&mut table, self.start, self.end_address(), new_flags);
+        self.flags = new_flags;
+    }
+
+    /// 为包含`addr`的页面分配清零的物理帧，页面已经映射或者内存不足时返回false
+    pub fn populate(&self, addr: VirtAddr) -> bool {
+        use crate::memory::PAGE_TABLE;
+        let page: Page<Page4KB> = Page::include_address(addr);
+        let mut table = PAGE_TABLE.lock();
+        if table.translate_page(page).is_ok() {
+            return false;
+        }
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        let frame = match allocator.alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        unsafe {
+            // 地址落在不属于该进程的大页中时无法映射，交给调用者按非法访问处理
+            match table.map_to(page, frame.frame(), self.flags | PageTableFlags::WRITABLE, allocator) {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    allocator.dealloc(frame);
+                    return false;
+                }
+            }
+            intrinsics::write_bytes(page.start_address().as_mut_ptr::<u8>(), 0, Page4KB::P_SIZE as usize);
+            if !self.flags.contains(PageTableFlags::WRITABLE) {
+                table.update_flags(page, self.flags).expect("update page flags failed").flush();
+            }
+        }
+        true
+    }
+
+    /// 处理对写保护页面的写入，共享的物理帧会被复制，独占的物理帧直接恢复可写
+    pub fn copy_on_write(&self, addr: VirtAddr) -> bool {
+        use crate::memory::PAGE_TABLE;
+        let page: Page<Page4KB> = Page::include_address(addr);
+        // 在获取页表锁之前分配，堆扩展时需要页表
+        let mut buffer = vec![0_u8; Page4KB::P_SIZE as usize];
+        let mut table = PAGE_TABLE.lock();
+        let frame = match table.translate_page(page) {
+            Ok(frame) => frame,
+            Err(_) => return false,
+        };
+        if !is_shared(frame) {
+            unsafe {
+                table.update_flags(page, self.flags).expect("update page flags failed").flush();
+            }
+            return true;
+        }
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
+        let copy = match allocator.alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        // 所有进程共享同一个页表，旧的帧仍然映射在原地址上，先将内容保存下来
+        unsafe {
+            intrinsics::copy_nonoverlapping(page.start_address().as_ptr::<u8>(), buffer.as_mut_ptr(), buffer.len());
             table.unmap(page).expect("unmap page failed").1.flush();
+            table.map_to(page, copy.frame(), self.flags, allocator).expect("map memory err").flush();
+            intrinsics::copy_nonoverlapping(buffer.as_ptr(), page.start_address().as_mut_ptr::<u8>(), buffer.len());
+        }
+        release_frame(allocator, frame);
+        true
+    }
+
+    /// 向下扩展内存使其从`new_start`开始（用于栈），只能用于延迟映射的内存
+    pub fn grow_down(&mut self, new_start: VirtAddr) {
+        assert!(self.lazy, "only lazy memory can grow down");
+        assert_eq!(new_start.as_u64() % Page4KB::P_SIZE, 0, "stack address not page aligned");
+        if new_start < self.start {
+            self.size += (self.start - new_start) as usize;
+            self.start = new_start;
         }
     }
 
+    /// 在`at`(相对于起始地址的偏移，必须按页对齐)处将内存一分为二，
+    /// 返回`[start + at, end)`部分，跨越拆分位置的大页会被拆分为较小的页面
+    pub fn split_off(&mut self, at: usize) -> Memory {
+        use crate::memory::PAGE_TABLE;
+        assert_eq!(at % Page4KB::P_SIZE as usize, 0, "split address not page aligned");
+        assert!(at <= self.size, "split address out of range");
+        if !self.lazy {
+            let mut table = PAGE_TABLE.lock();
+            let mut allocator = FRAME_ALLOCATOR.lock();
+            let allocator = allocator.as_mut().expect("frame allocator not init");
+            split_huge_pages(&mut table, allocator, self.start + at);
+        }
+        let tail = Memory {
+            start: self.start + at,
+            size: self.size - at,
+            flags: self.flags,
+            lazy: self.lazy,
+        };
+        self.size = at;
+        tail
+    }
+
     pub fn resize(&mut self, new_size: usize, clear: bool) {
-        use crate::memory::RECU_PAGE_TABLE;
+        use crate::memory::PAGE_TABLE;
         use system::ia_32e::paging::result::TranslateError;
 
-        let mut table = RECU_PAGE_TABLE.lock();
+        let mut table = PAGE_TABLE.lock();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let allocator = allocator.as_mut().expect("frame allocator not init");
 
-        if new_size > self.size {
+        if new_size > self.size && self.lazy {
+            // 新增的页面在访问时才分配，只需清零原结束地址所在页的剩余部分
+            let old_end = VirtAddr::new(self.start.as_u64() + self.size as u64);
+            let page: Page<Page4KB> = Page::include_address(old_end);
+            if clear && old_end.as_u64() % Page4KB::P_SIZE != 0 && table.translate_page(page).is_ok() {
+                let tail = (page.start_address().as_u64() + Page4KB::P_SIZE - old_end.as_u64()) as usize;
+                unsafe {
+                    intrinsics::write_bytes(old_end.as_mut_ptr::<u8>(), 0, core::cmp::min(tail, new_size - self.size));
+                }
+            }
+        } else if new_size > self.size {
             let start_page: Page<Page4KB> = Page::include_address(VirtAddr::new(self.start.as_u64() + self.size as u64));
             let end_page = Page::include_address(VirtAddr::new(self.start.as_u64() + new_size as u64 - 1));
             for page in Page::range_include(start_page, end_page) {
@@ -87,11 +333,9 @@ impl Memory {
                     Err(err) => {
                         match err {
                             TranslateError::PageNotMapped => {
+                                let frame = allocator.alloc().expect("out of physical memory");
                                 unsafe {
-                                    //todo: allocator frame
-                                    let frame = HEAP.lock().alloc(Layout::from_size_align(page.size() as usize, page.size() as usize).expect("no aligned")).expect("allocate memory failed");
-                                    let frame = Frame::include_address(PhysAddr::new(frame.as_ptr() as u64));
-                                    table.map_to(page, frame, self.flags, FRAME_ALLOCATOR.lock().as_mut().expect("frame allocator not init")).expect("map page mapped failed").flush();
+                                    table.map_to(page, frame.frame(), self.flags, allocator).expect("map page mapped failed").flush();
                                 }
                             }
                             TranslateError::ParentEntryHugePage => { panic!(format!("address {:#?} already mapped", page.start_address()).as_str()) },
@@ -109,15 +353,132 @@ impl Memory {
                 }
             }
         } else if new_size < self.size {
-            let start_page: Page<Page4KB> = Page::include_address(VirtAddr::new(self.start.as_u64() + new_size as u64));
-            let end_page = Page::include_address(VirtAddr::new(self.start.as_u64() + self.size as u64 - 1));
-            for page in Page::range_include(start_page, end_page) {
-                if table.translate_page(page.clone()).is_ok() {
-                    table.unmap(page).expect("unmap page error").1.flush();
+            // 保留新结束地址所在的页
+            let new_end = VirtAddr::new(align_up(self.start.as_u64() + new_size as u64, Page4KB::P_SIZE));
+            split_huge_pages(&mut table, allocator, new_end);
+            unmap_range(&mut table, allocator, new_end, self.end_address());
+        }
+        self.size = new_size;
+    }
+}
+
+/// 大小为`size`的大页能否映射在`addr`处
+fn fits(addr: VirtAddr, remain: u64, size: u64) -> bool {
+    addr.as_u64() % size == 0 && remain >= size
+}
+
+/// 分配连续的物理内存并映射为一个大页，返回映射的大小
+fn map_huge<S: PageSize>(table: &mut KernelPageTable, allocator: &mut BuddyFrameAllocator, addr: VirtAddr, flags: PageTableFlags) -> Option<u64>
+    where KernelPageTable: Mapper<S> {
+    let layout = Layout::from_size_align(S::P_SIZE as usize, S::P_SIZE as usize).ok()?;
+    let frame = allocator.alloc_size(layout)?;
+    let count = (S::P_SIZE / Page4KB::P_SIZE) as usize;
+    let page: Page<S> = Page::include_address(addr);
+    match unsafe { table.map_to(page, Frame::include_address(frame.start_address()), flags, allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Some(S::P_SIZE)
+        }
+        Err(_) => {
+            allocator.dealloc_size(frame.frame(), count);
+            None
+        }
+    }
+}
+
+/// 拆分包含`addr`的大页，直到`addr`位于页面边界上
+fn split_huge_pages(table: &mut KernelPageTable, allocator: &mut BuddyFrameAllocator, addr: VirtAddr) {
+    loop {
+        let aligned = match table.translate(addr) {
+            TranslationResult::Frame1GB { .. } => addr.as_u64() % Page1GB::P_SIZE == 0,
+            TranslationResult::Frame2MB { .. } => addr.as_u64() % Page2MB::P_SIZE == 0,
+            _ => true,
+        };
+        if aligned {
+            return;
+        }
+        table.split_huge_page(addr, allocator).expect("split huge page failed");
+    }
+}
+
+/// 一批解除映射的页面中最多推迟释放的物理帧数
+const UNMAP_BATCH: usize = 32;
+
+/// 解除`[start, end)`中所有已映射页面的映射并释放物理帧，区域边界上不能有跨越边界的大页。
+/// 其他CPU刷新TLB之前物理帧不能被重新分配，页面按批解除映射，整批刷新后再释放
+fn unmap_range(table: &mut KernelPageTable, allocator: &mut BuddyFrameAllocator, start: VirtAddr, end: VirtAddr) {
+    let mut freed: [Option<(Frame, usize)>; UNMAP_BATCH] = [None; UNMAP_BATCH];
+    let mut count = 0;
+    let mut batch_start = start;
+    let mut addr = start;
+    while addr < end {
+        // 延迟映射的内存中可能有从未访问过的页面
+        let (size, frame) = match table.translate(addr) {
+            TranslationResult::Frame1GB { frame, .. } => {
+                table.unmap(Page::<Page1GB>::include_address(addr)).expect("unmap page failed").1.ignore();
+                (Page1GB::P_SIZE, Some((Frame::include_address(frame.start_address()), (Page1GB::P_SIZE / Page4KB::P_SIZE) as usize)))
+            }
+            TranslationResult::Frame2MB { frame, .. } => {
+                table.unmap(Page::<Page2MB>::include_address(addr)).expect("unmap page failed").1.ignore();
+                (Page2MB::P_SIZE, Some((Frame::include_address(frame.start_address()), (Page2MB::P_SIZE / Page4KB::P_SIZE) as usize)))
+            }
+            TranslationResult::Frame4KB { .. } => {
+                let (frame, flush) = table.unmap(Page::<Page4KB>::include_address(addr)).expect("unmap page failed");
+                flush.ignore();
+                (Page4KB::P_SIZE, Some((frame, 1)))
+            }
+            _ => (Page4KB::P_SIZE, None),
+        };
+        if let Some(frame) = frame {
+            if count == 0 {
+                batch_start = addr;
+            }
+            freed[count] = Some(frame);
+            count += 1;
+        }
+        addr += size;
+        if count == UNMAP_BATCH || (count > 0 && addr >= end) {
+            flush_range(batch_start, addr);
+            for entry in freed[..count].iter_mut() {
+                match entry.take() {
+                    Some((frame, 1)) => release_frame(allocator, frame),
+                    Some((frame, frames)) => allocator.dealloc_size(frame, frames),
+                    None => {}
                 }
             }
+            count = 0;
         }
-        self.size = new_size;
+    }
+}
+
+/// 修改`[start, end)`中所有已映射页面的flags，全部修改后统一刷新TLB
+fn update_range(table: &mut KernelPageTable, start: VirtAddr, end: VirtAddr, flags: PageTableFlags) {
+    let mut addr = start;
+    let mut updated = false;
+    while addr < end {
+        addr += unsafe {
+            match table.translate(addr) {
+                TranslationResult::Frame1GB { .. } => {
+                    table.update_flags(Page::<Page1GB>::include_address(addr), flags).expect("update page flags failed").ignore();
+                    updated = true;
+                    Page1GB::P_SIZE
+                }
+                TranslationResult::Frame2MB { .. } => {
+                    table.update_flags(Page::<Page2MB>::include_address(addr), flags).expect("update page flags failed").ignore();
+                    updated = true;
+                    Page2MB::P_SIZE
+                }
+                TranslationResult::Frame4KB { .. } => {
+                    table.update_flags(Page::<Page4KB>::include_address(addr), flags).expect("update page flags failed").ignore();
+                    updated = true;
+                    Page4KB::P_SIZE
+                }
+                _ => Page4KB::P_SIZE,
+            }
+        };
+    }
+    if updated {
+        flush_range(start, end);
     }
 }
 
@@ -148,6 +509,12 @@ impl SharedMemory {
         }
     }
 
+    /// 在`at`处拆分内存，返回后半部分的所有权
+    pub fn split_off(&self, at: usize) -> SharedMemory {
+        let tail = self.with(|memory| memory.split_off(at));
+        SharedMemory::Owned(Arc::new(Mutex::new(tail)))
+    }
+
     pub fn borrow(&self) -> SharedMemory {
         match *self {
             SharedMemory::Owned(ref memory_lock) => SharedMemory::Borrowed(Arc::downgrade(memory_lock)),
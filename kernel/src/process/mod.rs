@@ -5,19 +5,30 @@ use core::mem;
 use core::sync::atomic::AtomicUsize;
 
 use bitflags::_core::sync::atomic::Ordering;
-use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use spin::{Mutex, Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use system::bits::flags::PageTableFlags;
 use system::ia_32e::cpu::control::CR3;
+use system::ia_32e::VirtAddr;
 use system::result::{Error, ProcessErrorKind, Result};
+use system::syscall::flag::O_RDWR;
 
-use crate::memory::alloc_memory;
-use crate::process::process::{Process, Status};
+use crate::devices::console::console_file;
+use crate::memory::{alloc_memory, release_pcid};
+use crate::process::fault::MAX_STACK_SIZE;
+use crate::process::memory::{Memory, SharedMemory, USER_END, USER_STACK_OFFSET, USER_STACK_SIZE, USER_STACK_SLOT};
+use crate::process::process::{Process, ROOT_UID, Status, USER_UID};
 use crate::process::types::{AtomicProcessId, MAX_PROCESS, ProcessId};
+use crate::utils::kaslr_random;
 
 pub mod registers;
 pub mod process;
 pub mod types;
 pub mod scheduler;
 pub mod memory;
+pub mod file;
+pub mod vma;
+pub mod fault;
+pub mod shm;
 
 
 /// A unique number that identifies the current CPU - used for scheduling
@@ -30,6 +41,11 @@ pub fn cpu_id() -> usize {
     CPU_ID.load(Ordering::Relaxed)
 }
 
+/// 启用地址随机化时内核栈顶随机下移的最大字节数
+const STACK_RANDOM_RANGE: u64 = 0x1000;
+/// 启用地址随机化时用户栈顶在栈槽中随机下移的最大字节数，栈槽中仍然保留栈的最大大小
+const USER_STACK_RANDOM_RANGE: u64 = USER_STACK_SLOT - MAX_STACK_SIZE as u64 - 0x10_0000;
+
 pub static CURRENT_PROCESS: AtomicProcessId = AtomicProcessId::default();
 static CONTEXTS: Once<RwLock<ProcessList>> = Once::new();
 
@@ -83,14 +99,37 @@ impl ProcessList {
         Ok(self.list.get(&id).expect("Failed to insert new context. ID is out of bounds."))
     }
 
+    /// 创建运行`func`的无特权进程
     pub fn spawn(&mut self, func: fn()) -> Result<&Arc<RwLock<Process>>> {
-        let r_lock = self.new_process()?;
+        self.spawn_as(func, USER_UID)
+    }
+
+    /// 创建运行`func`的特权进程，用于内核启动的驱动程序
+    pub fn spawn_privileged(&mut self, func: fn()) -> Result<&Arc<RwLock<Process>>> {
+        self.spawn_as(func, ROOT_UID)
+    }
+
+    fn spawn_as(&mut self, func: fn(), uid: u32) -> Result<&Arc<RwLock<Process>>> {
+        // the new process inherits the file table like fork, but not the user id
+        let files = self.current().map(|cur| cur.read().files.clone()).unwrap_or_default();
+        let id = self.new_process()?.read().id;
+        let stack = match user_stack(id) {
+            Some(stack) => stack,
+            None => {
+                self.remove(id);
+                return Err(Error::new_process(ProcessErrorKind::CrateNewProcessFailed, Some(format!("create new process failed: no user stack for pid:[{:?}]", id))));
+            }
+        };
+        let r_lock = self.get(id).expect("new process not found");
         let mut pro = r_lock.write();
+        pro.files = files;
+        pro.uid = uid;
+        pro.stack = Some(stack);
         let fx = unsafe { alloc_memory(512).expect("allocate memory failed") };
         let mut stack = vec![0_u8; 65536].into_boxed_slice();
-        let offset = stack.len() - mem::size_of::<usize>();
+        // 栈顶在一定范围内随机下移，保持16字节对齐
+        let offset = stack.len() - mem::size_of::<usize>() - kaslr_random(STACK_RANDOM_RANGE / 16) as usize * 16;
         unsafe {
-            let offset = stack.len() - mem::size_of::<usize>();
             let func_ptr = stack.as_mut_ptr().add(offset);
             *(func_ptr as *mut usize) = func as usize;
         }
@@ -105,11 +144,31 @@ impl ProcessList {
         Ok(r_lock)
     }
 
+    /// 移除进程，页表不再被其他进程使用时回收它的PCID
     pub fn remove(&mut self, id: ProcessId) -> Option<Arc<RwLock<Process>>> {
-        self.list.remove(&id)
+        let process = self.list.remove(&id)?;
+        let table = |process: &RwLock<Process>| process.read().register.get_page_table() as u64 & !CR3::PCID_MASK;
+        let cr3 = table(&*process);
+        if !self.list.values().any(|other| table(&**other) == cr3) {
+            release_pcid(cr3);
+        }
+        Some(process)
     }
 }
 
+/// 在进程`id`的栈槽中创建延迟映射的用户栈，栈槽超出用户栈区域时返回`None`。
+/// 启用地址随机化时栈顶从栈槽顶部随机下移若干页
+fn user_stack(id: ProcessId) -> Option<SharedMemory> {
+    let slot_top = (id.into() as u64).checked_add(1)?.checked_mul(USER_STACK_SLOT)?.checked_add(USER_STACK_OFFSET)?;
+    if slot_top > USER_END {
+        return None;
+    }
+    let top = slot_top - kaslr_random(USER_STACK_RANDOM_RANGE / 0x1000) * 0x1000;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let start = VirtAddr::new(top - USER_STACK_SIZE as u64);
+    Some(SharedMemory::Owned(Arc::new(Mutex::new(Memory::new_lazy(start, USER_STACK_SIZE, flags)))))
+}
+
 /// Initialize contexts, called if needed
 fn init_contexts() -> RwLock<ProcessList> {
     RwLock::new(ProcessList::new())
@@ -122,6 +181,11 @@ pub fn process() -> RwLockReadGuard<'static, ProcessList> {
     CONTEXTS.call_once(init_contexts).read()
 }
 
+/// Get the global schemes list without blocking, `None` if it is not initialized or locked for writing
+pub fn try_process() -> Option<RwLockReadGuard<'static, ProcessList>> {
+    CONTEXTS.r#try().and_then(|list| list.try_read())
+}
+
 /// Get the global schemes list, mutable
 pub fn process_mut() -> RwLockWriteGuard<'static, ProcessList> {
     CONTEXTS.call_once(init_contexts).write()
@@ -136,6 +200,11 @@ pub fn init_process() {
     context.kfx = Some(fx);
     context.status = Status::Runnable;
     context.running = true;
+    context.uid = ROOT_UID;
+    // wire stdin, stdout and stderr to the console
+    for _ in 0..3 {
+        context.files.add(console_file(O_RDWR)).expect("could not open console");
+    }
     // context.cpu_id = Some(cpu_id());
     CURRENT_PROCESS.store(context.id, Ordering::SeqCst);
 }
@@ -1,10 +1,22 @@
 use alloc::boxed::Box;
+use core::ops::Range;
 use alloc::vec::Vec;
 
+use system::ia_32e::VirtAddr;
+
 use crate::memory::alloc_memory;
-use crate::process::memory::{Memory, SharedMemory};
+use crate::process::fault::MAX_STACK_SIZE;
+use crate::process::file::FileTable;
+use crate::process::memory::{Memory, SharedMemory, USER_HEAP_OFFSET, USER_HEAP_SIZE};
 use crate::process::registers::ProcessRegister;
+use crate::process::shm::ShmTable;
 use crate::process::types::ProcessId;
+use crate::process::vma::VmaTree;
+
+/// 有特权的用户ID，内核的初始进程使用
+pub const ROOT_UID: u32 = 0;
+/// 新建进程默认的无特权用户ID
+pub const USER_UID: u32 = 1000;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Status {
@@ -25,6 +37,8 @@ pub struct Process {
     pub status: Status,
     /// Current system call
     pub syscall: Option<(usize, usize, usize, usize, usize, usize)>,
+    /// Log every system call entry and exit to the serial console
+    pub trace: bool,
     /// Head buffer to use when system call buffers are not page aligned
     pub syscall_head: Box<[u8]>,
     /// Tail buffer to use when system call buffers are not page aligned
@@ -41,10 +55,20 @@ pub struct Process {
     pub heap: Option<SharedMemory>,
     /// User stack
     pub stack: Option<SharedMemory>,
+    /// Anonymous mappings created by mmap
+    pub vmas: VmaTree,
     /// Kernel FX - used to store SIMD and FPU registers on context switch
     pub kfx: Option<Box<[u8]>>,
     /// CPU ID, if locked
     pub cpu_id: Option<usize>,
+    /// File descriptor table
+    pub files: FileTable,
+    /// Shared memory objects opened by this process
+    pub shm: ShmTable,
+    /// User id, 0 is the privileged user
+    pub uid: u32,
+    /// I/O port ranges granted to this process, applied to the TSS I/O bitmap on switch
+    pub io_ports: Vec<Range<u32>>,
 
 }
 
@@ -57,6 +81,7 @@ impl Process {
             sigmask: [0; 2],
             status: Status::Blocked,
             syscall: None,
+            trace: false,
             syscall_head,
             syscall_tail,
             register: ProcessRegister::new(),
@@ -64,11 +89,34 @@ impl Process {
             image: Vec::new(),
             heap: None,
             stack: None,
+            vmas: VmaTree::new(),
             sigstack: None,
             kfx: None,
             running: false,
-            cpu_id: None
+            cpu_id: None,
+            files: FileTable::new(),
+            shm: ShmTable::new(),
+            uid: USER_UID,
+            io_ports: Vec::new(),
+        }
+    }
+
+    /// `[start, start + size)`是否与映像、堆、栈以及已有的映射都不重叠。
+    /// 堆和栈按照可以扩展到的最大范围计算
+    pub fn is_free(&self, start: VirtAddr, size: usize) -> bool {
+        let (start, end) = (start.as_u64(), start.as_u64() + size as u64);
+        let overlaps = |lower: u64, upper: u64| lower < end && start < upper;
+        let memory_overlaps = |memory: &Memory| overlaps(memory.start_address().as_u64(), memory.end_address().as_u64());
+        if overlaps(USER_HEAP_OFFSET, USER_HEAP_OFFSET + USER_HEAP_SIZE)
+            || self.image.iter().any(|memory| memory.with(|memory| memory_overlaps(memory)))
+            || self.sigstack.as_ref().map_or(false, memory_overlaps) {
+            return false;
         }
+        let stack_overlaps = self.stack.as_ref().map_or(false, |stack| stack.with(|memory| {
+            let top = memory.end_address().as_u64();
+            overlaps(top.saturating_sub(MAX_STACK_SIZE as u64), top)
+        }));
+        !stack_overlaps && self.vmas.is_free(VirtAddr::new(start), size)
     }
 
     /// Block the context, and return true if it was runnable before being blocked
@@ -80,4 +128,14 @@ impl Process {
             false
         }
     }
+
+    /// Unblock the context, and return true if it was blocked before being unblocked
+    pub fn unblock(&mut self) -> bool {
+        if self.status == Status::Blocked {
+            self.status = Status::Runnable;
+            true
+        } else {
+            false
+        }
+    }
 }
@@ -40,7 +40,7 @@ impl ProcessRegister {
         }
     }
     /// get context page table
-    pub fn get_page_table(&mut self) -> usize { self.cr3 }
+    pub fn get_page_table(&self) -> usize { self.cr3 }
     /// set context fx register
     pub fn set_fx(&mut self, address: usize) {
         self.fx = address;
@@ -81,18 +81,13 @@ impl ProcessRegister {
         value
     }
 
-    /// switch to next context
+    /// switch to next context, the page table must be switched by `switch_address_space` before
     #[inline(never)]
     #[naked]
     #[cold]
     pub unsafe fn switch_to(&mut self, next: &mut ProcessRegister) {
         // save fx register
         llvm_asm!("fxsave [$0]"         :               : "r"(self.fx)      : "memory" :"intel", "volatile");
-        // switch cr3
-        llvm_asm!("mov $0, cr3"    : "=r"(self.cr3)     :                   : "memory" : "intel", "volatile");
-        if self.cr3 != next.cr3 {
-            llvm_asm!("mov cr3,$0" :                    :"r"(next.cr3)      : "memory" : "intel", "volatile");
-        }
         // switch rflags
         llvm_asm!("pushfq; pop $0" :"=r"(self.rflags)   :                   : "memory" : "intel", "volatile");
         llvm_asm!("push $0; popfq" :                    :"r"(next.rflags)   : "memory" : "intel", "volatile");
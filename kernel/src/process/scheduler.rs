@@ -3,13 +3,16 @@ use alloc::sync::Arc;
 
 use bitflags::_core::sync::atomic::{AtomicBool, Ordering};
 use spin::{Mutex, RwLock};
-use system::ia_32e::instructions::interrupt::{disable_interrupt, enable_interrupt, system_pause};
+use system::ia_32e::instructions::interrupt::{disable_interrupt, enable_interrupt, system_pause, without_interrupts};
+use system::ia_32e::instructions::register::read_cr3;
 
 use lazy_static::lazy_static;
 
-use crate::descriptor::TICKS;
-use crate::process::{CURRENT_PROCESS, process_mut};
+use crate::descriptor::{switch_io_ports, TICKS};
+use crate::memory::{set_active_address_space, switch_address_space};
+use crate::process::{CURRENT_PROCESS, process_mut, try_process};
 use crate::process::process::{Process, Status};
+use crate::process::registers::ProcessRegister;
 use crate::process::types::ProcessId;
 
 lazy_static! {
@@ -63,6 +66,8 @@ impl Scheduler {
         process.running = false;
         let id = CURRENT_PROCESS.swap(next_process_id, Ordering::SeqCst);
         self.queue.push_back(id);
+        switch_io_ports(&process.io_ports, &next_process.io_ports);
+        switch_page_table(&mut process.register, &mut next_process.register);
         unsafe {
             process.register.switch_to(&mut next_process.register);
         }
@@ -70,6 +75,16 @@ impl Scheduler {
     }
 }
 
+/// 除当前进程之外是否还有可以运行的进程，在中断中调用，进程表被占用时保守地返回true
+pub fn has_other_runnable() -> bool {
+    let list = match try_process() {
+        Some(list) => list,
+        None => return true,
+    };
+    let result = list.iter().any(|(_, proc)| proc.try_read().map_or(true, |proc| !proc.running && proc.status == Status::Runnable));
+    result
+}
+
 // use too many lock
 pub fn switch() -> bool {
     // lock here
@@ -131,6 +146,8 @@ pub fn switch() -> bool {
     } else {
         let mut next = next_process.unwrap().write();
         let mut current = current_process.write();
+        switch_io_ports(&current.io_ports, &next.io_ports);
+        switch_page_table(&mut current.register, &mut next.register);
         unsafe {
             current.register.switch_to(&mut next.register)
         }
@@ -139,3 +156,13 @@ pub fn switch() -> bool {
     TICKS.store(0, Ordering::SeqCst);
     res
 }
+
+/// 在`switch_to`之前调用，内核映射在所有页表中相同，可以在切换上下文之前切换页表。
+/// 先更新当前CPU的地址空间，之后发起的TLB刷新请求会发给当前CPU；
+/// 页表的PCID缓存没有过期时切换页表不会清除TLB
+fn switch_page_table(current: &mut ProcessRegister, next: &mut ProcessRegister) {
+    let cr3 = next.get_page_table() as u64;
+    set_active_address_space(cr3);
+    current.set_page_table(unsafe { read_cr3() } as usize);
+    next.set_page_table(without_interrupts(|| unsafe { switch_address_space(cr3) }) as usize);
+}
@@ -0,0 +1,180 @@
+//! 命名的共享内存对象
+//!
+//! 对象由物理帧组成，创建和扩大时分配清零的帧。对象本身和每个映射各持有帧的一个引用
+//! （见`memory::share_frame`），对象被释放或者缩小后，仍被映射的帧在最后一个映射解除时才归还。
+//! 全局表只保存对象的弱引用，所有句柄关闭并且所有映射解除后内存即被释放。
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ptr;
+
+use spin::Mutex;
+use system::bits::flags::PageTableFlags;
+use system::ia_32e::paging::{Frame, FrameAllocator, Page4KB, PageSize};
+use system::ia_32e::VirtAddr;
+use system::syscall::flag::{O_CREAT, O_EXCL};
+use system::syscall::result::{EBADF, EEXIST, EINVAL, Error, ENOENT, ENOMEM, Result};
+
+use lazy_static::lazy_static;
+
+use crate::memory::{FRAME_ALLOCATOR, phys_to_virt};
+use crate::process::memory::{Memory, release_frame};
+
+/// 名称的最大长度
+const MAX_NAME_LEN: usize = 255;
+/// 对象的最大大小
+const MAX_SIZE: usize = 0x1000_0000;
+
+lazy_static! {
+    static ref OBJECTS: Mutex<BTreeMap<String, Weak<SharedObject>>> = Mutex::new(BTreeMap::new());
+}
+
+pub struct SharedObject {
+    name: String,
+    frames: Mutex<Vec<Frame>>,
+}
+
+impl SharedObject {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 对象的大小，按页对齐
+    pub fn size(&self) -> usize {
+        self.frames.lock().len() * Page4KB::P_SIZE as usize
+    }
+
+    /// 调整对象的大小，新增的部分为0，已有的映射不受影响
+    pub fn resize(&self, size: usize) -> Result<()> {
+        let count = pages(size)?;
+        let mut frames = self.frames.lock();
+        if count > frames.len() {
+            let new = alloc_zeroed(count - frames.len())?;
+            frames.extend(new);
+        } else {
+            let tail = frames.split_off(count);
+            release_frames(&tail);
+        }
+        Ok(())
+    }
+
+    /// 将对象从头开始的`size`字节映射到`start`处，地址已被占用时返回`EEXIST`
+    pub fn map(&self, start: VirtAddr, size: usize, flags: PageTableFlags) -> Result<Memory> {
+        let count = pages(size)?;
+        let frames = self.frames.lock();
+        if count == 0 || count > frames.len() {
+            return Err(Error::new(EINVAL));
+        }
+        Memory::new_shared(start, &frames[..count], flags).ok_or(Error::new(EEXIST))
+    }
+}
+
+impl Drop for SharedObject {
+    fn drop(&mut self) {
+        release_frames(&self.frames.lock());
+        let mut objects = OBJECTS.lock();
+        // 同名的新对象可能已经替换了该项
+        if objects.get(&self.name).map_or(false, |object| object.upgrade().is_none()) {
+            objects.remove(&self.name);
+        }
+    }
+}
+
+/// `size`字节需要的页数，超过`MAX_SIZE`时返回`EINVAL`
+fn pages(size: usize) -> Result<usize> {
+    if size > MAX_SIZE {
+        return Err(Error::new(EINVAL));
+    }
+    Ok((size + Page4KB::P_SIZE as usize - 1) / Page4KB::P_SIZE as usize)
+}
+
+/// 分配`count`个清零的物理帧，空闲的帧不够或者内存不足时释放已分配的帧并返回`ENOMEM`
+fn alloc_zeroed(count: usize) -> Result<Vec<Frame>> {
+    // 先检查空闲的帧，避免在内核堆上为无法满足的请求分配数组
+    let available = FRAME_ALLOCATOR.lock().as_ref().expect("frame allocator not init").free_frames();
+    if count > available {
+        return Err(Error::new(ENOMEM));
+    }
+    let mut frames = Vec::with_capacity(count);
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    for _ in 0..count {
+        match allocator.alloc() {
+            Some(frame) => frames.push(frame.frame()),
+            None => {
+                for &frame in frames.iter() {
+                    release_frame(allocator, frame);
+                }
+                return Err(Error::new(ENOMEM));
+            }
+        }
+    }
+    drop(allocator);
+    for frame in frames.iter() {
+        unsafe { ptr::write_bytes(phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 0, Page4KB::P_SIZE as usize) };
+    }
+    Ok(frames)
+}
+
+fn release_frames(frames: &[Frame]) {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut().expect("frame allocator not init");
+    for &frame in frames {
+        release_frame(allocator, frame);
+    }
+}
+
+/// 打开名为`name`的对象，设置`O_CREAT`并且对象不存在时创建大小为`size`的对象
+pub fn open(name: &str, size: usize, flags: usize) -> Result<Arc<SharedObject>> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::new(EINVAL));
+    }
+    let mut objects = OBJECTS.lock();
+    if let Some(object) = objects.get(name).and_then(Weak::upgrade) {
+        // 释放对象时需要获取全局表的锁
+        drop(objects);
+        if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+            return Err(Error::new(EEXIST));
+        }
+        return Ok(object);
+    }
+    if flags & O_CREAT == 0 {
+        return Err(Error::new(ENOENT));
+    }
+    let object = Arc::new(SharedObject {
+        name: name.to_string(),
+        frames: Mutex::new(alloc_zeroed(pages(size)?)?),
+    });
+    objects.insert(name.to_string(), Arc::downgrade(&object));
+    Ok(object)
+}
+
+/// 进程打开的共享内存对象，句柄从1开始
+#[derive(Clone, Default)]
+pub struct ShmTable {
+    objects: BTreeMap<usize, Arc<SharedObject>>,
+}
+
+impl ShmTable {
+    pub fn new() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, object: Arc<SharedObject>) -> usize {
+        let handle = (1..).find(|handle| !self.objects.contains_key(handle)).expect("no free shm handle");
+        self.objects.insert(handle, object);
+        handle
+    }
+
+    pub fn get(&self, handle: usize) -> Result<&Arc<SharedObject>> {
+        self.objects.get(&handle).ok_or(Error::new(EBADF))
+    }
+
+    pub fn remove(&mut self, handle: usize) -> Result<Arc<SharedObject>> {
+        self.objects.remove(&handle).ok_or(Error::new(EBADF))
+    }
+}
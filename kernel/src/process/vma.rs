@@ -0,0 +1,196 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+use system::bits::flags::PageTableFlags;
+use system::ia_32e::VirtAddr;
+use system::syscall::result::{EEXIST, EINVAL, Error, ENOMEM, Result};
+
+use crate::process::memory::{Memory, SharedMemory};
+
+/// 进程地址空间中一段连续的映射
+#[derive(Debug)]
+pub struct Region {
+    start: VirtAddr,
+    size: usize,
+    flags: PageTableFlags,
+    /// 是否为共享映射（fork时共享同一块内存而不是复制）
+    shared: bool,
+    memory: SharedMemory,
+}
+
+impl Region {
+    /// 创建一个新的匿名映射，页面在第一次访问时分配并清零
+    pub fn anonymous(start: VirtAddr, size: usize, flags: PageTableFlags, shared: bool) -> Self {
+        let memory = Memory::new_lazy(start, size, flags);
+        Self {
+            start,
+            size,
+            flags,
+            shared,
+            memory: SharedMemory::Owned(Arc::new(Mutex::new(memory))),
+        }
+    }
+
+    /// 共享内存对象的映射，`memory`由`Memory::new_shared`创建
+    pub fn shared_object(memory: Memory) -> Self {
+        Self {
+            start: memory.start_address(),
+            size: memory.size(),
+            flags: memory.flags(),
+            shared: true,
+            memory: SharedMemory::Owned(Arc::new(Mutex::new(memory))),
+        }
+    }
+
+    pub fn start_address(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn end_address(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn flags(&self) -> PageTableFlags {
+        self.flags
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    pub fn memory(&self) -> &SharedMemory {
+        &self.memory
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end_address()
+    }
+
+    fn split_off(&mut self, at: VirtAddr) -> Region {
+        let offset = (at - self.start) as usize;
+        let tail = Region {
+            start: at,
+            size: self.size - offset,
+            flags: self.flags,
+            shared: self.shared,
+            memory: self.memory.split_off(offset),
+        };
+        self.size = offset;
+        tail
+    }
+}
+
+/// 进程的虚拟内存区域，按起始地址排序，区域之间不会重叠
+#[derive(Debug, Default)]
+pub struct VmaTree {
+    regions: BTreeMap<VirtAddr, Region>,
+}
+
+impl VmaTree {
+    pub fn new() -> Self {
+        Self {
+            regions: BTreeMap::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Region> + '_ {
+        self.regions.values()
+    }
+
+    /// 查找包含给定地址的区域
+    pub fn find(&self, addr: VirtAddr) -> Option<&Region> {
+        self.regions.range(..=addr).next_back().map(|(_, region)| region).filter(|region| region.contains(addr))
+    }
+
+    /// 判断`[start, start + size)`是否与已有区域重叠
+    pub fn is_free(&self, start: VirtAddr, size: usize) -> bool {
+        let end = start + size;
+        self.regions.range(..end).next_back().map_or(true, |(_, region)| region.end_address() <= start)
+    }
+
+    /// 在`[lower, upper)`中查找第一个可以容纳`size`字节的空闲位置
+    pub fn find_free(&self, lower: VirtAddr, upper: VirtAddr, size: usize) -> Option<VirtAddr> {
+        let mut candidate = lower;
+        for region in self.regions.values() {
+            if region.end_address() <= candidate {
+                continue;
+            }
+            if region.start_address() >= candidate + size {
+                break;
+            }
+            candidate = region.end_address();
+        }
+        if candidate + size <= upper {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    /// 插入新的区域，与已有区域重叠时返回`EEXIST`
+    pub fn insert(&mut self, region: Region) -> Result<()> {
+        if region.size() == 0 {
+            return Err(Error::new(EINVAL));
+        }
+        if !self.is_free(region.start_address(), region.size()) {
+            return Err(Error::new(EEXIST));
+        }
+        self.regions.insert(region.start_address(), region);
+        Ok(())
+    }
+
+    /// 在`addr`处拆分区域，使其成为某个区域的起始地址
+    fn split_at(&mut self, addr: VirtAddr) {
+        let key = match self.regions.range(..addr).next_back() {
+            Some((&key, region)) if region.contains(addr) => key,
+            _ => return,
+        };
+        let tail = self.regions.get_mut(&key).expect("region disappeared").split_off(addr);
+        self.regions.insert(addr, tail);
+    }
+
+    /// 取出`[start, start + size)`中的所有区域，跨越边界的区域会先被拆分
+    fn take_range(&mut self, start: VirtAddr, size: usize) -> Vec<Region> {
+        let end = start + size;
+        self.split_at(start);
+        self.split_at(end);
+        let keys: Vec<VirtAddr> = self.regions.range(start..end).map(|(&key, _)| key).collect();
+        keys.iter().filter_map(|key| self.regions.remove(key)).collect()
+    }
+
+    /// 解除`[start, start + size)`的映射，范围内没有映射的部分会被忽略
+    pub fn unmap(&mut self, start: VirtAddr, size: usize) {
+        // 区域被drop时会解除页面映射
+        drop(self.take_range(start, size));
+    }
+
+    /// 修改`[start, start + size)`的访问权限，范围内必须全部已映射
+    pub fn protect(&mut self, start: VirtAddr, size: usize, flags: PageTableFlags) -> Result<()> {
+        let end = start + size;
+        let mut cursor = start;
+        for region in self.regions.range(..end).map(|(_, region)| region) {
+            if region.end_address() <= cursor {
+                continue;
+            }
+            if region.start_address() > cursor {
+                return Err(Error::new(ENOMEM));
+            }
+            cursor = region.end_address();
+        }
+        if cursor < end {
+            return Err(Error::new(ENOMEM));
+        }
+        for mut region in self.take_range(start, size) {
+            region.memory.with(|memory| memory.remap(flags));
+            region.flags = flags;
+            self.regions.insert(region.start_address(), region);
+        }
+        Ok(())
+    }
+}
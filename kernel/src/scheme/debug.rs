@@ -0,0 +1,89 @@
+use alloc::collections::BTreeSet;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::RwLock;
+use system::ia_32e::instructions::interrupt::without_interrupts;
+use system::syscall::data::Stat;
+use system::syscall::flag::MODE_CHR;
+use system::syscall::result::{EAGAIN, EBADF, Error, Result};
+
+use crate::scheme::Scheme;
+use crate::serial::SERIAL;
+
+/// `debug:`，读写串口控制台
+pub struct DebugScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeSet<usize>>,
+}
+
+impl DebugScheme {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeSet::new()),
+        }
+    }
+
+    fn check(&self, id: usize) -> Result<()> {
+        if self.handles.read().contains(&id) {
+            Ok(())
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+}
+
+impl Scheme for DebugScheme {
+    fn open(&self, _path: &str, _flags: usize) -> Result<usize> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id);
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        self.check(id)?;
+        let count = without_interrupts(|| {
+            let mut serial = SERIAL.lock();
+            let mut count = 0;
+            while count < buf.len() {
+                match serial.try_receive() {
+                    Some(byte) => {
+                        buf[count] = byte;
+                        count += 1;
+                    }
+                    None => break,
+                }
+            }
+            count
+        });
+        if count == 0 && !buf.is_empty() {
+            return Err(Error::new(EAGAIN));
+        }
+        Ok(count)
+    }
+
+    fn write(&self, id: usize, buf: &[u8]) -> Result<usize> {
+        self.check(id)?;
+        without_interrupts(|| {
+            let mut serial = SERIAL.lock();
+            for &byte in buf {
+                serial.send(byte);
+            }
+        });
+        Ok(buf.len())
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
+        self.check(id)?;
+        stat.st_mode = MODE_CHR | 0o666;
+        Ok(0)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        if self.handles.write().remove(&id) {
+            Ok(0)
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+}
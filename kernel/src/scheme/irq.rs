@@ -0,0 +1,125 @@
+use alloc::collections::BTreeMap;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::RwLock;
+use system::syscall::data::Stat;
+use system::syscall::flag::MODE_CHR;
+use system::syscall::result::{EAGAIN, EBADF, EINVAL, Error, ENOENT, ESRCH, Result};
+
+use crate::descriptor::{InterruptIndex, PIC_MAIN};
+use crate::process::process;
+use crate::scheme::Scheme;
+use crate::syscall::process::require_privileged;
+
+/// ISA中断数量
+pub const IRQ_COUNT: usize = 16;
+
+/// 每个ISA中断触发的次数
+static COUNTS: [AtomicUsize; IRQ_COUNT] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// 每个ISA中断的用户态订阅者数量，存在订阅者时内核不再自己处理该设备
+static SUBSCRIBERS: [AtomicUsize; IRQ_COUNT] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// 是否有用户态驱动订阅了该中断
+pub fn irq_subscribed(index: InterruptIndex) -> bool {
+    let irq = u8::from(index) - PIC_MAIN;
+    SUBSCRIBERS[irq as usize].load(Ordering::SeqCst) > 0
+}
+
+/// 由中断处理函数调用，记录一次中断
+pub fn irq_trigger(index: InterruptIndex) {
+    let irq = u8::from(index) - PIC_MAIN;
+    COUNTS[irq as usize].fetch_add(1, Ordering::SeqCst);
+}
+
+struct Handle {
+    irq: usize,
+    /// 上次读取时的中断次数
+    seen: usize,
+}
+
+/// `irq:N`，读取时返回上次读取后第`N`号ISA中断触发的次数(8字节)，没有新中断时返回`EAGAIN`
+///
+/// 只有特权进程可以打开，打开后内核不再处理该设备的数据(例如键盘扫描码)，由用户态驱动负责
+pub struct IrqScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Handle>>,
+}
+
+impl IrqScheme {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Scheme for IrqScheme {
+    fn open(&self, path: &str, _flags: usize) -> Result<usize> {
+        let irq = path.trim_matches('/').parse::<usize>().map_err(|_| Error::new(ENOENT))?;
+        if irq >= IRQ_COUNT {
+            return Err(Error::new(ENOENT));
+        }
+        {
+            let list = process();
+            let current = list.current().ok_or(Error::new(ESRCH))?.read();
+            require_privileged(&current)?;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        SUBSCRIBERS[irq].fetch_add(1, Ordering::SeqCst);
+        let seen = COUNTS[irq].load(Ordering::SeqCst);
+        self.handles.write().insert(id, Handle { irq, seen });
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < mem::size_of::<usize>() {
+            return Err(Error::new(EINVAL));
+        }
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        let count = COUNTS[handle.irq].load(Ordering::SeqCst);
+        let new = count.wrapping_sub(handle.seen);
+        if new == 0 {
+            return Err(Error::new(EAGAIN));
+        }
+        handle.seen = count;
+        buf[..mem::size_of::<usize>()].copy_from_slice(&new.to_ne_bytes());
+        Ok(mem::size_of::<usize>())
+    }
+
+    fn write(&self, id: usize, buf: &[u8]) -> Result<usize> {
+        // 中断处理函数已经发送了EOI，这里只检查句柄
+        if self.handles.read().contains_key(&id) {
+            Ok(buf.len())
+        } else {
+            Err(Error::new(EBADF))
+        }
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
+        if !self.handles.read().contains_key(&id) {
+            return Err(Error::new(EBADF));
+        }
+        stat.st_mode = MODE_CHR | 0o600;
+        Ok(0)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        let handle = self.handles.write().remove(&id).ok_or(Error::new(EBADF))?;
+        SUBSCRIBERS[handle.irq].fetch_sub(1, Ordering::SeqCst);
+        Ok(0)
+    }
+}
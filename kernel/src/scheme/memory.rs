@@ -0,0 +1,83 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::RwLock;
+use system::ia_32e::paging::FrameAllocator;
+use system::syscall::data::Stat;
+use system::syscall::flag::MODE_FILE;
+use system::syscall::result::{EACCES, EBADF, Error, ENOENT, Result};
+
+use crate::memory::{FRAME_ALLOCATOR, HEAP, SLAB};
+use crate::scheme::{Scheme, Snapshot};
+
+/// `memory:`，提供内核堆，slab缓存和物理帧的使用情况
+pub struct MemoryScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Snapshot>>,
+}
+
+impl MemoryScheme {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Scheme for MemoryScheme {
+    fn open(&self, path: &str, _flags: usize) -> Result<usize> {
+        if !path.trim_matches('/').is_empty() {
+            return Err(Error::new(ENOENT));
+        }
+        let mut data = {
+            let heap = HEAP.lock();
+            format!("total: {}\nused: {}\nrequested: {}\nfree: {}\n",
+                    heap.stats_total_bytes(),
+                    heap.stats_alloc_actual(),
+                    heap.stats_alloc_user(),
+                    heap.stats_total_bytes() - heap.stats_alloc_actual())
+        };
+        if let Some(frames) = FRAME_ALLOCATOR.lock().as_ref() {
+            data.push_str(&format!("frames_free: {}\nframes_used: {}\n", frames.free_frames(), frames.used_frames()));
+        }
+        for cache in SLAB.caches() {
+            let stats = cache.stats();
+            data.push_str(&format!("{}: slabs={} objects={}/{} allocs={} frees={} magazine_hits={}\n",
+                                   cache.name(), stats.slabs, stats.objects, stats.capacity,
+                                   stats.allocations, stats.frees, stats.magazine_hits));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Snapshot::new(Vec::from(data.as_bytes())));
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        Ok(handle.read(buf))
+    }
+
+    fn write(&self, _id: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::new(EACCES))
+    }
+
+    fn seek(&self, id: usize, pos: usize, whence: usize) -> Result<usize> {
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        handle.seek(pos, whence)
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        stat.st_mode = MODE_FILE | 0o444;
+        stat.st_size = handle.len() as u64;
+        Ok(0)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF)).and(Ok(0))
+    }
+}
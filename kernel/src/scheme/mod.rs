@@ -0,0 +1,207 @@
+//! scheme是内核资源的命名空间，`open("scheme:path")`会被转发到名为`scheme`的提供者
+//!
+//! 内核子系统通过`SchemeList::insert`注册自己的scheme，新增资源无需修改系统调用层
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use system::syscall::data::Stat;
+use system::syscall::flag::{SEEK_CUR, SEEK_END, SEEK_SET};
+use system::syscall::result::{EEXIST, EINVAL, Error, ENOENT, ESPIPE, Result};
+
+use crate::process::file::FileObject;
+use crate::process::types::SchemeId;
+
+pub mod debug;
+pub mod irq;
+pub mod memory;
+pub mod sys;
+pub mod user;
+
+/// scheme提供者需要实现的操作，`id`为`open`返回的句柄
+pub trait Scheme: Send + Sync {
+    /// 打开scheme中的路径，返回句柄
+    fn open(&self, path: &str, flags: usize) -> Result<usize>;
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize>;
+
+    fn write(&self, id: usize, buf: &[u8]) -> Result<usize>;
+
+    fn seek(&self, _id: usize, _pos: usize, _whence: usize) -> Result<usize> {
+        Err(Error::new(ESPIPE))
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize>;
+
+    fn close(&self, id: usize) -> Result<usize>;
+}
+
+/// 已注册的scheme
+pub struct SchemeList {
+    map: BTreeMap<SchemeId, Arc<dyn Scheme>>,
+    names: BTreeMap<String, SchemeId>,
+    next_id: usize,
+}
+
+impl SchemeList {
+    pub fn new() -> Self {
+        Self {
+            map: BTreeMap::new(),
+            names: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn get(&self, id: SchemeId) -> Option<&Arc<dyn Scheme>> {
+        self.map.get(&id)
+    }
+
+    pub fn get_name(&self, name: &str) -> Option<(SchemeId, &Arc<dyn Scheme>)> {
+        self.names.get(name).and_then(|&id| self.map.get(&id).map(|scheme| (id, scheme)))
+    }
+
+    pub fn iter_name(&self) -> impl Iterator<Item=(&String, &SchemeId)> + '_ {
+        self.names.iter()
+    }
+
+    /// 注册一个新的scheme，名称已经存在时返回`EEXIST`
+    pub fn insert(&mut self, name: &str, scheme: Arc<dyn Scheme>) -> Result<SchemeId> {
+        if name.is_empty() || name.contains(':') {
+            return Err(Error::new(EINVAL));
+        }
+        if self.names.contains_key(name) {
+            return Err(Error::new(EEXIST));
+        }
+        while self.map.contains_key(&SchemeId::from(self.next_id)) {
+            self.next_id += 1;
+        }
+        let id = SchemeId::from(self.next_id);
+        self.next_id += 1;
+        self.map.insert(id, scheme);
+        self.names.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    /// 注销scheme，已经打开的文件不受影响
+    pub fn remove(&mut self, id: SchemeId) -> Option<Arc<dyn Scheme>> {
+        self.names.retain(|_, scheme_id| *scheme_id != id);
+        self.map.remove(&id)
+    }
+}
+
+static SCHEMES: Once<RwLock<SchemeList>> = Once::new();
+
+/// 注册内核提供的scheme
+fn init_schemes() -> RwLock<SchemeList> {
+    let mut list = SchemeList::new();
+    list.insert("sys", Arc::new(sys::SysScheme::new())).expect("failed to register sys scheme");
+    list.insert("debug", Arc::new(debug::DebugScheme::new())).expect("failed to register debug scheme");
+    list.insert("memory", Arc::new(memory::MemoryScheme::new())).expect("failed to register memory scheme");
+    list.insert("irq", Arc::new(irq::IrqScheme::new())).expect("failed to register irq scheme");
+    RwLock::new(list)
+}
+
+/// Get the global schemes list, const
+pub fn schemes() -> RwLockReadGuard<'static, SchemeList> {
+    SCHEMES.call_once(init_schemes).read()
+}
+
+/// Get the global schemes list, mutable
+pub fn schemes_mut() -> RwLockWriteGuard<'static, SchemeList> {
+    SCHEMES.call_once(init_schemes).write()
+}
+
+/// 通过scheme打开的文件
+pub struct SchemeFile {
+    pub scheme_id: SchemeId,
+    pub scheme: Arc<dyn Scheme>,
+    pub number: usize,
+}
+
+impl FileObject for SchemeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.scheme.read(self.number, buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.scheme.write(self.number, buf)
+    }
+
+    fn seek(&self, pos: usize, whence: usize) -> Result<usize> {
+        self.scheme.seek(self.number, pos, whence)
+    }
+
+    fn fstat(&self, stat: &mut Stat) -> Result<()> {
+        self.scheme.fstat(self.number, stat)?;
+        stat.st_dev = self.scheme_id.into() as u64;
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        self.scheme.close(self.number).map(|_| ())
+    }
+}
+
+/// 解析`scheme:path`并打开对应的资源
+pub fn open(url: &str, flags: usize) -> Result<SchemeFile> {
+    let mut parts = url.splitn(2, ':');
+    let name = parts.next().unwrap_or("");
+    let path = parts.next().ok_or(Error::new(ENOENT))?;
+    let (scheme_id, scheme) = {
+        let list = schemes();
+        let (id, scheme) = list.get_name(name).ok_or(Error::new(ENOENT))?;
+        (id, scheme.clone())
+    };
+    // 释放scheme列表的锁之后再调用open，scheme可能需要较长时间
+    let number = scheme.open(path, flags)?;
+    Ok(SchemeFile {
+        scheme_id,
+        scheme,
+        number,
+    })
+}
+
+/// 只读的数据快照，供在open时生成内容的scheme使用
+pub struct Snapshot {
+    data: Vec<u8>,
+    seek: usize,
+}
+
+impl Snapshot {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            seek: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let remain = &self.data[core::cmp::min(self.seek, self.data.len())..];
+        let count = core::cmp::min(remain.len(), buf.len());
+        buf[..count].copy_from_slice(&remain[..count]);
+        self.seek += count;
+        count
+    }
+
+    pub fn seek(&mut self, pos: usize, whence: usize) -> Result<usize> {
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => self.seek as isize,
+            SEEK_END => self.data.len() as isize,
+            _ => return Err(Error::new(EINVAL)),
+        };
+        let new = base + pos as isize;
+        if new < 0 {
+            return Err(Error::new(EINVAL));
+        }
+        self.seek = new as usize;
+        Ok(self.seek)
+    }
+}
@@ -0,0 +1,96 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::RwLock;
+use system::syscall::data::Stat;
+use system::syscall::flag::MODE_FILE;
+use system::syscall::result::{EACCES, EBADF, Error, ENOENT, Result};
+
+use crate::process::process;
+use crate::scheme::{schemes, Scheme, Snapshot};
+use crate::time;
+
+/// `sys:`，以文本形式提供内核状态
+/// - `sys:uptime` 启动后经过的秒数，精确到纳秒
+/// - `sys:context` 进程列表
+/// - `sys:scheme` 已注册的scheme
+pub struct SysScheme {
+    next_id: AtomicUsize,
+    handles: RwLock<BTreeMap<usize, Snapshot>>,
+}
+
+impl SysScheme {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicUsize::new(0),
+            handles: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+fn uptime() -> String {
+    let uptime = time::uptime();
+    format!("{}.{:09}\n", uptime.as_secs(), uptime.subsec_nanos())
+}
+
+fn context() -> String {
+    let mut s = String::from("PID STATUS TRACE\n");
+    for (id, lock) in process().iter() {
+        let process = lock.read();
+        let _ = writeln!(s, "{} {:?} {}", id.into(), process.status, process.trace);
+    }
+    s
+}
+
+fn scheme() -> String {
+    let mut s = String::new();
+    for (name, id) in schemes().iter_name() {
+        let _ = writeln!(s, "{} {}", id.into(), name);
+    }
+    s
+}
+
+impl Scheme for SysScheme {
+    fn open(&self, path: &str, _flags: usize) -> Result<usize> {
+        let data = match path.trim_matches('/') {
+            "uptime" => uptime(),
+            "context" => context(),
+            "scheme" => scheme(),
+            _ => return Err(Error::new(ENOENT)),
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.handles.write().insert(id, Snapshot::new(Vec::from(data.as_bytes())));
+        Ok(id)
+    }
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        Ok(handle.read(buf))
+    }
+
+    fn write(&self, _id: usize, _buf: &[u8]) -> Result<usize> {
+        Err(Error::new(EACCES))
+    }
+
+    fn seek(&self, id: usize, pos: usize, whence: usize) -> Result<usize> {
+        let mut handles = self.handles.write();
+        let handle = handles.get_mut(&id).ok_or(Error::new(EBADF))?;
+        handle.seek(pos, whence)
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
+        let handles = self.handles.read();
+        let handle = handles.get(&id).ok_or(Error::new(EBADF))?;
+        stat.st_mode = MODE_FILE | 0o444;
+        stat.st_size = handle.len() as u64;
+        Ok(0)
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.handles.write().remove(&id).ok_or(Error::new(EBADF)).and(Ok(0))
+    }
+}
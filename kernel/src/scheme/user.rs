@@ -0,0 +1,244 @@
+//! 用户态scheme提供者
+//!
+//! 提供者通过`SYS_SCHEME_REGISTER`得到一个文件描述符，从中读取内核转发的请求(`Packet`)，
+//! 处理完成后将回复写回。发起请求的进程被阻塞，直到收到回复或者提供者关闭文件描述符时被唤醒。
+//!
+//! 目前所有进程共享同一个页表，所以请求中的缓冲区地址可以直接交给提供者访问。
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+use system::ia_32e::instructions::interrupt::system_pause;
+use system::syscall::data::{Packet, Stat};
+use system::syscall::number::{SYS_CLOSE, SYS_FSTAT, SYS_LSEEK, SYS_OPEN, SYS_READ, SYS_WRITE};
+use system::syscall::result::{EAGAIN, EINVAL, Error, EPIPE, Result};
+
+use crate::devices::timer;
+use crate::process::{CURRENT_PROCESS, process};
+use crate::process::file::FileObject;
+use crate::process::scheduler::switch;
+use crate::process::types::{AtomicSchemeId, ProcessId, SchemeId};
+use crate::scheme::{Scheme, schemes_mut};
+
+pub struct UserInner {
+    name: String,
+    scheme_id: AtomicSchemeId,
+    next_id: AtomicU64,
+    /// 等待提供者读取的请求
+    todo: Mutex<VecDeque<Packet>>,
+    /// 提供者已经回复的请求
+    done: Mutex<BTreeMap<u64, usize>>,
+    /// 等待回复的请求和发起请求的进程，只在持有`done`时修改
+    waiters: Mutex<BTreeMap<u64, ProcessId>>,
+    alive: AtomicBool,
+}
+
+impl UserInner {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            scheme_id: AtomicSchemeId::default(),
+            next_id: AtomicU64::new(0),
+            todo: Mutex::new(VecDeque::new()),
+            done: Mutex::new(BTreeMap::new()),
+            waiters: Mutex::new(BTreeMap::new()),
+            alive: AtomicBool::new(true),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_scheme_id(&self, id: SchemeId) {
+        self.scheme_id.store(id, Ordering::SeqCst);
+    }
+
+    /// 将请求转发给提供者，阻塞当前进程直到收到回复
+    fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+        if !self.alive.load(Ordering::SeqCst) {
+            return Err(Error::new(EPIPE));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let pid = CURRENT_PROCESS.load(Ordering::SeqCst);
+        self.waiters.lock().insert(id, pid);
+        self.todo.lock().push_back(Packet {
+            id,
+            pid: pid.into(),
+            a,
+            b,
+            c,
+            d,
+        });
+        loop {
+            {
+                let mut done = self.done.lock();
+                if let Some(value) = done.remove(&id) {
+                    return Error::demux(value);
+                }
+                if !self.alive.load(Ordering::SeqCst) {
+                    self.waiters.lock().remove(&id);
+                    return Err(Error::new(EPIPE));
+                }
+                // 持有`done`时阻塞，提供者的回复不会在阻塞之前到达而丢失唤醒
+                let list = process();
+                if let Some(lock) = list.current() {
+                    lock.write().block();
+                }
+            }
+            // 被唤醒后重新检查回复，没有其他进程可以运行时等待
+            if !switch() {
+                system_pause();
+            }
+        }
+    }
+
+    /// 提供者读取请求，没有请求时返回`EAGAIN`
+    fn read_requests(&self, buf: &mut [u8]) -> Result<usize> {
+        let size = mem::size_of::<Packet>();
+        if buf.len() < size {
+            return Err(Error::new(EINVAL));
+        }
+        let mut todo = self.todo.lock();
+        let mut count = 0;
+        while count + size <= buf.len() {
+            match todo.pop_front() {
+                Some(packet) => unsafe {
+                    ptr::write_unaligned(buf.as_mut_ptr().add(count) as *mut Packet, packet);
+                },
+                None => break,
+            }
+            count += size;
+        }
+        if count == 0 {
+            return Err(Error::new(EAGAIN));
+        }
+        Ok(count)
+    }
+
+    /// 提供者写入回复，遇到没有进程等待的回复时停止，第一个回复就无效时返回`EINVAL`
+    fn write_responses(&self, buf: &[u8]) -> Result<usize> {
+        let size = mem::size_of::<Packet>();
+        let mut done = self.done.lock();
+        let mut count = 0;
+        while count + size <= buf.len() {
+            let packet = unsafe { ptr::read_unaligned(buf.as_ptr().add(count) as *const Packet) };
+            // 只接受仍有进程在等待的请求，否则伪造或重复的回复会一直留在`done`中
+            let pid = match self.waiters.lock().remove(&packet.id) {
+                Some(pid) => pid,
+                None if count == 0 => return Err(Error::new(EINVAL)),
+                None => break,
+            };
+            done.insert(packet.id, packet.a);
+            wake(pid);
+            count += size;
+        }
+        Ok(count)
+    }
+
+    /// 提供者退出，唤醒所有等待回复的进程
+    fn shutdown(&self) {
+        let _done = self.done.lock();
+        self.alive.store(false, Ordering::SeqCst);
+        let waiters = mem::take(&mut *self.waiters.lock());
+        for pid in waiters.values() {
+            wake(*pid);
+        }
+    }
+}
+
+/// 唤醒被阻塞的进程`pid`
+fn wake(pid: ProcessId) {
+    let list = process();
+    if let Some(lock) = list.get(pid) {
+        if lock.write().unblock() {
+            timer::wake();
+        }
+    }
+}
+
+/// 注册到scheme列表中的用户态scheme，只持有弱引用，提供者退出后请求返回`EPIPE`
+pub struct UserScheme {
+    inner: Weak<UserInner>,
+}
+
+impl UserScheme {
+    pub fn new(inner: Weak<UserInner>) -> Self {
+        Self {
+            inner,
+        }
+    }
+
+    fn call(&self, a: usize, b: usize, c: usize, d: usize) -> Result<usize> {
+        let inner = self.inner.upgrade().ok_or(Error::new(EPIPE))?;
+        inner.call(a, b, c, d)
+    }
+}
+
+impl Scheme for UserScheme {
+    fn open(&self, path: &str, flags: usize) -> Result<usize> {
+        // 路径长度放在`d`中，flags放在句柄位置
+        self.call(SYS_OPEN, flags, path.as_ptr() as usize, path.len())
+    }
+
+    fn read(&self, id: usize, buf: &mut [u8]) -> Result<usize> {
+        self.call(SYS_READ, id, buf.as_mut_ptr() as usize, buf.len())
+    }
+
+    fn write(&self, id: usize, buf: &[u8]) -> Result<usize> {
+        self.call(SYS_WRITE, id, buf.as_ptr() as usize, buf.len())
+    }
+
+    fn seek(&self, id: usize, pos: usize, whence: usize) -> Result<usize> {
+        self.call(SYS_LSEEK, id, pos, whence)
+    }
+
+    fn fstat(&self, id: usize, stat: &mut Stat) -> Result<usize> {
+        self.call(SYS_FSTAT, id, stat as *mut Stat as usize, mem::size_of::<Stat>())
+    }
+
+    fn close(&self, id: usize) -> Result<usize> {
+        self.call(SYS_CLOSE, id, 0, 0)
+    }
+}
+
+/// 提供者持有的文件，关闭时注销scheme并唤醒所有等待的请求
+pub struct UserSchemeFile {
+    inner: Arc<UserInner>,
+}
+
+impl UserSchemeFile {
+    pub fn new(inner: Arc<UserInner>) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl FileObject for UserSchemeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read_requests(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.inner.write_responses(buf)
+    }
+
+    fn close(&self) -> Result<()> {
+        self.inner.shutdown();
+        schemes_mut().remove(self.inner.scheme_id.load(Ordering::SeqCst));
+        Ok(())
+    }
+}
+
+/// 注册名为`name`的用户态scheme，返回提供者使用的文件
+pub fn register(name: &str) -> Result<UserSchemeFile> {
+    let inner = Arc::new(UserInner::new(String::from(name)));
+    let id = schemes_mut().insert(name, Arc::new(UserScheme::new(Arc::downgrade(&inner))))?;
+    inner.set_scheme_id(id);
+    Ok(UserSchemeFile::new(inner))
+}
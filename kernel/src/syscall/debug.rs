@@ -0,0 +1,46 @@
+use alloc::string::String;
+
+use system::syscall::number::*;
+use system::syscall::result::Result;
+
+/// 根据系统调用号获取调用名称
+pub fn name(number: usize) -> &'static str {
+    match number {
+        SYS_TEST => "test",
+        SYS_TRACE => "trace",
+        SYS_IOPORT => "ioport",
+        SYS_SHM_OPEN => "shm_open",
+        SYS_SHM_RESIZE => "shm_resize",
+        SYS_SHM_MAP => "shm_map",
+        SYS_SHM_CLOSE => "shm_close",
+        SYS_SCHEME_REGISTER => "scheme_register",
+        SYS_READ => "read",
+        SYS_WRITE => "write",
+        SYS_OPEN => "open",
+        SYS_LSEEK => "lseek",
+        SYS_FSTAT => "fstat",
+        SYS_CLOSE => "close",
+        SYS_DUP => "dup",
+        SYS_DUP2 => "dup2",
+        SYS_FCNTL => "fcntl",
+        SYS_BRK => "brk",
+        SYS_MMAP => "mmap",
+        SYS_MUNMAP => "munmap",
+        SYS_MPROTECT => "mprotect",
+        SYS_CLOCK_GETTIME => "clock_gettime",
+        _ => "unknown",
+    }
+}
+
+/// 格式化系统调用及其参数，例如`trace(0x0, 0x1, 0x0, 0x0, 0x0)`
+pub fn format_call(a: usize, b: usize, c: usize, d: usize, e: usize, f: usize) -> String {
+    format!("{}({:#x}, {:#x}, {:#x}, {:#x}, {:#x})", name(a), b, c, d, e, f)
+}
+
+/// 格式化系统调用的返回值，出错时使用`Error::text`显示错误信息
+pub fn format_result(result: &Result<usize>) -> String {
+    match result {
+        Ok(value) => format!("Ok({:#x})", value),
+        Err(err) => format!("Err({}: {})", err.errno, err.text()),
+    }
+}
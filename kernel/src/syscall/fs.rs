@@ -0,0 +1,131 @@
+use alloc::sync::Arc;
+
+use spin::RwLock;
+use system::syscall::data::Stat;
+use system::syscall::flag::{F_GETFD, F_GETFL, F_SETFD, FD_CLOEXEC, O_ACCMODE, O_CLOEXEC, O_RDONLY, O_RDWR, O_WRONLY};
+use system::syscall::result::{EBADF, EINVAL, Error, ESRCH, Result};
+
+use crate::scheme;
+
+use crate::process::file::{FileDescription, OpenFile};
+use crate::process::process;
+use crate::process::types::FileDescriptor;
+use crate::syscall::process::require_privileged;
+
+/// 获取当前进程中文件描述符对应的文件描述，不持有进程锁
+fn description(fd: FileDescriptor) -> Result<Arc<RwLock<FileDescription>>> {
+    let list = process();
+    let current = list.current().ok_or(Error::new(ESRCH))?.read();
+    Ok(current.files.get(fd)?.description.clone())
+}
+
+/// 打开`scheme:path`，返回新的文件描述符
+pub fn open(path: &[u8], flags: usize) -> Result<usize> {
+    let path = core::str::from_utf8(path).map_err(|_| Error::new(EINVAL))?;
+    let file = scheme::open(path, flags)?;
+    let file = OpenFile::new(FileDescription::new(Arc::new(file), flags & !O_CLOEXEC), flags & O_CLOEXEC == O_CLOEXEC);
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    current.files.add(file).map(FileDescriptor::into)
+}
+
+/// 将当前进程注册为名为`name`的scheme提供者，返回用于收发消息的文件描述符
+pub fn scheme_register(name: &[u8]) -> Result<usize> {
+    let name = core::str::from_utf8(name).map_err(|_| Error::new(EINVAL))?;
+    {
+        let list = process();
+        let current = list.current().ok_or(Error::new(ESRCH))?.read();
+        require_privileged(&current)?;
+    }
+    let file = scheme::user::register(name)?;
+    let file = OpenFile::new(FileDescription::new(Arc::new(file), O_RDWR), true);
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    current.files.add(file).map(FileDescriptor::into)
+}
+
+/// 文件描述的访问模式不是`allowed`之一时返回`EBADF`
+fn check_access(description: &FileDescription, allowed: [usize; 2]) -> Result<()> {
+    if allowed.contains(&(description.flags & O_ACCMODE)) {
+        Ok(())
+    } else {
+        Err(Error::new(EBADF))
+    }
+}
+
+pub fn read(fd: FileDescriptor, buf: &mut [u8]) -> Result<usize> {
+    let description = description(fd)?;
+    let object = {
+        let description = description.read();
+        check_access(&description, [O_RDONLY, O_RDWR])?;
+        description.object.clone()
+    };
+    object.read(buf)
+}
+
+pub fn write(fd: FileDescriptor, buf: &[u8]) -> Result<usize> {
+    let description = description(fd)?;
+    let object = {
+        let description = description.read();
+        check_access(&description, [O_WRONLY, O_RDWR])?;
+        description.object.clone()
+    };
+    object.write(buf)
+}
+
+pub fn lseek(fd: FileDescriptor, pos: usize, whence: usize) -> Result<usize> {
+    let description = description(fd)?;
+    let object = description.read().object.clone();
+    object.seek(pos, whence)
+}
+
+pub fn fstat(fd: FileDescriptor, stat: &mut Stat) -> Result<usize> {
+    let description = description(fd)?;
+    let object = description.read().object.clone();
+    *stat = Stat::default();
+    object.fstat(stat)?;
+    Ok(0)
+}
+
+pub fn close(fd: FileDescriptor) -> Result<usize> {
+    let file = {
+        let list = process();
+        let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+        current.files.remove(fd)?
+    };
+    // 释放进程锁之后再关闭，最后一个引用被释放时会调用`FileObject::close`
+    drop(file);
+    Ok(0)
+}
+
+pub fn dup(fd: FileDescriptor) -> Result<usize> {
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    current.files.dup(fd).map(FileDescriptor::into)
+}
+
+pub fn dup2(old: FileDescriptor, new: FileDescriptor) -> Result<usize> {
+    let replaced = {
+        let list = process();
+        let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+        current.files.dup2(old, new)?
+    };
+    // 释放进程锁之后再关闭被替换的文件描述符
+    drop(replaced);
+    Ok(new.into())
+}
+
+pub fn fcntl(fd: FileDescriptor, cmd: usize, arg: usize) -> Result<usize> {
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    let file = current.files.get_mut(fd)?;
+    match cmd {
+        F_GETFD => Ok(if file.cloexec { FD_CLOEXEC } else { 0 }),
+        F_SETFD => {
+            file.cloexec = arg & FD_CLOEXEC == FD_CLOEXEC;
+            Ok(0)
+        }
+        F_GETFL => Ok(file.description.read().flags),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
@@ -0,0 +1,185 @@
+use alloc::sync::Arc;
+
+use spin::Mutex;
+use system::bits::flags::PageTableFlags;
+use system::ia_32e::{align_up, VirtAddr};
+use system::ia_32e::paging::{Page4KB, PageSize};
+use system::syscall::flag::*;
+use system::syscall::result::{EEXIST, EINVAL, Error, ENODEV, ENOMEM, ESRCH, Result};
+
+use crate::process::memory::{Memory, SharedMemory, USER_END, USER_HEAP_OFFSET, USER_HEAP_SIZE, USER_MMAP_OFFSET, USER_MMAP_SIZE};
+use crate::process::process;
+use crate::process::process::Process;
+use crate::process::shm;
+use crate::process::vma::Region;
+
+/// 将`PROT_*`转换为用户页面的flags
+fn prot_to_flags(prot: usize) -> PageTableFlags {
+    // PROT_NONE的页面仅保留PRESENT，用户态无法访问
+    if prot & (PROT_READ | PROT_WRITE | PROT_EXEC) == 0 {
+        return PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    }
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if prot & PROT_WRITE == PROT_WRITE {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// 按页对齐后的长度，长度为0或者超出用户空间的大小时返回`EINVAL`
+fn page_align(len: usize) -> Result<usize> {
+    if len == 0 || len as u64 > USER_END {
+        return Err(Error::new(EINVAL));
+    }
+    Ok(align_up(len as u64, Page4KB::P_SIZE) as usize)
+}
+
+/// 检查`[addr, addr + len)`是否为按页对齐的用户地址，返回按页对齐后的长度
+fn check_range(addr: usize, len: usize) -> Result<usize> {
+    let size = page_align(len)?;
+    if addr % Page4KB::P_SIZE as usize != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    match addr.checked_add(size) {
+        Some(end) if end as u64 <= USER_END => Ok(size),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// 用户映射的最低地址，空指针附近的页面始终不映射
+const MMAP_MIN_ADDR: usize = 0x1_0000;
+
+/// 选择映射`len`字节的地址。设置`MAP_FIXED`时必须使用`addr`，
+/// 否则`addr`可用时优先使用，不可用时在mmap区域中查找空闲位置。
+/// 地址低于`MMAP_MIN_ADDR`或超出用户空间时返回`EINVAL`，与进程已有的内存重叠时返回`EEXIST`
+fn place(current: &Process, addr: usize, len: usize, flags: usize) -> Result<VirtAddr> {
+    let size = page_align(len)?;
+    let usable = |addr: usize| check_range(addr, len).is_ok() && addr >= MMAP_MIN_ADDR;
+    let start = if flags & MAP_FIXED == MAP_FIXED {
+        if !usable(addr) {
+            return Err(Error::new(EINVAL));
+        }
+        VirtAddr::new(addr as u64)
+    } else if addr != 0 && usable(addr) && current.is_free(VirtAddr::new(addr as u64), size) {
+        VirtAddr::new(addr as u64)
+    } else {
+        let lower = VirtAddr::new(USER_MMAP_OFFSET);
+        let upper = VirtAddr::new(USER_MMAP_OFFSET + USER_MMAP_SIZE);
+        current.vmas.find_free(lower, upper, size).ok_or(Error::new(ENOMEM))?
+    };
+    if !current.is_free(start, size) {
+        return Err(Error::new(EEXIST));
+    }
+    Ok(start)
+}
+
+pub fn brk(addr: usize) -> Result<usize> {
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    let heap_start = USER_HEAP_OFFSET as usize;
+    let current_break = current.heap.as_ref().map_or(heap_start, |heap| heap.with(|memory| memory.end_address().as_usize()));
+    if addr == 0 {
+        return Ok(current_break);
+    }
+    if addr < heap_start || addr > heap_start + USER_HEAP_SIZE as usize {
+        return Err(Error::new(ENOMEM));
+    }
+    let size = addr - heap_start;
+    let current = &mut *current;
+    match current.heap {
+        Some(ref heap) => heap.with(|memory| memory.resize(size, true)),
+        None => {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+            let memory = Memory::new_lazy(VirtAddr::new(heap_start as u64), size, flags);
+            current.heap = Some(SharedMemory::Owned(Arc::new(Mutex::new(memory))));
+        }
+    }
+    Ok(addr)
+}
+
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize> {
+    // 目前只支持匿名映射
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(Error::new(ENODEV));
+    }
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Error::new(EINVAL)),
+    };
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    let size = page_align(len)?;
+    let start = place(&current, addr, len, flags)?;
+    current.vmas.insert(Region::anonymous(start, size, prot_to_flags(prot), shared))?;
+    Ok(start.as_usize())
+}
+
+pub fn munmap(addr: usize, len: usize) -> Result<usize> {
+    let size = check_range(addr, len)?;
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    current.vmas.unmap(VirtAddr::new(addr as u64), size);
+    Ok(0)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> Result<usize> {
+    let size = check_range(addr, len)?;
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    current.vmas.protect(VirtAddr::new(addr as u64), size, prot_to_flags(prot))?;
+    Ok(0)
+}
+
+/// 打开或创建命名的共享内存对象，返回句柄
+pub fn shm_open(name: &[u8], size: usize, flags: usize) -> Result<usize> {
+    let name = core::str::from_utf8(name).map_err(|_| Error::new(EINVAL))?;
+    // 分配清零的物理帧时不持有进程锁
+    let object = shm::open(name, size, flags)?;
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    Ok(current.shm.add(object))
+}
+
+pub fn shm_resize(handle: usize, size: usize) -> Result<usize> {
+    let object = {
+        let list = process();
+        let current = list.current().ok_or(Error::new(ESRCH))?.read();
+        current.shm.get(handle)?.clone()
+    };
+    object.resize(size)?;
+    Ok(0)
+}
+
+/// 映射共享内存对象的前`len`字节，`flags`只支持`MAP_FIXED`
+pub fn shm_map(handle: usize, addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize> {
+    if flags & !MAP_FIXED != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    let object = current.shm.get(handle)?.clone();
+    let size = page_align(len)?;
+    if size > object.size() {
+        return Err(Error::new(EINVAL));
+    }
+    let start = place(&current, addr, len, flags)?;
+    let memory = object.map(start, size, prot_to_flags(prot))?;
+    current.vmas.insert(Region::shared_object(memory))?;
+    Ok(start.as_usize())
+}
+
+/// 关闭句柄，已经建立的映射仍然有效
+pub fn shm_close(handle: usize) -> Result<usize> {
+    let object = {
+        let list = process();
+        let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+        current.shm.remove(handle)?
+    };
+    // 最后一个引用可能在这里释放，此时不持有进程锁
+    drop(object);
+    Ok(0)
+}
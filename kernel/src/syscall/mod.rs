@@ -1,27 +1,76 @@
 use system::ia_32e::call_convention::InterruptStack;
-use system::result::Result;
+use system::syscall::data::{Stat, TimeSpec};
+use system::syscall::number::*;
+use system::syscall::result::{Error, ENOSYS, Result};
 
 use crate::process::process_mut;
+use crate::process::types::FileDescriptor;
+use crate::time::Instant;
+
+pub mod debug;
+pub mod fs;
+pub mod memory;
+pub mod process;
+pub mod time;
+pub mod validate;
 
 pub fn syscall(rax: usize, rbx: usize, rcx: usize, rdx: usize, es: usize, rflgas: usize, rbp: usize, stack: &mut InterruptStack) {
     #[inline(always)]
-    fn handler(rax: usize, rbx: usize, rcx: usize, rdx: usize, _es: usize, rflgas: usize, _rbp: usize, _stack: &mut InterruptStack) -> Result<usize> {
-        println!("rax:{}", rax);
-        if rax == 0x2000_0000 {
-            test_syscall(rbx, rcx, rdx, rflgas)
+    fn handler(rax: usize, rbx: usize, rcx: usize, rdx: usize, es: usize, rflgas: usize, _rbp: usize, _stack: &mut InterruptStack) -> Result<usize> {
+        match rax {
+            SYS_TEST => {
+                test_syscall(rbx, rcx, rdx, rflgas);
+                Ok(0)
+            }
+            SYS_TRACE => process::trace(rbx, rcx),
+            SYS_IOPORT => process::ioport(rbx, rcx),
+            SYS_SCHEME_REGISTER => fs::scheme_register(validate::validate_slice(rbx as *const u8, rcx)?),
+            SYS_READ => fs::read(FileDescriptor::from(rbx), validate::validate_slice_mut(rcx as *mut u8, rdx)?),
+            SYS_WRITE => fs::write(FileDescriptor::from(rbx), validate::validate_slice(rcx as *const u8, rdx)?),
+            SYS_OPEN => fs::open(validate::validate_slice(rbx as *const u8, rcx)?, rdx),
+            SYS_LSEEK => fs::lseek(FileDescriptor::from(rbx), rcx, rdx),
+            SYS_FSTAT => fs::fstat(FileDescriptor::from(rbx), &mut validate::validate_slice_mut(rcx as *mut Stat, 1)?[0]),
+            SYS_CLOSE => fs::close(FileDescriptor::from(rbx)),
+            SYS_DUP => fs::dup(FileDescriptor::from(rbx)),
+            SYS_DUP2 => fs::dup2(FileDescriptor::from(rbx), FileDescriptor::from(rcx)),
+            SYS_FCNTL => fs::fcntl(FileDescriptor::from(rbx), rcx, rdx),
+            SYS_BRK => memory::brk(rbx),
+            SYS_MMAP => memory::mmap(rbx, rcx, rdx, es),
+            SYS_MUNMAP => memory::munmap(rbx, rcx),
+            SYS_MPROTECT => memory::mprotect(rbx, rcx, rdx),
+            SYS_SHM_OPEN => memory::shm_open(validate::validate_slice(rbx as *const u8, rcx)?, rdx, es),
+            SYS_SHM_RESIZE => memory::shm_resize(rbx, rcx),
+            SYS_SHM_MAP => memory::shm_map(rbx, rcx, rdx, es, rflgas),
+            SYS_SHM_CLOSE => memory::shm_close(rbx),
+            SYS_CLOCK_GETTIME => time::clock_gettime(rbx, &mut validate::validate_slice_mut(rcx as *mut TimeSpec, 1)?[0]),
+            _ => Err(Error::new(ENOSYS)),
         }
-        Ok(0)
     }
 
+    let mut trace = None;
     {
         let process = process_mut();
         if let Some(cur) = process.current() {
             let mut cur_process = cur.write();
-            cur_process.syscall = Some((rax, rbx, rcx, rdx, rflgas, es))
+            cur_process.syscall = Some((rax, rbx, rcx, rdx, es, rflgas));
+            if cur_process.trace {
+                trace = Some(cur_process.id);
+            }
         }
     }
 
-    let _res = handler(rax, rbx, rcx, rdx, es, rflgas, rbp, stack);
+    // 只有开启跟踪时才读取时钟
+    let trace = trace.map(|id| {
+        serial_println!("[{}] {}", id.into(), debug::format_call(rax, rbx, rcx, rdx, es, rflgas));
+        (id, Instant::now())
+    });
+
+    let res = handler(rax, rbx, rcx, rdx, es, rflgas, rbp, stack);
+
+    if let Some((id, start)) = trace {
+        let elapsed = start.elapsed();
+        serial_println!("[{}] {} = {} ({} ns)", id.into(), debug::name(rax), debug::format_result(&res), elapsed.as_nanos());
+    }
 
     {
         let process = process_mut();
@@ -30,8 +79,10 @@ pub fn syscall(rax: usize, rbx: usize, rcx: usize, rdx: usize, es: usize, rflgas
             cur_process.syscall = None
         }
     }
+
+    stack.scratch.set_rax(Error::mux(res));
 }
 
 pub fn test_syscall(rbx: usize, rcx: usize, rdx: usize, rflgas: usize) {
     println!("rbx: {}, rcx: {},rdx: {},rflags:{}", rbx, rcx, rdx, rflgas)
-}
\ No newline at end of file
+}
@@ -0,0 +1,57 @@
+use core::sync::atomic::Ordering;
+
+use system::syscall::result::{EINVAL, EPERM, Error, ESRCH, Result};
+
+use crate::descriptor::switch_io_ports;
+
+use crate::process::{CURRENT_PROCESS, process};
+use crate::process::process::{Process, ROOT_UID};
+use crate::process::types::ProcessId;
+
+/// 开启或关闭进程的系统调用跟踪，`pid`为0时表示当前进程，跟踪其他进程需要特权
+pub fn trace(pid: usize, enable: usize) -> Result<usize> {
+    let current = CURRENT_PROCESS.load(Ordering::SeqCst);
+    let id = if pid == 0 { current } else { ProcessId::from(pid) };
+    if id != current {
+        let list = process();
+        require_privileged(&list.current().ok_or(Error::new(ESRCH))?.read())?;
+    }
+    set_trace(id, enable != 0)?;
+    Ok(0)
+}
+
+/// 在内核中开启或关闭指定进程的系统调用跟踪
+pub fn set_trace(id: ProcessId, enable: bool) -> Result<()> {
+    let list = process();
+    let lock = list.get(id).ok_or(Error::new(ESRCH))?;
+    lock.write().trace = enable;
+    Ok(())
+}
+
+/// 检查进程是否有特权
+pub fn require_privileged(process: &Process) -> Result<()> {
+    if process.uid == ROOT_UID {
+        Ok(())
+    } else {
+        Err(Error::new(EPERM))
+    }
+}
+
+/// I/O端口的数量，最后一个端口是0xFFFF
+const IO_PORT_COUNT: usize = 0x1_0000;
+
+/// 允许当前进程在Ring3访问`[start, start + count)`范围内的I/O端口
+pub fn ioport(start: usize, count: usize) -> Result<usize> {
+    let end = start.checked_add(count).ok_or(Error::new(EINVAL))?;
+    if count == 0 || end > IO_PORT_COUNT {
+        return Err(Error::new(EINVAL));
+    }
+    let list = process();
+    let mut current = list.current().ok_or(Error::new(ESRCH))?.write();
+    require_privileged(&current)?;
+    let range = start as u32..end as u32;
+    // 当前进程正在运行，直接修改TSS中的位图
+    switch_io_ports(&[], &[range.clone()]);
+    current.io_ports.push(range);
+    Ok(0)
+}
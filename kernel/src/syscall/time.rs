@@ -0,0 +1,17 @@
+use system::syscall::data::TimeSpec;
+use system::syscall::flag::{CLOCK_BOOTTIME, CLOCK_MONOTONIC};
+use system::syscall::result::{EINVAL, Error, Result};
+
+use crate::time::{monotonic, uptime};
+
+/// 将时钟`clock`的当前时间写入`time`
+pub fn clock_gettime(clock: usize, time: &mut TimeSpec) -> Result<usize> {
+    let now = match clock {
+        CLOCK_MONOTONIC => monotonic(),
+        CLOCK_BOOTTIME => uptime(),
+        _ => return Err(Error::new(EINVAL)),
+    };
+    time.tv_sec = now.as_secs() as i64;
+    time.tv_nsec = i64::from(now.subsec_nanos());
+    Ok(0)
+}
@@ -0,0 +1,73 @@
+use core::{mem, slice};
+
+use system::bits::flags::{PageFaultErrorCode, PageTableFlags};
+use system::ia_32e::VirtAddr;
+use system::ia_32e::paging::{Page, Page4KB, PageSize};
+use system::syscall::result::{EFAULT, Error, Result};
+
+use crate::memory::PAGE_TABLE;
+use crate::process::fault::resolve_fault;
+use crate::process::memory::USER_END;
+
+/// 检查`[address, address + size)`是否在用户空间中，且每一页都已映射为用户可访问（`write`时还需可写），
+/// 延迟映射和写时复制的页面会在这里分配
+fn validate(address: usize, size: usize, write: bool) -> Result<()> {
+    if size == 0 {
+        return Ok(());
+    }
+    let end = address.checked_add(size).filter(|&end| end as u64 <= USER_END).ok_or(Error::new(EFAULT))?;
+    let start_page: Page<Page4KB> = Page::include_address(VirtAddr::try_new(address as u64).map_err(|_| Error::new(EFAULT))?);
+    let end_page = Page::include_address(VirtAddr::try_new(end as u64 - 1).map_err(|_| Error::new(EFAULT))?);
+    let mut code = PageFaultErrorCode::USER_MODE;
+    if write {
+        code |= PageFaultErrorCode::CAUSED_BY_WRITE;
+    }
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    for page in Page::range_include(start_page, end_page) {
+        let flags = PAGE_TABLE.lock().page_flags(page);
+        let resolved = match flags {
+            None => resolve_fault(page.start_address(), code),
+            Some(flags) if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE) => false,
+            // 启用CR0.WP后内核写入只读页面也会缺页，而系统调用持有进程锁时缺页处理无法复制页面，需要提前复制
+            Some(flags) if write && !flags.contains(PageTableFlags::WRITABLE) => {
+                resolve_fault(page.start_address(), code | PageFaultErrorCode::PROTECTION_VIOLATION)
+            }
+            Some(_) => true,
+        };
+        // 缺页处理之后再检查一次，确认得到的映射满足要求
+        let valid = resolved && PAGE_TABLE.lock().page_flags(page).map_or(false, |flags| flags.contains(required));
+        if !valid {
+            return Err(Error::new(EFAULT));
+        }
+    }
+    Ok(())
+}
+
+/// 切片的字节数，溢出或者指针没有按`T`对齐时返回`EFAULT`
+fn slice_size<T>(address: usize, len: usize) -> Result<usize> {
+    if address % mem::align_of::<T>() != 0 {
+        return Err(Error::new(EFAULT));
+    }
+    len.checked_mul(mem::size_of::<T>()).ok_or(Error::new(EFAULT))
+}
+
+/// 将系统调用传入的指针转换为切片
+pub fn validate_slice<T>(ptr: *const T, len: usize) -> Result<&'static [T]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    validate(ptr as usize, slice_size::<T>(ptr as usize, len)?, false)?;
+    Ok(unsafe { slice::from_raw_parts(ptr, len) })
+}
+
+/// 将系统调用传入的指针转换为可变切片
+pub fn validate_slice_mut<T>(ptr: *mut T, len: usize) -> Result<&'static mut [T]> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    validate(ptr as usize, slice_size::<T>(ptr as usize, len)?, true)?;
+    Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
+}
@@ -0,0 +1,147 @@
+//! 纳秒精度的单调时钟
+//!
+//! CPU支持不变的TSC（invariant TSC）时以TSC为时钟源，启动时以HPET（没有HPET时使用8253）为基准校准其频率，
+//! 否则依次退化为HPET的主计数器和时钟中断的节拍数。返回的时间不会小于任何CPU上已经返回过的时间
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+
+use system::ia_32e::cpu::timer::Chips8253;
+use system::ia_32e::instructions::register::rdtsc;
+
+use crate::descriptor::UPTIME_TICKS;
+use crate::devices::hpet;
+use crate::devices::timer::TICK_NANOS;
+use crate::utils::SystemFunctionalCheck;
+
+/// 校准时等待的微秒数
+const CALIBRATE_MICROS: u64 = 10_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// 时钟中断的节拍数，精度为一个节拍
+    Ticks,
+    Hpet,
+    Tsc,
+}
+
+static SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Ticks as u8);
+/// TSC每秒的计数，没有校准时为0
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// 校准开始时的TSC，作为TSC时钟的零点
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// 调用`init`时的时间，作为启动时间的零点
+static BOOT_NANOS: AtomicU64 = AtomicU64::new(0);
+/// 已经返回过的最大时间，各CPU的时钟源存在微小偏差时保证时间单调递增
+static LAST_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// 校准TSC并选择时钟源，需要在初始化HPET之后调用
+pub fn init() {
+    let start = rdtsc();
+    calibration_wait(CALIBRATE_MICROS);
+    let frequency = (rdtsc() - start) * (1_000_000 / CALIBRATE_MICROS);
+    TSC_BASE.store(start, Ordering::SeqCst);
+    TSC_FREQUENCY.store(frequency, Ordering::SeqCst);
+    let source = if SystemFunctionalCheck::get_check_result().support_invariant_tsc() && frequency != 0 {
+        ClockSource::Tsc
+    } else if hpet::hpet().is_some() {
+        ClockSource::Hpet
+    } else {
+        ClockSource::Ticks
+    };
+    SOURCE.store(source as u8, Ordering::SeqCst);
+    BOOT_NANOS.store(Instant::now().0, Ordering::SeqCst);
+    println!("clock: {:?}, tsc {} Hz", source, frequency);
+}
+
+/// 以HPET为基准忙等待`micros`微秒，没有HPET时使用8253
+pub fn calibration_wait(micros: u64) {
+    match hpet::nanos() {
+        Some(begin) => while hpet::nanos().unwrap_or(u64::max_value()) - begin < micros * 1000 {},
+        None => unsafe { Chips8253::wait(micros) },
+    }
+}
+
+pub fn source() -> ClockSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => ClockSource::Hpet,
+        2 => ClockSource::Tsc,
+        _ => ClockSource::Ticks,
+    }
+}
+
+/// TSC每秒的计数，没有校准时为0
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+fn read_nanos() -> u64 {
+    match source() {
+        ClockSource::Tsc => {
+            let ticks = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+            (u128::from(ticks) * u128::from(NANOS_PER_SEC) / u128::from(tsc_frequency())) as u64
+        }
+        ClockSource::Hpet => hpet::nanos().unwrap_or(0),
+        ClockSource::Ticks => UPTIME_TICKS.load(Ordering::Relaxed) as u64 * TICK_NANOS,
+    }
+}
+
+/// 单调时钟上的一个时间点
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        let nanos = read_nanos();
+        let mut last = LAST_NANOS.load(Ordering::SeqCst);
+        while nanos > last {
+            match LAST_NANOS.compare_exchange_weak(last, nanos, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Instant(nanos),
+                Err(current) => last = current,
+            }
+        }
+        Instant(last)
+    }
+
+    /// 距离时钟源零点的时间
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// 从`earlier`到`self`经过的时间，`earlier`更晚时返回0
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(rhs.as_nanos() as u64))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// 单调时钟的当前时间
+pub fn monotonic() -> Duration {
+    Instant::now().as_duration()
+}
+
+/// 调用`init`以来经过的时间
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant(BOOT_NANOS.load(Ordering::Relaxed)))
+}
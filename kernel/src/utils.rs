@@ -1,10 +1,20 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use raw_cpuid::CpuId;
+use spin::Mutex;
 use system::ia_32e::{ApicInfo, PhysAddr, VirtAddr};
 use system::ia_32e::cpu::control::CR3;
-use system::ia_32e::paging::PageTable;
+use system::ia_32e::paging::{Page4KB, paging_levels, PageSize, PageTable};
 use system::ia_32e::paging::result::FrameError;
+use system::SystemInformation;
 
 use crate::descriptor::init_apic;
+use crate::memory::phys_to_virt;
+
+/// 内核映像相对于链接地址的偏移
+static KERNEL_SLIDE: AtomicU64 = AtomicU64::new(0);
+/// 地址随机化使用的xorshift状态，为0时表示关闭地址随机化
+static KASLR_STATE: Mutex<u64> = Mutex::new(0);
 
 #[derive(Default, Debug)]
 pub struct SystemFunctionalCheck {
@@ -12,6 +22,12 @@ pub struct SystemFunctionalCheck {
     x2apic: bool,
     acpi: bool,
     msr: bool,
+    page1gb: bool,
+    pcid: bool,
+    invpcid: bool,
+    pat: bool,
+    tsc_deadline: bool,
+    invariant_tsc: bool,
     initial_local_apic_id: u8,
 }
 
@@ -26,6 +42,9 @@ macro_rules! attr_impl {
 impl SystemFunctionalCheck {
     pub fn get_check_result() -> Self {
         let cpuid = CpuId::new();
+        let page1gb = cpuid.get_extended_function_info().map_or(false, |info| info.has_1gib_pages());
+        let invpcid = cpuid.get_extended_feature_info().map_or(false, |info| info.has_invpcid());
+        let invariant_tsc = cpuid.get_extended_function_info().map_or(false, |info| info.has_invariant_tsc());
         return match cpuid.get_feature_info() {
             Some(info) => {
                 Self {
@@ -33,6 +52,12 @@ impl SystemFunctionalCheck {
                     xapic: info.has_apic(),
                     acpi: info.has_acpi(),
                     msr: info.has_msr(),
+                    page1gb,
+                    pcid: info.has_pcid(),
+                    invpcid,
+                    pat: info.has_pat(),
+                    tsc_deadline: info.has_tsc_deadline(),
+                    invariant_tsc,
                     initial_local_apic_id: info.initial_local_apic_id(),
                 }
             }
@@ -44,9 +69,43 @@ impl SystemFunctionalCheck {
     attr_impl!(support_xapic,xapic,bool);
     attr_impl!(support_acpi,acpi,bool);
     attr_impl!(support_msr,msr,bool);
+    attr_impl!(support_1gb_page,page1gb,bool);
+    attr_impl!(support_pcid,pcid,bool);
+    attr_impl!(support_invpcid,invpcid,bool);
+    attr_impl!(support_pat,pat,bool);
+    attr_impl!(support_tsc_deadline,tsc_deadline,bool);
+    attr_impl!(support_invariant_tsc,invariant_tsc,bool);
     attr_impl!(initial_local_apic_id,initial_local_apic_id,u8);
 }
 
+/// 记录引导程序选择的内核滑动偏移和随机化种子，需要在分配堆空间之前调用
+pub fn init_kaslr(info: &SystemInformation) {
+    KERNEL_SLIDE.store(info.kernel_slide(), Ordering::SeqCst);
+    *KASLR_STATE.lock() = info.kaslr_seed();
+    if info.kaslr_seed() != 0 {
+        println!("kaslr: kernel slide {:#x}", info.kernel_slide());
+    }
+}
+
+/// 运行时地址减去该值得到链接地址，用于对照符号表
+pub fn kernel_slide() -> u64 {
+    KERNEL_SLIDE.load(Ordering::SeqCst)
+}
+
+/// 返回`[0, bound)`中的随机数，关闭地址随机化时总是返回0
+pub fn kaslr_random(bound: u64) -> u64 {
+    let mut state = KASLR_STATE.lock();
+    if *state == 0 || bound == 0 {
+        return 0;
+    }
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x % bound
+}
+
 pub fn loop_hlt() -> ! {
     loop {
         system::ia_32e::instructions::interrupt::hlt();
@@ -55,45 +114,35 @@ pub fn loop_hlt() -> ! {
 
 pub fn initialize_apic(info: ApicInfo) -> Result<(), &'static str> {
     let result = SystemFunctionalCheck::get_check_result();
-    if cfg!(x2apic) && !result.support_x2apic() {
+    if cfg!(feature = "x2apic") && !result.support_x2apic() {
         return Err("the cpu don't support x2apic");
     }
-    if cfg!(xapic) && !result.support_acpi() {
+    if cfg!(feature = "xapic") && !result.support_xapic() {
         return Err("the cpu don't support xapic");
     }
     init_apic(info);
     Ok(())
 }
 
-/// 此函数只能调用一次 避免给`＆mut`引用造成别名
-pub unsafe fn get_pml4t(offset: VirtAddr) -> &'static PageTable {
+/// 返回当前的最高级页表（5级分页时为5级页表），通过物理内存的线性映射访问
+pub unsafe fn get_pml4t() -> &'static PageTable {
     let (frame, _) = CR3::read();
-    let phys = frame.start_address();
-    let virt = offset + phys.as_u64();
-    let ptr: *mut PageTable = virt.as_mut_ptr();
-    &mut *ptr
+    &*phys_to_virt(frame.start_address()).as_ptr()
 }
 
-pub unsafe fn translate_address(addr: VirtAddr, offset: VirtAddr) -> Option<PhysAddr> {
-    __translate_address(addr, offset)
-}
-
-fn __translate_address(addr: VirtAddr, offset: VirtAddr) -> Option<PhysAddr> {
-    let (frame, _) = CR3::read();
-    let index = &[addr.page4_index(), addr.page3_index(), addr.page2_index(), addr.page1_index()];
-    let mut frame = frame;
-    for &i in index.iter() {
-        let virt = offset + frame.start_address().as_u64();
-        let ptr: *const PageTable = virt.as_ptr();
+/// 通过物理内存的线性映射遍历当前页表，将虚拟地址转换为物理地址
+pub fn translate_address(addr: VirtAddr) -> Option<PhysAddr> {
+    let (mut frame, _) = CR3::read();
+    for level in (1..=paging_levels()).rev() {
+        let ptr: *const PageTable = phys_to_virt(frame.start_address()).as_ptr();
         let table = unsafe { &*ptr };
-        let entry = &table[i];
+        let entry = &table[addr.page_index(level)];
+        // 该级页表项映射的大小
+        let size = Page4KB::P_SIZE << (9 * (level - 1));
         frame = match entry.frame() {
             Ok(f) => f,
             Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => {
-                println!("addr: {:#?} is already mapped", addr.as_u64());
-                return None;
-            }
+            Err(FrameError::HugeFrame) => return Some(entry.addr() + (addr.as_u64() & (size - 1))),
         }
     }
 
@@ -116,6 +116,7 @@ bitflags! {
         /// 表示该页是否能在用户模式访问 置1时用户模式，置0为内核模式
         const USER_ACCESSIBLE = 1 << 2;
         /// 页级写穿标志位， 如果置1表示写穿`write-through`用于缓存 置0表示 回写`write-back`
+        /// 写入PAT之后作为PAT索引的一部分，见`cpu::pat::CacheType`
         const WRITE_THROUGH =   1 << 3;
         /// 禁止页级缓存标志位 置1时表示页不能缓存，否则表示页可以缓存
         const NO_CACHE =        1 << 4;
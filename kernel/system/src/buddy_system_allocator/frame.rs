@@ -129,6 +129,16 @@ impl FrameAllocator {
 
         self.allocated -= size;
     }
+
+    /// Return the number of frames allocated (rounded up to power of two)
+    pub fn stats_allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Return the total number of frames added to the allocator
+    pub fn stats_total(&self) -> usize {
+        self.total
+    }
 }
 
 /// A locked version of `FrameAllocator`
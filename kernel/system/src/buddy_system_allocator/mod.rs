@@ -290,19 +290,24 @@ unsafe impl GlobalAlloc for LockedHeap {
 /// Create a locked heap:
 /// ```
 /// use buddy_system_allocator::*;
+/// use core::alloc::Layout;
 /// use system::buddy_system_allocator::{LockedHeapWithRescue, Heap};
-/// let heap = LockedHeapWithRescue::new(|heap: &mut Heap| {});
+/// let heap = LockedHeapWithRescue::new(|heap: &mut Heap, layout: &Layout| {});
 /// ```
 ///
-/// Before oom, the allocator will try to call rescue function and try for one more time.
+/// Before oom, the allocator will try to call rescue function with the failed layout
+/// and try for one more time.
 pub struct LockedHeapWithRescue {
     inner: Mutex<Heap>,
-    rescue: fn(&mut Heap),
+    rescue: fn(&mut Heap, &Layout),
 }
 
+unsafe impl Sync for LockedHeapWithRescue{}
+unsafe impl Send for LockedHeapWithRescue{}
+
 impl LockedHeapWithRescue {
     /// Creates an empty heap
-    pub const fn new(rescue: fn(&mut Heap)) -> LockedHeapWithRescue {
+    pub const fn new(rescue: fn(&mut Heap, &Layout)) -> LockedHeapWithRescue {
         LockedHeapWithRescue {
             inner: Mutex::new(Heap::new()),
             rescue,
@@ -324,7 +329,7 @@ unsafe impl GlobalAlloc for LockedHeapWithRescue {
         match inner.alloc(layout) {
             Ok(allocation) => allocation.as_ptr(),
             Err(_) => {
-                (self.rescue)(&mut inner);
+                (self.rescue)(&mut inner, &layout);
                 inner
                     .alloc(layout)
                     .ok()
@@ -0,0 +1,264 @@
+//! 调试用的分配器，用于查找内核堆的泄漏和越界访问
+//!
+//! 每次分配都在块前面保存分配信息，并在用户数据两侧加上红区(redzone)，释放时检查红区是否被改写。
+//! 释放的内存被填充为毒值并放入隔离区，延迟一段时间后才真正释放，
+//! 离开隔离区时检查毒值，以此发现释放后写入(use-after-free)。
+//! 所有存活的分配串成链表，可以列出某个检查点之后分配且仍未释放的内存。
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+
+use crate::Mutex;
+
+/// 记录的调用栈深度
+pub const CALLER_DEPTH: usize = 4;
+/// 用户数据两侧红区的最小字节数
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xFD;
+/// 释放后填充的毒值
+const FREE_POISON: u8 = 0x6B;
+/// 隔离区最多保存的块数和字节数
+const QUARANTINE_LEN: usize = 256;
+const QUARANTINE_BYTES: usize = 0x10_0000;
+
+const LIVE_MAGIC: u64 = 0xA110_CA7E_D0D0_CAFE;
+const FREED_MAGIC: u64 = 0xF4EE_D0D0_DEAD_BEEF;
+
+/// 位于每个块的起始位置
+#[repr(C)]
+struct Header {
+    magic: u64,
+    size: usize,
+    align: usize,
+    seq: u64,
+    callers: [usize; CALLER_DEPTH],
+    prev: *mut Header,
+    next: *mut Header,
+}
+
+/// 一次存活的分配
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    pub ptr: usize,
+    pub size: usize,
+    pub align: usize,
+    /// 分配序号，与`DebugAllocator::checkpoint`的返回值比较
+    pub seq: u64,
+    /// 分配时的返回地址，从内向外，未知的部分为0
+    pub callers: [usize; CALLER_DEPTH],
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugStats {
+    pub live: usize,
+    pub live_bytes: usize,
+    pub quarantined: usize,
+    pub quarantined_bytes: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Quarantined {
+    ptr: usize,
+    layout: Layout,
+}
+
+struct State {
+    head: *mut Header,
+    seq: u64,
+    stats: DebugStats,
+    quarantine: [Option<Quarantined>; QUARANTINE_LEN],
+    /// 隔离区是一个环形队列，`first`是最早放入的块
+    first: usize,
+}
+
+unsafe impl Send for State {}
+
+/// 用户数据相对于块起始位置的偏移，以及整个块的布局
+fn block_layout(layout: &Layout) -> (usize, Layout) {
+    let align = max(layout.align(), 16);
+    let offset = (size_of::<Header>() + REDZONE_SIZE + align - 1) & !(align - 1);
+    let size = offset + layout.size() + REDZONE_SIZE;
+    (offset, Layout::from_size_align(size, align).expect("invalid debug block layout"))
+}
+
+fn is_filled(start: *const u8, len: usize, value: u8) -> bool {
+    (0..len).all(|i| unsafe { *start.add(i) } == value)
+}
+
+pub struct DebugAllocator {
+    backing: &'static (dyn GlobalAlloc + Sync),
+    /// 将调用栈上的返回地址填入给定的数组
+    backtrace: fn(&mut [usize; CALLER_DEPTH]),
+    state: Mutex<State>,
+}
+
+impl DebugAllocator {
+    pub fn new(backing: &'static (dyn GlobalAlloc + Sync), backtrace: fn(&mut [usize; CALLER_DEPTH])) -> Self {
+        Self {
+            backing,
+            backtrace,
+            state: Mutex::new(State {
+                head: null_mut(),
+                seq: 0,
+                stats: DebugStats { live: 0, live_bytes: 0, quarantined: 0, quarantined_bytes: 0 },
+                quarantine: [None; QUARANTINE_LEN],
+                first: 0,
+            }),
+        }
+    }
+
+    /// 返回当前的分配序号，之后的分配都不小于该值
+    pub fn checkpoint(&self) -> u64 {
+        self.state.lock().seq
+    }
+
+    /// 对`checkpoint`之后分配并且仍然存活的内存调用`f`，
+    /// 调用期间持有分配器的锁，`f`中不能分配内存
+    pub fn for_each_leak<F>(&self, checkpoint: u64, mut f: F) where F: FnMut(&Allocation) {
+        let state = self.state.lock();
+        let mut current = state.head;
+        while !current.is_null() {
+            let header = unsafe { &*current };
+            if header.seq >= checkpoint {
+                let layout = Layout::from_size_align(header.size, header.align).expect("corrupted heap header");
+                let (offset, _) = block_layout(&layout);
+                f(&Allocation {
+                    ptr: current as usize + offset,
+                    size: header.size,
+                    align: header.align,
+                    seq: header.seq,
+                    callers: header.callers,
+                });
+            }
+            current = header.next;
+        }
+    }
+
+    pub fn stats(&self) -> DebugStats {
+        self.state.lock().stats
+    }
+
+    /// 检查并释放隔离区中的所有块
+    pub fn flush_quarantine(&self) {
+        loop {
+            // 先释放锁再归还给后备分配器
+            let block = Self::pop_quarantine(&mut self.state.lock());
+            match block {
+                Some(block) => unsafe { self.release(block) },
+                None => break,
+            }
+        }
+    }
+
+    fn pop_quarantine(state: &mut State) -> Option<Quarantined> {
+        let block = state.quarantine[state.first].take()?;
+        state.first = (state.first + 1) % QUARANTINE_LEN;
+        state.stats.quarantined -= 1;
+        state.stats.quarantined_bytes -= block.layout.size();
+        Some(block)
+    }
+
+    /// 块离开隔离区时检查毒值，然后归还给后备分配器
+    unsafe fn release(&self, block: Quarantined) {
+        let header = block.ptr as *mut Header;
+        let (offset, layout) = block_layout(&Layout::from_size_align_unchecked((*header).size, (*header).align));
+        let data = (block.ptr + offset) as *const u8;
+        if (*header).magic != FREED_MAGIC || !is_filled(data, (*header).size, FREE_POISON) {
+            panic!("heap: use after free at {:#x}, size {} allocated by {:x?}", data as usize, (*header).size, (*header).callers);
+        }
+        self.backing.dealloc(block.ptr as *mut u8, layout);
+    }
+}
+
+unsafe impl Sync for DebugAllocator {}
+
+unsafe impl GlobalAlloc for DebugAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (offset, block) = block_layout(&layout);
+        let ptr = self.backing.alloc(block);
+        if ptr.is_null() {
+            return ptr;
+        }
+        let data = ptr.add(offset);
+        let header = ptr as *mut Header;
+        let mut callers = [0; CALLER_DEPTH];
+        (self.backtrace)(&mut callers);
+        ptr::write_bytes(ptr.add(size_of::<Header>()), REDZONE_BYTE, offset - size_of::<Header>());
+        ptr::write_bytes(data.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+
+        let mut state = self.state.lock();
+        header.write(Header {
+            magic: LIVE_MAGIC,
+            size: layout.size(),
+            align: layout.align(),
+            seq: state.seq,
+            callers,
+            prev: null_mut(),
+            next: state.head,
+        });
+        if !state.head.is_null() {
+            (*state.head).prev = header;
+        }
+        state.head = header;
+        state.seq += 1;
+        state.stats.live += 1;
+        state.stats.live_bytes += layout.size();
+        data
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (offset, block) = block_layout(&layout);
+        let start = ptr.sub(offset);
+        let header = start as *mut Header;
+        match (*header).magic {
+            LIVE_MAGIC => {}
+            FREED_MAGIC => panic!("heap: double free at {:#x}, allocated by {:x?}", ptr as usize, (*header).callers),
+            _ => panic!("heap: invalid free or corrupted header at {:#x}", ptr as usize),
+        }
+        if (*header).size != layout.size() || (*header).align != layout.align() {
+            panic!("heap: free {:#x} with layout {:?}, allocated with size {} align {}",
+                   ptr as usize, layout, (*header).size, (*header).align);
+        }
+        let left = start.add(size_of::<Header>());
+        if !is_filled(left, offset - size_of::<Header>(), REDZONE_BYTE) {
+            panic!("heap: underflow before {:#x}, allocated by {:x?}", ptr as usize, (*header).callers);
+        }
+        if !is_filled(ptr.add(layout.size()), REDZONE_SIZE, REDZONE_BYTE) {
+            panic!("heap: overflow after {:#x} size {}, allocated by {:x?}", ptr as usize, layout.size(), (*header).callers);
+        }
+
+        let mut state = self.state.lock();
+        if (*header).prev.is_null() {
+            state.head = (*header).next;
+        } else {
+            (*(*header).prev).next = (*header).next;
+        }
+        if !(*header).next.is_null() {
+            (*(*header).next).prev = (*header).prev;
+        }
+        state.stats.live -= 1;
+        state.stats.live_bytes -= layout.size();
+        drop(state);
+        (*header).magic = FREED_MAGIC;
+        ptr::write_bytes(ptr, FREE_POISON, layout.size());
+
+        // 隔离区已满时先释放最早的块，归还给后备分配器时不持有锁
+        let mut state = self.state.lock();
+        while state.stats.quarantined == QUARANTINE_LEN || state.stats.quarantined_bytes + block.size() > QUARANTINE_BYTES {
+            match Self::pop_quarantine(&mut state) {
+                Some(old) => {
+                    drop(state);
+                    self.release(old);
+                    state = self.state.lock();
+                }
+                None => break,
+            }
+        }
+        let last = (state.first + state.stats.quarantined) % QUARANTINE_LEN;
+        state.quarantine[last] = Some(Quarantined { ptr: start as usize, layout: block });
+        state.stats.quarantined += 1;
+        state.stats.quarantined_bytes += block.size();
+    }
+}
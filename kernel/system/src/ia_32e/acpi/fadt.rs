@@ -0,0 +1,102 @@
+//! FADT（Fixed ACPI Description Table），签名为`FACP`
+use crate::ia_32e::acpi::{GenericAddress, Table};
+
+/// 各字段在表中的偏移，ACPI 1.0的表较短，之后添加的字段可能不存在
+const FIRMWARE_CTRL: usize = 36;
+const DSDT: usize = 40;
+const SCI_INT: usize = 46;
+const SMI_CMD: usize = 48;
+const PM1A_EVT_BLK: usize = 56;
+const PM1A_CNT_BLK: usize = 64;
+const PM_TMR_BLK: usize = 76;
+const CENTURY: usize = 108;
+const IAPC_BOOT_ARCH: usize = 109;
+const FLAGS: usize = 112;
+const RESET_REG: usize = 116;
+const RESET_VALUE: usize = 128;
+const X_FIRMWARE_CTRL: usize = 132;
+const X_DSDT: usize = 140;
+const X_PM_TMR_BLK: usize = 208;
+
+/// `flags`中PM定时器是32位的
+const TMR_VAL_EXT: u32 = 1 << 8;
+/// `flags`中支持通过`reset_register`重启
+const RESET_REG_SUP: u32 = 1 << 10;
+/// `flags`中没有PM定时器等固定的硬件
+const HW_REDUCED_ACPI: u32 = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// FACS的物理地址
+    pub firmware_ctrl: u64,
+    /// DSDT的物理地址
+    pub dsdt: u64,
+    /// SCI中断在8259A模式下的中断号
+    pub sci_interrupt: u16,
+    /// 写入`acpi_enable`后进入ACPI模式的I/O端口，为0时已经处于ACPI模式
+    pub smi_command_port: u32,
+    pub pm1a_event_block: u32,
+    pub pm1a_control_block: u32,
+    /// 频率为3.579545MHz的PM定时器
+    pub pm_timer: Option<GenericAddress>,
+    /// CMOS中世纪的寄存器编号，为0时不支持
+    pub century: u8,
+    /// IA-PC启动架构的flags，ACPI 1.0中该字段保留为0
+    pub boot_arch: u16,
+    pub flags: u32,
+    /// 写入后重启系统的寄存器以及写入的值
+    pub reset: Option<(GenericAddress, u8)>,
+}
+
+impl Fadt {
+    pub(super) fn parse(table: &Table) -> Self {
+        let flags = table.read::<u32>(FLAGS).unwrap_or(0);
+        // 64位的地址不为0时优先使用
+        let address = |legacy: usize, extended: usize| {
+            table.read::<u64>(extended).filter(|&address| address != 0)
+                .unwrap_or_else(|| u64::from(table.read::<u32>(legacy).unwrap_or(0)))
+        };
+        let pm_timer = table.read::<GenericAddress>(X_PM_TMR_BLK)
+            .filter(|timer| timer.address != 0)
+            .or_else(|| table.read::<u32>(PM_TMR_BLK).filter(|&port| port != 0).map(|port| GenericAddress {
+                address_space: GenericAddress::SYSTEM_IO,
+                bit_width: 32,
+                bit_offset: 0,
+                access_size: 0,
+                address: u64::from(port),
+            }))
+            .filter(|_| flags & HW_REDUCED_ACPI == 0);
+        let reset = match (table.read::<GenericAddress>(RESET_REG), table.read::<u8>(RESET_VALUE)) {
+            (Some(register), Some(value)) if flags & RESET_REG_SUP != 0 => Some((register, value)),
+            _ => None,
+        };
+        Self {
+            firmware_ctrl: address(FIRMWARE_CTRL, X_FIRMWARE_CTRL),
+            dsdt: address(DSDT, X_DSDT),
+            sci_interrupt: table.read::<u16>(SCI_INT).unwrap_or(0),
+            smi_command_port: table.read::<u32>(SMI_CMD).unwrap_or(0),
+            pm1a_event_block: table.read::<u32>(PM1A_EVT_BLK).unwrap_or(0),
+            pm1a_control_block: table.read::<u32>(PM1A_CNT_BLK).unwrap_or(0),
+            pm_timer,
+            century: table.read::<u8>(CENTURY).unwrap_or(0),
+            boot_arch: if table.revision() >= 2 { table.read::<u16>(IAPC_BOOT_ARCH).unwrap_or(0) } else { 0 },
+            flags,
+            reset,
+        }
+    }
+
+    /// PM定时器的计数是32位的，否则只有24位
+    pub fn pm_timer_32bit(&self) -> bool {
+        self.flags & TMR_VAL_EXT != 0
+    }
+
+    /// 存在8042键盘控制器，ACPI 1.0无法判断时返回true
+    pub fn has_8042(&self) -> bool {
+        self.boot_arch == 0 || self.boot_arch & (1 << 1) != 0
+    }
+
+    /// 存在CMOS中的实时时钟
+    pub fn has_cmos_rtc(&self) -> bool {
+        self.boot_arch & (1 << 5) == 0
+    }
+}
@@ -0,0 +1,48 @@
+//! HPET（High Precision Event Timer）描述表，签名为`HPET`
+use crate::ia_32e::acpi::{GenericAddress, Table};
+
+const EVENT_TIMER_BLOCK_ID: usize = 36;
+const BASE_ADDRESS: usize = 40;
+const HPET_NUMBER: usize = 52;
+const MIN_TICK: usize = 53;
+const PAGE_PROTECTION: usize = 55;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    /// 比较器（定时器）的数量
+    pub comparator_count: u8,
+    /// 主计数器是64位的
+    pub counter_64bit: bool,
+    /// 支持替代8259A和RTC的legacy中断路由
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// 寄存器的物理地址
+    pub base_address: u64,
+    pub hpet_number: u8,
+    /// 周期模式下不丢失中断的最小计数
+    pub min_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    /// 寄存器不在内存地址空间中时返回`None`
+    pub(super) fn parse(table: &Table) -> Option<Self> {
+        let id = table.read::<u32>(EVENT_TIMER_BLOCK_ID)?;
+        let base = table.read::<GenericAddress>(BASE_ADDRESS)?;
+        if base.address_space != GenericAddress::SYSTEM_MEMORY || base.address == 0 {
+            return None;
+        }
+        Some(Self {
+            hardware_revision: id as u8,
+            comparator_count: ((id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: id & (1 << 13) != 0,
+            legacy_replacement: id & (1 << 15) != 0,
+            pci_vendor_id: (id >> 16) as u16,
+            base_address: base.address,
+            hpet_number: table.read::<u8>(HPET_NUMBER).unwrap_or(0),
+            min_tick: table.read::<u16>(MIN_TICK).unwrap_or(0),
+            page_protection: table.read::<u8>(PAGE_PROTECTION).unwrap_or(0),
+        })
+    }
+}
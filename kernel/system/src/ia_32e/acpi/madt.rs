@@ -0,0 +1,154 @@
+//! MADT（Multiple APIC Description Table），签名为`APIC`
+use alloc::vec::Vec;
+
+use crate::bits::IrqFlags;
+use crate::ia_32e::acpi::Table;
+
+/// 头部之后依次是local APIC的物理地址和flags，之后是不定长的各项
+const LOCAL_APIC_ADDRESS: usize = 36;
+const FLAGS: usize = 40;
+const ENTRIES: usize = 44;
+
+const PROCESSOR_LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_OVERRIDE: u8 = 2;
+const LOCAL_APIC_NMI: u8 = 4;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const PROCESSOR_LOCAL_X2APIC: u8 = 9;
+
+/// 一个处理器的local APIC
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    /// ACPI处理器UID
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// 处理器已经可用
+    pub enabled: bool,
+    /// 处理器不可用，但可以在运行时启用
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    /// 寄存器的物理地址
+    pub address: u64,
+    /// 第一项重定向表项对应的全局系统中断（GSI）
+    pub gsi_base: u32,
+}
+
+/// ISA中断到全局系统中断（GSI）的重定向
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags，第0-1位是极性，第2-3位是触发方式
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    /// 转换为I/O APIC重定向表项的flags，取值为0时使用ISA总线的默认值：高电平有效，边沿触发
+    pub fn irq_flags(&self) -> IrqFlags {
+        let mut flags = IrqFlags::empty();
+        if self.flags & 0b11 == 0b11 {
+            flags |= IrqFlags::LOW_ACTIVE;
+        }
+        if (self.flags >> 2) & 0b11 == 0b11 {
+            flags |= IrqFlags::LEVEL_TRIGGERED;
+        }
+        flags
+    }
+}
+
+/// 连接到local APIC的LINT引脚上的NMI
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicNmi {
+    /// 0xFF表示所有处理器
+    pub processor_uid: u8,
+    pub flags: u16,
+    /// LINT0或者LINT1
+    pub lint: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// local APIC的物理地址，存在64位的覆盖项时使用覆盖项的地址
+    pub local_apic_address: u64,
+    /// 同时安装了8259A，使用APIC之前需要将其屏蔽
+    pub pc_at_compatible: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    pub(super) fn parse(table: &Table) -> Self {
+        let mut madt = Madt {
+            local_apic_address: u64::from(table.read::<u32>(LOCAL_APIC_ADDRESS).unwrap_or(0)),
+            pc_at_compatible: table.read::<u32>(FLAGS).map_or(false, |flags| flags & 1 != 0),
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+        let mut offset = ENTRIES;
+        // 每一项的第0字节是类型，第1字节是包括这两个字节在内的长度
+        while let (Some(ty), Some(len)) = (table.read::<u8>(offset), table.read::<u8>(offset + 1)) {
+            let len = len as usize;
+            if len < 2 || offset + len > table.len() {
+                break;
+            }
+            let u8_at = |at: usize| table.read::<u8>(offset + at).unwrap_or(0);
+            let u16_at = |at: usize| table.read::<u16>(offset + at).unwrap_or(0);
+            let u32_at = |at: usize| table.read::<u32>(offset + at).unwrap_or(0);
+            match ty {
+                PROCESSOR_LOCAL_APIC => madt.processors.push(Processor::new(u32::from(u8_at(2)), u32::from(u8_at(3)), u32_at(4))),
+                PROCESSOR_LOCAL_X2APIC => madt.processors.push(Processor::new(u32_at(12), u32_at(4), u32_at(8))),
+                IO_APIC => madt.io_apics.push(IoApicEntry {
+                    id: u8_at(2),
+                    address: u64::from(u32_at(4)),
+                    gsi_base: u32_at(8),
+                }),
+                INTERRUPT_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    bus: u8_at(2),
+                    irq: u8_at(3),
+                    gsi: u32_at(4),
+                    flags: u16_at(8),
+                }),
+                LOCAL_APIC_NMI => madt.nmis.push(LocalApicNmi {
+                    processor_uid: u8_at(2),
+                    flags: u16_at(3),
+                    lint: u8_at(5),
+                }),
+                LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    if let Some(address) = table.read::<u64>(offset + 4) {
+                        madt.local_apic_address = address;
+                    }
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+        madt
+    }
+
+    /// ISA中断`irq`对应的全局系统中断以及重定向表项的flags，没有覆盖项时两者编号相同
+    pub fn isa_irq(&self, irq: u8) -> (u32, IrqFlags) {
+        self.overrides.iter()
+            .find(|entry| entry.bus == 0 && entry.irq == irq)
+            .map_or((u32::from(irq), IrqFlags::empty()), |entry| (entry.gsi, entry.irq_flags()))
+    }
+}
+
+impl Processor {
+    fn new(processor_uid: u32, apic_id: u32, flags: u32) -> Self {
+        Self {
+            processor_uid,
+            apic_id,
+            enabled: flags & 1 != 0,
+            online_capable: flags & 2 != 0,
+        }
+    }
+}
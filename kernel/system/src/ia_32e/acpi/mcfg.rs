@@ -0,0 +1,51 @@
+//! MCFG表，描述PCI Express的内存映射配置空间（ECAM），签名为`MCFG`
+use alloc::vec::Vec;
+
+use crate::ia_32e::acpi::Table;
+
+/// 头部之后有8字节保留，之后是16字节的各项
+const ENTRIES: usize = 44;
+const ENTRY_SIZE: usize = 16;
+
+/// 一段PCI段组中连续总线的配置空间
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    /// 总线0对应的配置空间的物理地址
+    pub base_address: u64,
+    /// PCI段组编号
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub(super) fn parse(table: &Table) -> Self {
+        let mut entries = Vec::new();
+        let mut offset = ENTRIES;
+        while offset + ENTRY_SIZE <= table.len() {
+            entries.push(McfgEntry {
+                base_address: table.read::<u64>(offset).unwrap_or(0),
+                segment: table.read::<u16>(offset + 8).unwrap_or(0),
+                start_bus: table.read::<u8>(offset + 10).unwrap_or(0),
+                end_bus: table.read::<u8>(offset + 11).unwrap_or(0),
+            });
+            offset += ENTRY_SIZE;
+        }
+        Self { entries }
+    }
+
+    /// 段组0中`bus:device.function`的4KB配置空间的物理地址，总线不在任何一项中时返回`None`
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<u64> {
+        self.entries.iter()
+            .find(|entry| entry.segment == 0 && (entry.start_bus..=entry.end_bus).contains(&bus))
+            .map(|entry| entry.base_address
+                + (u64::from(bus) << 20)
+                + (u64::from(device & 0x1f) << 15)
+                + (u64::from(function & 0x7) << 12))
+    }
+}
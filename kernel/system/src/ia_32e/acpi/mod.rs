@@ -0,0 +1,185 @@
+//! ACPI表的查找和解析
+//!
+//! 从RSDP开始遍历RSDT（ACPI 1.0）或者XSDT（ACPI 2.0以上），校验每张表的校验和，
+//! 并将MADT、FADT、HPET和MCFG解析为对应的结构体。表都位于物理内存中，通过调用者提供的`map`访问：
+//! `map(phys, len)`返回物理内存`[phys, phys + len)`的虚拟地址，调用者负责在需要时建立映射
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+use core::slice;
+
+use crate::ia_32e::{PhysAddr, VirtAddr};
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::{InterruptOverride, IoApicEntry, LocalApicNmi, Madt, Processor};
+pub use mcfg::{Mcfg, McfgEntry};
+pub use rsdp::Rsdp;
+
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod rsdp;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AcpiError {
+    /// 没有找到RSDP
+    RsdpNotFound,
+    /// RSDP的签名或者校验和错误
+    InvalidRsdp,
+    /// 根表不是RSDT或者XSDT
+    InvalidRootTable([u8; 4]),
+    /// 表的长度或者校验和错误
+    InvalidChecksum([u8; 4]),
+}
+
+/// 所有ACPI表共有的头部
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// 包括头部在内整张表的字节数
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// 通用地址结构（GAS），描述位于内存或者I/O端口等地址空间中的寄存器
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+}
+
+/// 一张通过了校验的ACPI表
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    header: SdtHeader,
+    addr: VirtAddr,
+}
+
+impl Table {
+    /// 读取物理地址`phys`处的表，长度小于头部或者校验和错误时返回错误
+    ///
+    /// # Safety
+    ///
+    /// `map`返回的地址必须可以访问所要求的长度
+    pub unsafe fn new<F>(phys: PhysAddr, map: &F) -> Result<Self, AcpiError> where F: Fn(PhysAddr, usize) -> VirtAddr {
+        // 先读取头部得到表的长度，再映射整张表
+        let header: SdtHeader = read_unaligned(map(phys, size_of::<SdtHeader>()).as_ptr());
+        let length = header.length as usize;
+        if length < size_of::<SdtHeader>() {
+            return Err(AcpiError::InvalidChecksum(header.signature));
+        }
+        let addr = map(phys, length);
+        if !checksum(addr, length) {
+            return Err(AcpiError::InvalidChecksum(header.signature));
+        }
+        Ok(Self { header, addr })
+    }
+
+    pub fn header(&self) -> SdtHeader {
+        self.header
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        self.header.signature
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.header.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.length as usize
+    }
+
+    /// 读取表中偏移`offset`处的值，偏移从头部开始计算，超出表的长度时返回`None`
+    pub fn read<T: Copy>(&self, offset: usize) -> Option<T> {
+        if offset + size_of::<T>() > self.len() {
+            return None;
+        }
+        Some(unsafe { read_unaligned((self.addr.as_u64() + offset as u64) as *const T) })
+    }
+}
+
+/// 所有字节相加（忽略溢出）为0时校验和正确
+unsafe fn checksum(addr: VirtAddr, len: usize) -> bool {
+    slice::from_raw_parts(addr.as_ptr::<u8>(), len).iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// 解析得到的ACPI信息，固件没有提供或者校验失败的表为`None`
+#[derive(Debug)]
+pub struct Acpi {
+    /// RSDP的版本，0表示ACPI 1.0
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+    /// 根表中所有通过校验的表的签名和物理地址
+    pub tables: Vec<([u8; 4], PhysAddr)>,
+}
+
+impl Acpi {
+    /// 从物理地址`rsdp`处的RSDP开始解析，有XSDT时优先使用XSDT。校验和错误的表会被跳过
+    ///
+    /// # Safety
+    ///
+    /// `map`返回的地址必须可以访问所要求的长度
+    pub unsafe fn parse<F>(rsdp: PhysAddr, map: F) -> Result<Self, AcpiError> where F: Fn(PhysAddr, usize) -> VirtAddr {
+        let rsdp = Rsdp::read(map(rsdp, size_of::<Rsdp>()))?;
+        let (root, entry_size, expected) = match rsdp.xsdt_address() {
+            Some(xsdt) => (Table::new(xsdt, &map)?, 8, *b"XSDT"),
+            None => (Table::new(rsdp.rsdt_address(), &map)?, 4, *b"RSDT"),
+        };
+        if root.signature() != expected {
+            return Err(AcpiError::InvalidRootTable(root.signature()));
+        }
+
+        let mut acpi = Acpi {
+            revision: rsdp.revision(),
+            oem_id: rsdp.oem_id(),
+            madt: None,
+            fadt: None,
+            hpet: None,
+            mcfg: None,
+            tables: Vec::new(),
+        };
+        let count = (root.len() - size_of::<SdtHeader>()) / entry_size;
+        for i in 0..count {
+            let offset = size_of::<SdtHeader>() + i * entry_size;
+            let phys = if entry_size == 8 { root.read::<u64>(offset) } else { root.read::<u32>(offset).map(u64::from) };
+            let phys = PhysAddr::new(phys.expect("entry out of root table"));
+            let table = match Table::new(phys, &map) {
+                Ok(table) => table,
+                Err(_) => continue,
+            };
+            acpi.tables.push((table.signature(), phys));
+            match &table.signature() {
+                b"APIC" => acpi.madt = Some(Madt::parse(&table)),
+                b"FACP" => acpi.fadt = Some(Fadt::parse(&table)),
+                b"HPET" => acpi.hpet = Hpet::parse(&table),
+                b"MCFG" => acpi.mcfg = Some(Mcfg::parse(&table)),
+                _ => {}
+            }
+        }
+        Ok(acpi)
+    }
+}
@@ -0,0 +1,90 @@
+use core::mem::{size_of, zeroed};
+use core::ptr::{copy_nonoverlapping, read_unaligned};
+
+use crate::ia_32e::{PhysAddr, VirtAddr};
+use crate::ia_32e::acpi::{AcpiError, checksum};
+
+/// ACPI 1.0的RSDP只有前20字节
+const V1_SIZE: usize = 20;
+
+/// 根系统描述指针（RSDP），指向RSDT和XSDT
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // 以下字段从ACPI 2.0开始存在
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+impl Rsdp {
+    /// 读取`addr`处的RSDP，签名或者校验和错误时返回`AcpiError::InvalidRsdp`
+    ///
+    /// # Safety
+    ///
+    /// `addr`开始的20字节（ACPI 2.0以上为`length`字节）必须可以访问
+    pub unsafe fn read(addr: VirtAddr) -> Result<Self, AcpiError> {
+        let ptr = addr.as_ptr::<u8>();
+        if &read_unaligned(ptr as *const [u8; 8]) != b"RSD PTR " || !checksum(addr, V1_SIZE) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        let mut rsdp: Rsdp = zeroed();
+        copy_nonoverlapping(ptr, &mut rsdp as *mut Rsdp as *mut u8, V1_SIZE);
+        if rsdp.revision >= 2 {
+            rsdp = read_unaligned(ptr as *const Rsdp);
+            if (rsdp.length as usize) < V1_SIZE || !checksum(addr, rsdp.length as usize) {
+                return Err(AcpiError::InvalidRsdp);
+            }
+        }
+        Ok(rsdp)
+    }
+
+    /// 按照ACPI规范以16字节对齐依次在EBDA的前1KB和BIOS只读区域`[0xE0000, 0x100000)`中查找RSDP，
+    /// 返回其物理地址。UEFI引导时应当使用配置表中的地址
+    ///
+    /// # Safety
+    ///
+    /// `map(phys, len)`返回的地址必须可以访问`len`字节
+    pub unsafe fn search<F>(map: F) -> Option<PhysAddr> where F: Fn(PhysAddr, usize) -> VirtAddr {
+        // BIOS数据区0x40E处保存了EBDA的段地址
+        let ebda = u64::from(read_unaligned(map(PhysAddr::new(0x40e), 2).as_ptr::<u16>())) << 4;
+        let areas = [(ebda, ebda + 0x400), (0xe0000, 0x100000)];
+        for &(start, end) in areas.iter().filter(|&&(start, _)| start != 0) {
+            // 每个候选位置之后还需要能读取完整的RSDP
+            let base = map(PhysAddr::new(start), (end - start) as usize + size_of::<Rsdp>());
+            for phys in (start..end).step_by(16) {
+                if Self::read(base + (phys - start)).is_ok() {
+                    return Some(PhysAddr::new(phys));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    pub fn oem_id(&self) -> [u8; 6] {
+        self.oem_id
+    }
+
+    pub fn rsdt_address(&self) -> PhysAddr {
+        PhysAddr::new(u64::from(self.rsdt_address))
+    }
+
+    /// XSDT的物理地址，ACPI 1.0没有XSDT
+    pub fn xsdt_address(&self) -> Option<PhysAddr> {
+        if self.revision >= 2 && self.xsdt_address != 0 {
+            Some(PhysAddr::new(self.xsdt_address))
+        } else {
+            None
+        }
+    }
+}
@@ -4,10 +4,11 @@ use core::ops::{Add, AddAssign, Sub, SubAssign};
 use core::result::Result;
 
 use crate::bits::BitOpt;
-use crate::ia_32e::paging::{PageIndex, PageOffset};
+use crate::ia_32e::paging::{PageIndex, PageOffset, virt_addr_bits};
 
 /// Virtual Address 虚拟地址
-/// IA-32e模型线性地址的寻址能力只有48位，第48位用于线性地址寻址，高16位作为符号扩展
+/// IA-32e模型线性地址的寻址能力只有48位，第48位用于线性地址寻址，高16位作为符号扩展。
+/// 启用5级分页（LA57）后线性地址为57位，高7位是第56位的符号扩展，见`paging::five_level_paging`
 /// 此格式的地址被称为Canonical地址，在IA-32e模式下只有Canonical地址是可用地址空间
 /// Non-Canonical地址属于无效地址空间
 /// 基本的地址空间划分如下
@@ -70,32 +71,36 @@ pub struct NoCanonicalAddr(u64);
 
 impl VirtAddr {
     /// 创建一个Canonical地址，传入的地址不会进行检查
-    /// 该方法会检查第47位（P：已存在标示），48-63位将会被重写
+    /// 4级分页时使用第47位，5级分页时使用第56位对高位进行符号扩展，高位将会被重写
     pub fn new_unchecked(mut addr: u64) -> VirtAddr {
-        if addr.get_bit(47) {
-            addr.set_bits(48..64, 0xFFFF);
+        let bits = virt_addr_bits();
+        if addr.get_bit(bits - 1) {
+            addr.set_bits(bits..64, !0 >> bits);
         } else {
-            addr.set_bits(48..64, 0);
+            addr.set_bits(bits..64, 0);
         }
         VirtAddr(addr)
     }
 
     /// 该函数尝试创建一个Canonical地址，
-    /// 如果48位到64位是正确的符号扩展名（即47位的副本）或全部为空，将成功返回
+    /// 如果高位是正确的符号扩展名（即第47位或者5级分页时第56位的副本）或全部为空，将成功返回
     pub fn try_new(addr: u64) -> Result<VirtAddr, NoCanonicalAddr> {
-        // 获取[47，64)
-        match addr.get_bits(47..64) {
-            // 这里47位标示内存已存在
-            0 | 0x1FFFF => Ok(VirtAddr(addr)),
-            1 => Ok(VirtAddr::new_unchecked(addr)),
-            other => Err(NoCanonicalAddr(other)),
+        let bits = virt_addr_bits();
+        // 获取[bits - 1，64)
+        let high = addr.get_bits(bits - 1..64);
+        if high == 0 || high == !0 >> (bits - 1) {
+            Ok(VirtAddr(addr))
+        } else if high == 1 {
+            Ok(VirtAddr::new_unchecked(addr))
+        } else {
+            Err(NoCanonicalAddr(high))
         }
     }
     /// 使用给定的原始地址虚拟地址结构
     /// 如果给定的虚拟地址不符合Canonical地址将会Panic
     pub fn new(addr: u64) -> VirtAddr {
-        // 给定的地址48-64位必须是不包含任何数据的
-        Self::try_new(addr).expect("given address can not contain any data in bits 48 to 64")
+        // 给定的地址高位必须是不包含任何数据的
+        Self::try_new(addr).expect("given address is not canonical")
     }
 
 
@@ -176,6 +181,16 @@ impl VirtAddr {
     pub fn page4_index(&self) -> PageIndex {
         PageIndex::new_truncate((self.0 >> 12 >> 9 >> 9 >> 9) as u16)
     }
+
+    /// 返回五级页表索引（9位），只在5级分页时有效
+    pub fn page5_index(&self) -> PageIndex {
+        PageIndex::new_truncate((self.0 >> 12 >> 9 >> 9 >> 9 >> 9) as u16)
+    }
+
+    /// 返回`level`级页表索引，`level`从1开始
+    pub fn page_index(&self, level: usize) -> PageIndex {
+        PageIndex::new_truncate((self.0 >> (12 + 9 * (level - 1))) as u16)
+    }
     /// 将虚拟地址向上对齐
     pub fn align_up<U>(self, align: U) -> Self where U: Into<u64> {
         VirtAddr(align_down(self.0, align.into()))
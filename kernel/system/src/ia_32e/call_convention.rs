@@ -46,6 +46,10 @@ impl ScratchRegisters {
     pub fn r11(&self) -> VirtAddr {
         VirtAddr::new({ self.r11 } as u64)
     }
+    /// 设置返回到调用方的rax，用于系统调用的返回值
+    pub fn set_rax(&mut self, value: usize) {
+        self.rax = value;
+    }
 }
 
 #[macro_export]
@@ -254,6 +258,22 @@ impl InterruptStack {
 
 }
 
+/// 带有错误码的异常（例如#PF，#GP）进入处理程序时的栈布局
+#[repr(packed)]
+pub struct InterruptErrorStack {
+    pub preserved: PreservedRegisters,
+    pub scratch: ScratchRegisters,
+    /// 处理器压入的错误码
+    pub code: usize,
+    pub iret: IretRegisters,
+}
+
+impl InterruptErrorStack {
+    pub fn dump(&self) -> String {
+        format!("[Code] {:#X} {} {} {}", { self.code }, self.preserved.dump(), self.scratch.dump(), self.iret.dump())
+    }
+}
+
 #[macro_export]
 macro_rules! interrupt {
     ($name:ident,$func:block) => {
@@ -310,3 +330,30 @@ macro_rules! interrupt_frame {
         }
     };
 }
+
+/// 用于带有错误码的异常，返回前会将错误码弹出
+#[macro_export]
+macro_rules! interrupt_error {
+    ($name:ident,$stack:ident,$func:block) => {
+        #[naked]
+        pub unsafe extern "C" fn $name(){
+            #[inline(never)]
+            unsafe fn inner($stack: &mut $crate::ia_32e::call_convention::InterruptErrorStack){
+                $func
+            }
+
+            $crate::push_scratch!();
+            $crate::push_preserved!();
+            $crate::cld!();
+
+            let rsp = $crate::get_rsp!();
+            inner(&mut *(rsp as *mut $crate::ia_32e::call_convention::InterruptErrorStack));
+
+            $crate::pop_preserved!();
+            $crate::pop_scratch!();
+            // 丢弃错误码
+            llvm_asm!("add rsp, 8" : : : : "intel", "volatile");
+            $crate::iret!();
+        }
+    };
+}
@@ -13,7 +13,7 @@ pub trait ControllerType {
 }
 
 
-pub struct XPAIC(xApic);
+pub struct XPAIC(xApic, IoApic);
 
 pub struct X2APIC(LocalApic,IoApic);
 
@@ -182,6 +182,15 @@ impl ProgrammableController<X2APIC> {
     pub unsafe fn io_apic_set_id(&mut self, id: u8) {
         self.io_apic.as_mut().expect("io apic not init").set_id(id)
     }
+
+    pub unsafe fn io_apic_set_vector(&mut self, irq: u8, vector: u8) {
+        self.io_apic.as_mut().expect("io apic not init").set_vector(irq, vector)
+    }
+
+    /// I/O APIC重定向表最后一项的编号
+    pub unsafe fn io_apic_max_table_entry(&mut self) -> u8 {
+        self.io_apic.as_mut().expect("io apic not init").max_table_entry()
+    }
 }
 
 impl ProgrammableController<PIC> {
@@ -296,93 +305,172 @@ impl ProgrammableController<PIC> {
     pub unsafe fn io_apic_set_id(&mut self, _id: u8) {
         panic!("8259 not support set set id")
     }
+
+    pub unsafe fn io_apic_set_vector(&mut self, _irq: u8, _vector: u8) {
+        panic!("8259 not support set vector")
+    }
 }
 
 impl ProgrammableController<XPAIC> {
     fn new(t: XPAIC) -> Self {
         Self{
             local_apic: None,
-            io_apic: None,
+            io_apic: Some(t.1),
             xapic: Some(t.0),
             pic: None,
             _mark:PhantomData
         }
     }
 
+    fn xapic(&mut self) -> &mut xApic {
+        self.xapic.as_mut().expect("xapic not init")
+    }
+
+    fn io_apic(&mut self) -> &mut IoApic {
+        self.io_apic.as_mut().expect("io apic not init")
+    }
+
     pub unsafe fn version(&mut self) -> String {
-        format!("xapic: {}", self.xapic.as_ref().expect("xapic not init").version())
+        format!("xapic: {}, io apic: {}", self.xapic().version(), self.io_apic().version())
+    }
+
+    /// 当前CPU的local APIC ID
+    pub unsafe fn id(&mut self) -> u32 {
+        self.xapic().id()
     }
 
     pub unsafe fn eoi(&mut self, _number: Option<u8>) {
-        self.xapic.as_mut().expect("xapic not init").eoi()
+        self.xapic().eoi()
     }
 
-    pub unsafe fn init(&mut self, _info: ApicInfo) {
-        self.xapic.as_mut().expect("xapic not init").cpu_init()
+    /// 初始化local APIC和I/O APIC，I/O APIC的第`i`项重定向到`i + ioapic_offset`号向量，
+    /// 初始化后所有的项都被屏蔽，需要通过`enable_irq`逐个开启
+    pub unsafe fn init(&mut self, info: ApicInfo) {
+        let xapic = self.xapic();
+        xapic.cpu_init();
+        if let Some(mode) = info.ipi_destination_mode {
+            xapic.set_ipi_destination_mode(mode);
+        }
+        if let Some(mode) = info.timer_mode {
+            xapic.set_timer_mode(mode);
+        }
+        if let Some(divide) = info.timer_divide {
+            xapic.set_timer_divide(divide);
+        }
+        if let Some(initial) = info.timer_initial {
+            xapic.set_timer_initial(initial);
+        }
+
+        let io_apic = self.io_apic();
+        io_apic.init(info.ioapic_offset.expect("missing IO APIC offset argument"));
+        for irq in 0..=io_apic.max_table_entry() {
+            io_apic.disable_irq(irq);
+        }
     }
 
     pub unsafe fn enable(&mut self) {
-        unimplemented!()
+        self.xapic().enable()
+    }
+
+    pub unsafe fn disable(&mut self) {
+        self.xapic().disable()
     }
-    pub unsafe fn disable(&mut self) { unimplemented!() }
+
     pub unsafe fn enable_timer(&mut self) {
-        unimplemented!()
+        self.xapic().enable_timer()
     }
-    pub unsafe fn disable_timer(&mut self) { unimplemented!() }
-    pub unsafe fn set_timer_mode(&mut self, _mode: TimerMode) {
-        unimplemented!()
+
+    pub unsafe fn disable_timer(&mut self) {
+        self.xapic().disable_timer()
     }
-    pub unsafe fn set_timer_divide(&mut self, _divide: TimerDivide) {
-        unimplemented!()
+
+    pub unsafe fn set_timer_mode(&mut self, mode: TimerMode) {
+        self.xapic().set_timer_mode(mode)
     }
-    pub unsafe fn set_timer_initial(&mut self, _initial: u32) { unimplemented!() }
-    pub unsafe fn set_logical_id(&mut self, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn set_timer_divide(&mut self, divide: TimerDivide) {
+        self.xapic().set_timer_divide(divide)
     }
-    pub unsafe fn send_ipi(&mut self, _vector: u8, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn set_timer_initial(&mut self, initial: u32) {
+        self.xapic().set_timer_initial(initial)
     }
-    pub unsafe fn send_ipi_all(&mut self, _vector: u8, _who: IpiAllShorthand) {
-        unimplemented!()
+
+    /// 定时器当前的计数
+    pub unsafe fn timer_current(&mut self) -> u32 {
+        self.xapic().timer_current()
     }
-    pub unsafe fn send_lowest_priority_ipi(&mut self, _vector: u8, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn set_logical_id(&mut self, dest: u32) {
+        self.xapic().set_logical_id(dest)
     }
-    pub unsafe fn send_lowest_priority_ipi_all(&mut self, _vector: u8, _who: IpiAllShorthand) {
-        unimplemented!()
+
+    pub unsafe fn send_ipi(&mut self, vector: u8, dest: u32) {
+        self.xapic().send_ipi(vector, dest)
     }
-    pub unsafe fn send_smi(&mut self, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn send_ipi_all(&mut self, vector: u8, who: IpiAllShorthand) {
+        self.xapic().send_ipi_all(vector, who)
     }
-    pub unsafe fn send_smi_all(&mut self, _who: IpiAllShorthand) {
-        unimplemented!()
+
+    pub unsafe fn send_lowest_priority_ipi(&mut self, vector: u8, dest: u32) {
+        self.xapic().send_lowest_priority_ipi(vector, dest)
     }
-    pub unsafe fn send_nmi(&mut self, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn send_lowest_priority_ipi_all(&mut self, vector: u8, who: IpiAllShorthand) {
+        self.xapic().send_lowest_priority_ipi_all(vector, who)
     }
-    pub unsafe fn send_nmi_all(&mut self, _who: IpiAllShorthand) {
-        unimplemented!()
+
+    pub unsafe fn send_smi(&mut self, dest: u32) {
+        self.xapic().send_smi(dest)
     }
-    pub unsafe fn send_sipi(&mut self, _vector: u8, _dest: u32) {
-        unimplemented!()
+
+    pub unsafe fn send_smi_all(&mut self, who: IpiAllShorthand) {
+        self.xapic().send_smi_all(who)
     }
-    pub unsafe fn send_sipi_all(&mut self, _vector: u8) {
-        unimplemented!()
+
+    pub unsafe fn send_nmi(&mut self, dest: u32) {
+        self.xapic().send_nmi(dest)
     }
-    pub unsafe fn send_ipi_self(&mut self, _vector: u8) {
-        unimplemented!()
+
+    pub unsafe fn send_nmi_all(&mut self, who: IpiAllShorthand) {
+        self.xapic().send_nmi_all(who)
     }
-    pub unsafe fn enable_irq(&mut self, _irq: u8, _dest: u32, _mode: IrqMode, _options: IrqFlags) { unimplemented!() }
-    pub unsafe fn disable_irq(&mut self, _irq: u8) {
-        unimplemented!()
+
+    pub unsafe fn send_sipi(&mut self, vector: u8, dest: u32) {
+        self.xapic().send_sipi(vector, dest)
     }
-    pub unsafe fn io_apic_set_arbitration_id(&mut self, _id: u8) {
-        unimplemented!()
+
+    pub unsafe fn send_sipi_all(&mut self, vector: u8) {
+        self.xapic().send_sipi_all(vector)
     }
-    pub unsafe fn io_apic_set_id(&mut self, _id: u8) {
-        unimplemented!()
+
+    pub unsafe fn send_ipi_self(&mut self, vector: u8) {
+        self.xapic().send_ipi_self(vector)
+    }
+
+    pub unsafe fn enable_irq(&mut self, irq: u8, dest: u32, mode: IrqMode, options: IrqFlags) {
+        self.io_apic().enable_irq(irq, dest, mode, options)
     }
-}
 
+    pub unsafe fn disable_irq(&mut self, irq: u8) {
+        self.io_apic().disable_irq(irq)
+    }
 
+    pub unsafe fn io_apic_set_arbitration_id(&mut self, id: u8) {
+        self.io_apic().set_arbitration_id(id)
+    }
 
+    pub unsafe fn io_apic_set_id(&mut self, id: u8) {
+        self.io_apic().set_id(id)
+    }
+
+    pub unsafe fn io_apic_set_vector(&mut self, irq: u8, vector: u8) {
+        self.io_apic().set_vector(irq, vector)
+    }
+
+    /// I/O APIC重定向表最后一项的编号
+    pub unsafe fn io_apic_max_table_entry(&mut self) -> u8 {
+        self.io_apic().max_table_entry()
+    }
+}
@@ -3,7 +3,7 @@ use crate::ia_32e::{PhysAddr, VirtAddr};
 use crate::ia_32e::instructions::register::read_cr4;
 use crate::ia_32e::paging::frame::Frame;
 
-use super::super::instructions::register::{read_cr0, read_cr2, read_cr3, write_cr0, write_cr2, write_cr3};
+use super::super::instructions::register::{read_cr0, read_cr2, read_cr3, write_cr0, write_cr3, write_cr4};
 
 #[derive(Debug)]
 pub struct CR0;
@@ -55,6 +55,10 @@ impl CR2 {
 pub struct CR3;
 
 impl CR3 {
+    /// 写入CR3时设置该位，切换到的PCID在TLB中的缓存不会被清除
+    pub const NO_FLUSH: u64 = 1 << 63;
+    pub const PCID_MASK: u64 = 0xFFF;
+
     /// 从CR3寄存器中读取读取当前P4页表的地址
     pub fn read() -> (Frame, CR3Flags) {
         let data = unsafe { read_cr3() };
@@ -70,6 +74,22 @@ impl CR3 {
         let data = addr.as_u64() | flags.bits();
         write_cr3(data)
     }
+
+    /// 启用CR4.PCIDE后读取P4页表的地址和当前的PCID
+    pub fn read_pcid() -> (Frame, u16) {
+        let data = unsafe { read_cr3() };
+        let frame = Frame::include_address(PhysAddr::new(data & 0x00F_FFFF_FFFF_F000));
+        (frame, (data & Self::PCID_MASK) as u16)
+    }
+
+    /// 启用CR4.PCIDE后切换页表和PCID，`no_flush`为false时清除TLB中该PCID的缓存
+    pub unsafe fn write_pcid(frame: Frame, pcid: u16, no_flush: bool) {
+        let mut data = frame.start_address().as_u64() | (pcid as u64 & Self::PCID_MASK);
+        if no_flush {
+            data |= Self::NO_FLUSH;
+        }
+        write_cr3(data)
+    }
 }
 
 pub struct CR4;
@@ -94,7 +114,7 @@ impl CR4 {
 
     /// 向CR4寄存器写入原始u64数据
     pub unsafe fn write_raw(data: u64) {
-        write_cr2(data)
+        write_cr4(data)
     }
 
     /// 向CR4寄存器写入 Flags数据
@@ -0,0 +1,209 @@
+//! 高精度事件定时器（HPET），由一个单调递增的主计数器和若干个比较器组成，
+//! 主计数器等于比较器的值时产生中断，可以替代8253作为时钟源
+use core::ptr::{read_volatile, write_volatile};
+
+/// 通用能力和ID寄存器，高32位是计数器的周期（飞秒）
+const CAPABILITIES: usize = 0x000;
+/// 通用配置寄存器
+const CONFIGURATION: usize = 0x010;
+/// 通用中断状态寄存器，电平触发时写1清除
+const INTERRUPT_STATUS: usize = 0x020;
+/// 主计数器
+const MAIN_COUNTER: usize = 0x0f0;
+/// 第N个比较器的配置寄存器位于`TIMER_CONFIG + TIMER_STRIDE * N`
+const TIMER_CONFIG: usize = 0x100;
+const TIMER_COMPARATOR: usize = 0x108;
+const TIMER_STRIDE: usize = 0x20;
+
+/// 主计数器开始计数，比较器可以产生中断
+const ENABLE_CNF: u64 = 1 << 0;
+/// 比较器0和1替代8253和RTC，分别连接到IRQ0和IRQ8
+const LEG_RT_CNF: u64 = 1 << 1;
+
+/// 电平触发，否则为边沿触发
+const TN_INT_TYPE_CNF: u64 = 1 << 1;
+const TN_INT_ENB_CNF: u64 = 1 << 2;
+/// 周期模式，否则为单次模式
+const TN_TYPE_CNF: u64 = 1 << 3;
+/// 比较器支持周期模式
+const TN_PER_INT_CAP: u64 = 1 << 4;
+/// 周期模式下允许直接写入累加器
+const TN_VAL_SET_CNF: u64 = 1 << 6;
+/// 中断连接的I/O APIC引脚位于第9-13位
+const TN_INT_ROUTE_SHIFT: u64 = 9;
+const TN_INT_ROUTE_MASK: u64 = 0x1f << TN_INT_ROUTE_SHIFT;
+/// 通过FSB（MSI）发送中断
+const TN_FSB_EN_CNF: u64 = 1 << 14;
+
+/// 1秒对应的飞秒数
+const FEMTOS_PER_SECOND: u64 = 1_000_000_000_000_000;
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HpetError {
+    /// 比较器编号超出范围
+    NoSuchTimer(u8),
+    /// 比较器不支持周期模式
+    PeriodicNotSupported(u8),
+    /// 比较器的中断不能连接到给定的I/O APIC引脚
+    RouteNotSupported(u8),
+}
+
+pub struct Hpet {
+    /// 寄存器所在的虚拟地址
+    base: usize,
+    /// 主计数器每次加1经过的飞秒数
+    period: u64,
+    timers: u8,
+}
+
+impl Hpet {
+    /// `base`为HPET寄存器映射到的虚拟地址，寄存器需要以不可缓存的方式映射
+    ///
+    /// # Safety
+    ///
+    /// `base`开始的1KB必须是HPET的寄存器
+    pub unsafe fn new(base: usize) -> Self {
+        let capabilities = read_volatile(base as *const u64);
+        Self {
+            base,
+            period: capabilities >> 32,
+            timers: ((capabilities >> 8) & 0x1f) as u8 + 1,
+        }
+    }
+
+    unsafe fn read(&self, reg: usize) -> u64 {
+        read_volatile((self.base + reg) as *const u64)
+    }
+
+    unsafe fn write(&mut self, reg: usize, value: u64) {
+        write_volatile((self.base + reg) as *mut u64, value);
+    }
+
+    /// 主计数器每次加1经过的飞秒数
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// 主计数器的频率（Hz）
+    pub fn frequency(&self) -> u64 {
+        FEMTOS_PER_SECOND / self.period
+    }
+
+    /// 比较器的数量
+    pub fn timers(&self) -> u8 {
+        self.timers
+    }
+
+    /// 主计数器是64位的，否则只有低32位有效
+    pub unsafe fn counter_64bit(&self) -> bool {
+        self.read(CAPABILITIES) & (1 << 13) != 0
+    }
+
+    /// 支持legacy中断路由
+    pub unsafe fn legacy_capable(&self) -> bool {
+        self.read(CAPABILITIES) & (1 << 15) != 0
+    }
+
+    /// 主计数器开始计数
+    pub unsafe fn enable(&mut self) {
+        let config = self.read(CONFIGURATION);
+        self.write(CONFIGURATION, config | ENABLE_CNF);
+    }
+
+    /// 主计数器停止计数，比较器不再产生中断
+    pub unsafe fn disable(&mut self) {
+        let config = self.read(CONFIGURATION);
+        self.write(CONFIGURATION, config & !ENABLE_CNF);
+    }
+
+    /// 比较器0和1的中断分别替代8253的IRQ0和RTC的IRQ8
+    pub unsafe fn set_legacy_route(&mut self, enable: bool) {
+        let config = self.read(CONFIGURATION) & !LEG_RT_CNF;
+        self.write(CONFIGURATION, if enable { config | LEG_RT_CNF } else { config });
+    }
+
+    /// 读取主计数器
+    pub unsafe fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER)
+    }
+
+    /// 主计数器停止时才能写入
+    pub unsafe fn set_counter(&mut self, value: u64) {
+        self.write(MAIN_COUNTER, value);
+    }
+
+    /// 主计数器的值对应的纳秒数
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        (u128::from(ticks) * u128::from(self.period) / u128::from(FEMTOS_PER_NANO)) as u64
+    }
+
+    /// 纳秒数对应的主计数器的值，至少为1
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        ((u128::from(nanos) * u128::from(FEMTOS_PER_NANO) / u128::from(self.period)) as u64).max(1)
+    }
+
+    /// 比较器`timer`的中断可以连接的I/O APIC引脚，第N位为1时可以连接到引脚N
+    pub unsafe fn routes(&self, timer: u8) -> Result<u32, HpetError> {
+        self.check(timer)?;
+        Ok((self.read(Self::config_reg(timer)) >> 32) as u32)
+    }
+
+    /// 比较器`timer`周期性地每经过`ticks`次计数产生一次边沿触发的中断，中断连接到I/O APIC的`route`引脚
+    pub unsafe fn set_periodic(&mut self, timer: u8, route: u8, ticks: u64) -> Result<(), HpetError> {
+        let config = self.route_config(timer, route)?;
+        if config & TN_PER_INT_CAP == 0 {
+            return Err(HpetError::PeriodicNotSupported(timer));
+        }
+        // 设置了TN_VAL_SET_CNF后，第一次写入比较器的是下一次中断的时间，第二次写入的是周期
+        self.write(Self::config_reg(timer), config | TN_INT_ENB_CNF | TN_TYPE_CNF | TN_VAL_SET_CNF);
+        let now = self.counter();
+        self.write(Self::comparator_reg(timer), now.wrapping_add(ticks));
+        self.write(Self::comparator_reg(timer), ticks);
+        Ok(())
+    }
+
+    /// 比较器`timer`在经过`ticks`次计数后产生一次边沿触发的中断，中断连接到I/O APIC的`route`引脚
+    pub unsafe fn set_one_shot(&mut self, timer: u8, route: u8, ticks: u64) -> Result<(), HpetError> {
+        let config = self.route_config(timer, route)?;
+        self.write(Self::config_reg(timer), config | TN_INT_ENB_CNF);
+        let now = self.counter();
+        self.write(Self::comparator_reg(timer), now.wrapping_add(ticks));
+        Ok(())
+    }
+
+    /// 关闭比较器`timer`的中断
+    pub unsafe fn disable_timer(&mut self, timer: u8) -> Result<(), HpetError> {
+        self.check(timer)?;
+        let config = self.read(Self::config_reg(timer));
+        self.write(Self::config_reg(timer), config & !TN_INT_ENB_CNF);
+        Ok(())
+    }
+
+    /// 清除电平触发的比较器`timer`的中断状态
+    pub unsafe fn clear_interrupt(&mut self, timer: u8) {
+        self.write(INTERRUPT_STATUS, 1 << u64::from(timer));
+    }
+
+    fn check(&self, timer: u8) -> Result<(), HpetError> {
+        if timer < self.timers { Ok(()) } else { Err(HpetError::NoSuchTimer(timer)) }
+    }
+
+    /// 检查引脚后返回清除了中断相关设置的配置寄存器的值，其中包含新的引脚
+    unsafe fn route_config(&self, timer: u8, route: u8) -> Result<u64, HpetError> {
+        if route >= 32 || self.routes(timer)? & (1 << route) == 0 {
+            return Err(HpetError::RouteNotSupported(route));
+        }
+        let config = self.read(Self::config_reg(timer))
+            & !(TN_INT_TYPE_CNF | TN_INT_ENB_CNF | TN_TYPE_CNF | TN_VAL_SET_CNF | TN_INT_ROUTE_MASK | TN_FSB_EN_CNF);
+        Ok(config | (u64::from(route) << TN_INT_ROUTE_SHIFT))
+    }
+
+    fn config_reg(timer: u8) -> usize {
+        TIMER_CONFIG + TIMER_STRIDE * timer as usize
+    }
+
+    fn comparator_reg(timer: u8) -> usize {
+        TIMER_COMPARATOR + TIMER_STRIDE * timer as usize
+    }
+}
@@ -8,3 +8,5 @@ pub mod control;
 pub mod apic;
 pub mod msr;
 pub mod timer;
+pub mod hpet;
+pub mod pat;
@@ -0,0 +1,72 @@
+//! 页属性表（PAT）
+//!
+//! 页表项中的PAT、PCD和PWT位组成3位的索引，从IA32_PAT的8项中选出页面的内存类型。
+//! 启动时写入固定的布局：前4项与上电时的默认值相同，只有PAT位和PWT对应的第5项由写穿改为写合并，
+//! 未写入PAT或不支持PAT时各类型的含义保持不变。4KB页表项的PAT位是第7位，大页是第12位。
+use crate::bits::PageTableFlags;
+use crate::ia_32e::cpu::msr::IA32_PAT;
+use crate::ia_32e::instructions::page_table::flush_all;
+use crate::ia_32e::instructions::register::{rdmsr, wrmsr};
+
+/// IA32_PAT中每一项的编码
+const UC: u64 = 0x00;
+const WC: u64 = 0x01;
+const WT: u64 = 0x04;
+const WB: u64 = 0x06;
+const UC_MINUS: u64 = 0x07;
+
+/// 依次为PA0到PA7
+const LAYOUT: [u64; 8] = [WB, WT, UC_MINUS, UC, WB, WC, UC_MINUS, UC];
+
+/// 页面的内存类型
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CacheType {
+    /// 回写，普通内存使用
+    WriteBack,
+    /// 写穿
+    WriteThrough,
+    /// 写合并，适用于帧缓冲区
+    WriteCombining,
+    /// 不可缓存，适用于设备的MMIO
+    Uncacheable,
+}
+
+impl CacheType {
+    /// 4KB页表项中选择PAT项的位
+    pub fn mask() -> PageTableFlags {
+        PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_CACHE | PageTableFlags::HUGE_PAGE
+    }
+
+    /// 该类型在4KB页表项中对应的位，只有写合并需要PAT位
+    pub fn flags(self) -> PageTableFlags {
+        match self {
+            CacheType::WriteBack => PageTableFlags::empty(),
+            CacheType::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            CacheType::Uncacheable => PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_CACHE,
+            CacheType::WriteCombining => PageTableFlags::WRITE_THROUGH | PageTableFlags::HUGE_PAGE,
+        }
+    }
+
+    /// 大页的PAT位与物理地址重叠，需要PAT位的类型只能使用4KB页面
+    pub fn huge_page_allowed(self) -> bool {
+        !self.flags().contains(PageTableFlags::HUGE_PAGE)
+    }
+}
+
+pub struct Pat;
+
+impl Pat {
+    pub fn read() -> u64 {
+        rdmsr(IA32_PAT)
+    }
+
+    /// 写入`CacheType`使用的布局，每个CPU都需要调用一次。
+    /// 需要CPU支持PAT（`CPUID.01H:EDX[16]`），写入前应当还没有使用第5项的映射
+    pub unsafe fn init() {
+        let value = LAYOUT.iter().enumerate().fold(0, |value, (i, &ty)| value | ty << (i * 8));
+        wrmsr(IA32_PAT, value);
+        // 清除按旧的内存类型缓存的数据和TLB
+        llvm_asm!("wbinvd" : : : "memory" : "volatile");
+        flush_all();
+    }
+}
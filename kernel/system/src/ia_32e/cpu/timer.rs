@@ -1,6 +1,6 @@
 use bitflags::bitflags;
 use crate::ia_32e::cpu::Port;
-use crate::ia_32e::instructions::port::{outb};
+use crate::ia_32e::instructions::port::{inb, outb};
 
 pub const TIMER_FREQUENCY: usize = 1193182;
 pub const TIMER_MODE: u16 = 0x43;
@@ -13,6 +13,9 @@ pub const TIMER5: u16 = 0x45;
 
 pub const IRQ_FREQUENCY: usize = 100;
 
+/// 控制计数器2的门控和读取其输出的端口
+const PIT_GATE: u16 = 0x61;
+
 pub const fn timer_count(frequency: usize) -> usize {
     TIMER_FREQUENCY / frequency
 }
@@ -46,7 +49,7 @@ bitflags! {
     }
 }
 
-struct Chips8253 {
+pub struct Chips8253 {
     // frequency (Hz)
     frequency: u16,
     control: ContorlWord,
@@ -110,6 +113,21 @@ impl Chips8253 {
         ((high as u16) << 8) | low as u16
     }
 
+    /// 使用计数器2忙等待`micros`微秒，最长约54ms，用于校准其他定时器。计数器0不受影响
+    pub unsafe fn wait(micros: u64) {
+        let count = (TIMER_FREQUENCY as u64 * micros / 1_000_000).min(0xffff).max(1) as u16;
+        // 关闭门控和扬声器，写入计数后再打开门控开始计数
+        let gate = inb(PIT_GATE) & !0x03;
+        outb(gate, PIT_GATE);
+        // 计数器2，先低字节后高字节，方式0：计数结束后输出变为高电平
+        outb(0b1011_0000, TIMER_MODE);
+        outb(count as u8, TIMER2);
+        outb((count >> 8) as u8, TIMER2);
+        outb(gate | 0x01, PIT_GATE);
+        while inb(PIT_GATE) & 0x20 == 0 {}
+        outb(gate, PIT_GATE);
+    }
+
     pub unsafe fn set_pit_count(&self, count: u16) {
         let mut port = Port::new(TIMER0);
         port.write(count as u8);
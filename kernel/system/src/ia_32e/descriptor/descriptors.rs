@@ -2,7 +2,7 @@ use core::fmt;
 
 use crate::bits::{BitOpt, DescriptorFlags};
 use super::super::Hex;
-use super::tss::TaskStateSegment;
+use super::tss::{TaskStateSegment, TssWithIoBitmap};
 
 /// 64位描述符
 #[derive(Clone)]
@@ -118,6 +118,29 @@ impl Descriptor {
         high.set_bits(0..32, ptr.get_bits(32..64));
         Descriptor::SystemSegment(low, high)
     }
+
+    /// 创建带有I/O许可位图的TSS段描述符，段限长包含整个位图
+    pub fn tss_segment_with_io_bitmap(ts: &'static TssWithIoBitmap) -> Descriptor {
+        use core::mem::size_of;
+
+        let ptr = ts as *const _ as u64;
+        let mut low = DescriptorFlags::PRESENT.bits();
+
+        // 段基址(低)
+        low.set_bits(16..40, ptr.get_bits(0..24));
+        low.set_bits(56..64, ptr.get_bits(24..32));
+        // 段限长(低16位以及高4位)
+        let limit = (size_of::<TssWithIoBitmap>() - 1) as u64;
+        low.set_bits(0..16, limit.get_bits(0..16));
+        low.set_bits(48..52, limit.get_bits(16..20));
+        // 段属性 1001表示64位TSS段描述符
+        low.set_bits(40..44, 0b1001);
+
+        let mut high: u64 = 0;
+        // 段基址(高)
+        high.set_bits(0..32, ptr.get_bits(32..64));
+        Descriptor::SystemSegment(low, high)
+    }
 }
 
 /// 用于将GDT，IDT等描述符保存为指针形式
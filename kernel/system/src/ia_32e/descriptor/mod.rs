@@ -5,7 +5,7 @@ pub use idt::{EntryOptions, HandlerFuncWithErrCode,HandlerFunc,PageFaultHandlerF
 #[cfg(feature="call")]
 pub use idt::{EntryOptions, InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue};
 pub use segment::SegmentSelector;
-pub use tss::TaskStateSegment;
+pub use tss::{IO_BITMAP_SIZE, TaskStateSegment, TssWithIoBitmap};
 
 ///! x86系统使用的所有描述符 包裹GDT IDT TSS等
 mod gdt;
@@ -33,4 +33,38 @@ impl TaskStateSegment {
             reserved_4: 0,
         }
     }
-}
\ No newline at end of file
+}
+/// I/O许可位图的大小，每个端口占1位
+pub const IO_BITMAP_SIZE: usize = 65536 / 8;
+
+/// 带有I/O许可位图的TSS
+/// 位图紧跟在TSS之后，位为0表示允许Ring3访问对应端口，位图末尾需要额外的一个全1字节
+#[repr(C, packed)]
+pub struct TssWithIoBitmap {
+    pub tss: TaskStateSegment,
+    io_bitmap: [u8; IO_BITMAP_SIZE + 1],
+}
+
+impl TssWithIoBitmap {
+    /// 创建一个禁止Ring3访问所有端口的TSS
+    pub const fn new() -> TssWithIoBitmap {
+        let mut tss = TaskStateSegment::new();
+        tss.io_map_base = core::mem::size_of::<TaskStateSegment>() as u16;
+        TssWithIoBitmap {
+            tss,
+            io_bitmap: [0xFF; IO_BITMAP_SIZE + 1],
+        }
+    }
+
+    /// 允许或禁止Ring3访问`[start, start + count)`范围内的端口
+    pub fn set_io_ports(&mut self, start: u16, count: u32, allowed: bool) {
+        for port in start as usize..core::cmp::min(start as usize + count as usize, 65536) {
+            let byte = &mut self.io_bitmap[port / 8];
+            if allowed {
+                *byte &= !(1 << (port % 8));
+            } else {
+                *byte |= 1 << (port % 8);
+            }
+        }
+    }
+}
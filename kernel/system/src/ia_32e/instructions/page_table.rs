@@ -1,14 +1,55 @@
 use crate::ia_32e::VirtAddr;
+use crate::Mutex;
+
+/// 多处理器系统中其他CPU可能缓存了被修改的页表项，由内核注册通知其他CPU的函数，
+/// 参数为页面的起始地址和页面大小
+static SHOOTDOWN: Mutex<Option<fn(VirtAddr, u64)>> = Mutex::new(None);
 
 #[inline]
 pub unsafe fn flush(addr: VirtAddr) {
     llvm_asm!("invlpg ($0)": :"r"(addr.as_u64()) :"memory")
 }
 
-/// 重新赋值CR3 使更改后的页表生效 更改页表后原页表依旧缓存与TLB中，重新加载页目录迫使CR3寄存器刷新TLB
+/// 重新赋值CR3 使更改后的页表生效 更改页表后原页表依旧缓存与TLB中，重新加载页目录迫使CR3寄存器刷新TLB。
+/// 启用PCID后只刷新当前PCID的缓存
 #[inline]
 pub unsafe fn flush_all() {
-    use crate::ia_32e::cpu::control::CR3;
-    let (frame, flags) = CR3::read();
-    CR3::write(frame, flags)
-}
\ No newline at end of file
+    use crate::ia_32e::instructions::register::{read_cr3, write_cr3};
+    // 保留低12位中的PCID
+    write_cr3(read_cr3())
+}
+
+/// INVPCID指令的类型
+#[derive(Debug, Clone, Copy)]
+#[repr(u64)]
+pub enum InvpcidKind {
+    /// 刷新指定PCID中指定地址的缓存，全局页面除外
+    Address = 0,
+    /// 刷新指定PCID的所有缓存，全局页面除外
+    Single = 1,
+    /// 刷新所有PCID的缓存，包括全局页面
+    AllIncludingGlobal = 2,
+    /// 刷新所有PCID的缓存，全局页面除外
+    AllExcludingGlobal = 3,
+}
+
+/// 需要CPU支持INVPCID（`CPUID.(EAX=07H,ECX=0):EBX[10]`），`pcid`和`addr`只在部分类型中使用
+#[inline]
+pub unsafe fn invpcid(kind: InvpcidKind, pcid: u16, addr: VirtAddr) {
+    let descriptor: [u64; 2] = [pcid as u64, addr.as_u64()];
+    llvm_asm!("invpcid ($0), $1" : : "r"(&descriptor), "r"(kind as u64) : "memory")
+}
+
+pub fn set_shootdown_handler(handler: fn(VirtAddr, u64)) {
+    *SHOOTDOWN.lock() = Some(handler);
+}
+
+/// 刷新当前CPU中`addr`所在页面的TLB项，并通知其他CPU
+pub unsafe fn flush_shared(addr: VirtAddr, page_size: u64) {
+    flush(addr);
+    // 复制出函数指针，避免在通知其他CPU期间持有锁
+    let handler = *SHOOTDOWN.lock();
+    if let Some(handler) = handler {
+        handler(addr, page_size);
+    }
+}
@@ -98,6 +98,21 @@ pub fn rdmsr(msr: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
+/// 读取时间戳计数器（TSC），`lfence`保证之前的指令都已经执行完成
+#[inline]
+pub fn rdtsc() -> u64 {
+    let (mut high, mut low) = (0_u32, 0_u32);
+    unsafe {
+        llvm_asm!("lfence; rdtsc"
+        : "={eax}"(low),"={edx}"(high)
+        :
+        : "memory"
+        : "volatile"
+        );
+    }
+    ((high as u64) << 32) | (low as u64)
+}
+
 /// 从msr寄存器中写入64位数据
 #[inline]
 pub unsafe fn wrmsr(msr: u32, data: u64) {
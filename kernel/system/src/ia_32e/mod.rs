@@ -152,13 +152,13 @@ impl ApicInfo {
     ///
     /// This function returns an error if any of the required fields are empty.
     pub fn build(self) -> Result<Self, String> {
-        if cfg!(x2apic)
-            && self.timer_vector.is_none()
+        if cfg!(feature = "x2apic")
+            && (self.timer_vector.is_none()
             || self.error_vector.is_none()
-            || self.spurious_vector.is_none(){
+            || self.spurious_vector.is_none()) {
             return Err(String::from("x2apic: required field(s) empty"));
         }
-        if cfg!(xapic) &&  self.ioapic_offset.is_none() {
+        if cfg!(feature = "xapic") && self.ioapic_offset.is_none() {
             return Err(String::from("xapic: required field(s) empty"));
         }
         Ok(self)
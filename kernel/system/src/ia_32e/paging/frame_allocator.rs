@@ -1,8 +1,10 @@
 use super::{FrameAllocator, PageSize, Page4KB};
 use alloc::vec::Vec;
+use crate::buddy_system_allocator;
 use crate::ia_32e::paging::{Frame, UnusedFrame, MemorySpace, MemoryType};
-use crate::ia_32e::PhysAddr;
+use crate::ia_32e::{align_down, align_up, PhysAddr};
 use core::alloc::Layout;
+use core::cmp::max;
 use super::{ MemoryArea};
 
 pub trait MemoryAreaManagement {
@@ -216,4 +218,110 @@ unsafe impl FrameAllocator<Page4KB> for BumpAllocator {
     fn dealloc_size(&mut self, _frame: Frame<Page4KB>, _count: usize) {
         unimplemented!()
     }
-}
\ No newline at end of file
+}
+
+/// 基于伙伴系统的物理帧分配器，只管理`FreeArea`类型的内存区域，
+/// 支持分配和释放连续的多个物理帧
+///
+/// 内部使用`BTreeSet`保存空闲块，必须在堆初始化之后使用
+pub struct BuddyFrameAllocator {
+    inner: buddy_system_allocator::FrameAllocator,
+    /// 不可分配的物理内存`[start, end)`，例如内核映像和引导信息
+    reserved: Vec<(u64, u64)>,
+}
+
+impl BuddyFrameAllocator {
+    pub fn new() -> Self {
+        Self {
+            inner: buddy_system_allocator::FrameAllocator::new(),
+            reserved: Vec::new(),
+        }
+    }
+
+    /// 将`[start, end)`标记为保留区域，必须在`add_area`之前调用
+    pub fn reserve(&mut self, start: u64, end: u64) {
+        if start < end {
+            self.reserved.push((align_down(start, Page4KB::P_SIZE), align_up(end, Page4KB::P_SIZE)));
+        }
+    }
+
+    /// 将`[start, end)`中除保留区域之外的完整物理帧加入分配器
+    fn add_frames(&mut self, start: u64, end: u64) {
+        let mut ranges = vec![(align_up(start, Page4KB::P_SIZE), align_down(end, Page4KB::P_SIZE))];
+        for &(r_start, r_end) in self.reserved.iter() {
+            let mut remain = Vec::new();
+            for (start, end) in ranges {
+                if r_end <= start || end <= r_start {
+                    remain.push((start, end));
+                    continue;
+                }
+                if start < r_start {
+                    remain.push((start, r_start));
+                }
+                if r_end < end {
+                    remain.push((r_end, end));
+                }
+            }
+            ranges = remain;
+        }
+        for (start, end) in ranges {
+            if start < end {
+                self.inner.add_frame((start / Page4KB::P_SIZE) as usize, (end / Page4KB::P_SIZE) as usize);
+            }
+        }
+    }
+
+    /// 满足`layout`的大小和对齐要求所需的帧数，伙伴系统分配的块按自身大小对齐
+    fn frame_count(layout: Layout) -> usize {
+        let size = align_up(layout.size() as u64, Page4KB::P_SIZE) / Page4KB::P_SIZE;
+        let align = align_up(layout.align() as u64, Page4KB::P_SIZE) / Page4KB::P_SIZE;
+        max(size, align) as usize
+    }
+
+    fn to_frame(number: usize) -> Frame {
+        Frame::include_address(PhysAddr::new(number as u64 * Page4KB::P_SIZE))
+    }
+
+    fn to_number(frame: Frame) -> usize {
+        (frame.start_address().as_u64() / Page4KB::P_SIZE) as usize
+    }
+}
+
+impl MemoryAreaManagement for BuddyFrameAllocator {
+    fn add_area(&mut self, start_addr: u64, end_addr: u64, ty: MemoryType, _len: u64) {
+        if ty == MemoryType::FreeArea {
+            self.add_frames(start_addr, end_addr)
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Page4KB> for BuddyFrameAllocator {
+    fn alloc(&mut self) -> Option<UnusedFrame<Page4KB>> {
+        self.inner.alloc(1).map(|number| unsafe { UnusedFrame::new(Self::to_frame(number)) })
+    }
+
+    fn dealloc(&mut self, frame: UnusedFrame<Page4KB>) {
+        self.inner.dealloc(Self::to_number(frame.frame()), 1)
+    }
+
+    fn free_frames(&self) -> usize {
+        self.inner.stats_total() - self.inner.stats_allocated()
+    }
+
+    fn used_frames(&self) -> usize {
+        self.inner.stats_allocated()
+    }
+
+    fn alloc_size(&mut self, layout: Layout) -> Option<UnusedFrame<Page4KB>> {
+        if layout.size() == 0 {
+            return None;
+        }
+        let count = Self::frame_count(layout);
+        self.inner.alloc(count).map(|number| unsafe { UnusedFrame::new(Self::to_frame(number)) })
+    }
+
+    /// `count`必须与分配时的帧数相同
+    fn dealloc_size(&mut self, frame: Frame<Page4KB>, count: usize) {
+        self.inner.dealloc(Self::to_number(frame), count)
+    }
+}
@@ -1,13 +1,13 @@
-use crate::ia_32e::paging::frame::Frame;
-use crate::ia_32e::paging::{PageTable, PageTableEntry};
-use crate::ia_32e::paging::page::{Page4KB, Page, Page1GB, Page2MB};
-use crate::ia_32e::paging::result::{PageTableWalkError, CreatePageTableError, MapToError, UnmapError, FlagUpdateError, TranslateError, TranslationResult};
-use crate::ia_32e::paging::allocator::{FrameAllocator, UnusedFrame};
 use crate::bits::PageTableFlags;
-use crate::ia_32e::paging::mapper::{MapperFlush, Mapper, MapAllSize};
-use crate::ia_32e::paging::result::FrameError;
-use crate::ia_32e::VirtAddr;
 use crate::ia_32e::cpu::control::CR3;
+use crate::ia_32e::instructions::page_table::flush;
+use crate::ia_32e::paging::{five_level_paging, PageTable, PageTableEntry};
+use crate::ia_32e::paging::allocator::{FrameAllocator, UnusedFrame};
+use crate::ia_32e::paging::frame::Frame;
+use crate::ia_32e::paging::mapper::{MapAllSize, Mapper, MapperFlush};
+use crate::ia_32e::paging::page::{Page, Page1GB, Page2MB, Page4KB, PageSize};
+use crate::ia_32e::paging::result::{CreatePageTableError, FlagUpdateError, FrameError, MapToError, PageTableWalkError, TranslateError, TranslationResult, UnmapError};
+use crate::ia_32e::VirtAddr;
 
 /// 将给定的物理帧转换为页表裸指针
 pub trait PhysicalToVirtual {
@@ -27,9 +27,9 @@ struct PageTableWalker<P: PhysicalToVirtual> {
 }
 
 impl<P: PhysicalToVirtual> PageTableWalker<P> {
-    ///
     /// # Safety
     ///
+    /// `p`必须能将任意页表所在的物理帧转换为可以访问的虚拟地址
     pub unsafe fn new(p: P) -> Self {
         Self { phy_to_vir: p }
     }
@@ -37,41 +37,62 @@ impl<P: PhysicalToVirtual> PageTableWalker<P> {
     /// MappedPageTable内部辅助函数可获取对下一级页面表的引用。
     /// 如果未使用该条目，则返回 `PageTableWalkError::NotMapped`。
     /// 如果在传递的条目中设置了`HUGE_PAGE`标志，则返回`PageTableWalkError::MappedToHugePage`。
-    fn next_table<'a>(&self, entry: &'a PageTableEntry) -> Result<&'a PageTable, PageTableWalkError> {
+    fn next_table<'b>(&self, entry: &'b PageTableEntry) -> Result<&'b PageTable, PageTableWalkError> {
         let table_ptr = self.phy_to_vir.phy_to_vir(entry.frame()?);
         let page_table: &PageTable = unsafe { &*table_ptr };
         Ok(page_table)
     }
+
     /// MappedPageTable内部辅助函数可获取对下一级页面表的可变引用。
     /// 如果未使用该条目，则返回 `PageTableWalkError::NotMapped`。
     /// 如果在传递的条目中设置了`HUGE_PAGE`标志，则返回`PageTableWalkError::MappedToHugePage`。
-    fn next_table_mut<'a>(&self, entry: &'a mut PageTableEntry) -> Result<&'a mut PageTable, PageTableWalkError> {
+    fn next_table_mut<'b>(&self, entry: &'b mut PageTableEntry) -> Result<&'b mut PageTable, PageTableWalkError> {
         let table_ptr = self.phy_to_vir.phy_to_vir(entry.frame()?);
         let page_table: &mut PageTable = unsafe { &mut *table_ptr };
         Ok(page_table)
     }
 
+    /// 返回`addr`所在的4级页表，4级分页时就是`top`，5级分页时`top`是5级页表，需要多走一级
+    fn level_4<'b>(&self, top: &'b PageTable, addr: VirtAddr) -> Result<&'b PageTable, PageTableWalkError> {
+        if five_level_paging() { self.next_table(&top[addr.page5_index()]) } else { Ok(top) }
+    }
+
+    fn level_4_mut<'b>(&self, top: &'b mut PageTable, addr: VirtAddr) -> Result<&'b mut PageTable, PageTableWalkError> {
+        if five_level_paging() { self.next_table_mut(&mut top[addr.page5_index()]) } else { Ok(top) }
+    }
+
+    /// 与`level_4_mut`相同，5级分页时4级页表不存在则创建
+    fn create_level_4<'b, A>(&self, top: &'b mut PageTable, addr: VirtAddr, flags: PageTableFlags, allocator: &mut A)
+                             -> Result<&'b mut PageTable, CreatePageTableError> where A: FrameAllocator<Page4KB> {
+        if five_level_paging() { self.create_next_table(&mut top[addr.page5_index()], flags, allocator) } else { Ok(top) }
+    }
+
     /// MappedPageTable内部辅助函数可根据需要创建下一级的页表。
     /// 如果传递的`entry`未使用，则从给定的分配器分配一个新帧，将其清零，然后将该`entry`更新到该地址。
     /// 如果传递的`entry`已被映射，则直接返回下一个表。
-    /// 如果`entry`未使用并且分配器返回`None`，则返回`MapToError::FrameAllocationFailed`。
-    /// 如果在传递的条目中设置了`HUGE_PAGE`标志，则返回`MapToError::ParentEntryHugePage`。
-    fn create_next_table<'a, A>(&self, entry: &'a mut PageTableEntry, allocator: &mut A) -> Result<&'a mut PageTable, CreatePageTableError>
+    /// `parent_flags`中的`USER_ACCESSIBLE`会被添加到`entry`中，否则用户态无法访问映射的页面。
+    /// 如果`entry`未使用并且分配器返回`None`，则返回`CreatePageTableError::FrameAllocateFailed`。
+    /// 如果在传递的条目中设置了`HUGE_PAGE`标志，则返回`CreatePageTableError::MappedToHugePage`。
+    fn create_next_table<'b, A>(&self, entry: &'b mut PageTableEntry, parent_flags: PageTableFlags, allocator: &mut A)
+                                -> Result<&'b mut PageTable, CreatePageTableError>
         where A: FrameAllocator<Page4KB> {
+        let parent_flags = parent_flags & PageTableFlags::USER_ACCESSIBLE;
         let mut created = false;
         // 如果当前entry没有被使用可以创建新的entry
         if entry.is_unused() {
             // 申请新的帧
             if let Some(frame) = allocator.alloc() {
-                entry.set_frame(frame.frame(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+                entry.set_frame(frame.frame(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE | parent_flags);
                 created = true;
             } else {
                 return Err(CreatePageTableError::FrameAllocateFailed);
             }
+        } else if !entry.flags().contains(parent_flags) {
+            entry.set_flags(entry.flags() | parent_flags);
         }
         let pt = match self.next_table_mut(entry) {
             Ok(table) => table,
-            Err(PageTableWalkError::MappedToHugePage) => Err(CreatePageTableError::MappedToHugePage)?,
+            Err(PageTableWalkError::MappedToHugePage) => return Err(CreatePageTableError::MappedToHugePage),
             Err(PageTableWalkError::NotMapped) => panic!("entry should be mapped at this point"),
         };
         if created {
@@ -81,15 +102,20 @@ impl<P: PhysicalToVirtual> PageTableWalker<P> {
     }
 }
 
-/// 已映射的页表
+/// 通过`PhysicalToVirtual`访问页表的映射器，不依赖递归映射
 #[derive(Debug)]
 pub struct MappedPageTable<'a, P: PhysicalToVirtual> {
     pt_walker: PageTableWalker<P>,
+    /// 最高级的页表，5级分页时是5级页表
     level_4_table: &'a mut PageTable,
 }
 
 impl<'a, P: PhysicalToVirtual> MappedPageTable<'a, P> {
-    // 创建4级页表
+    /// 使用给定的4级页表创建映射器
+    ///
+    /// # Safety
+    ///
+    /// `level_4_table`必须是有效的最高级页表（5级分页时为5级页表），并且`phy_to_vir`能够访问所有页表所在的物理帧
     pub unsafe fn new(level_4_table: &'a mut PageTable, phy_to_vir: P) -> Self {
         Self {
             pt_walker: PageTableWalker::new(phy_to_vir),
@@ -97,62 +123,137 @@ impl<'a, P: PhysicalToVirtual> MappedPageTable<'a, P> {
         }
     }
 
+    /// 使用CR3中当前的4级页表创建映射器
+    ///
+    /// # Safety
+    ///
+    /// 与`new`相同，同时调用者需要保证不存在其他对当前4级页表的可变引用
     pub unsafe fn from_cr3(phy_to_vir: P) -> Self {
         let (frame, _) = CR3::read();
-        let pt = frame.start_address().as_u64() as *mut PageTable;
-        let pml4t = &mut *pt;
-        Self {
-            pt_walker: PageTableWalker::new(phy_to_vir),
-            level_4_table: pml4t,
+        let pml4t = &mut *phy_to_vir.phy_to_vir(frame);
+        Self::new(pml4t, phy_to_vir)
+    }
+
+    /// 返回映射`page`的页表项的flags，大页返回大页页表项的flags，没有映射时返回`None`
+    pub fn page_flags(&self, page: Page<Page4KB>) -> Option<PageTableFlags> {
+        let p4 = self.pt_walker.level_4(&self.level_4_table, page.start_address()).ok()?;
+        let p3_entry = &self.pt_walker.next_table(&p4[page.p4_index()]).ok()?[page.p3_index()];
+        if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(p3_entry.flags());
+        }
+        let p2_entry = &self.pt_walker.next_table(p3_entry).ok()?[page.p2_index()];
+        if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(p2_entry.flags());
         }
+        let p1_entry = &self.pt_walker.next_table(p2_entry).ok()?[page.p1_index()];
+        if p1_entry.is_unused() {
+            return None;
+        }
+        Some(p1_entry.flags())
+    }
+
+    /// 将包含`addr`的大页拆分为下一级的页面（1GB拆分为2MB，2MB拆分为4KB），物理地址和flags保持不变。
+    /// `addr`所在的页面不是大页时返回`Ok(false)`。
+    /// 新的页表填写完成后才替换大页表项，拆分期间该区域一直可以访问
+    pub fn split_huge_page<A>(&mut self, addr: VirtAddr, allocator: &mut A) -> Result<bool, MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB>
+    {
+        let page: Page<Page4KB> = Page::include_address(addr);
+        let p4 = match self.pt_walker.level_4_mut(&mut self.level_4_table, addr) {
+            Ok(table) => table,
+            Err(_) => return Ok(false),
+        };
+        let p3 = match self.pt_walker.next_table_mut(&mut p4[page.p4_index()]) {
+            Ok(table) => table,
+            Err(_) => return Ok(false),
+        };
+        let p3_entry = &mut p3[page.p3_index()];
+        if p3_entry.is_unused() {
+            return Ok(false);
+        }
+        if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            Self::split_entry(&self.pt_walker, p3_entry, addr, Page2MB::P_SIZE, allocator)?;
+            return Ok(true);
+        }
+        let p2 = self.pt_walker.next_table_mut(p3_entry).map_err(|_| MapToError::ParentEntryHugePage)?;
+        let p2_entry = &mut p2[page.p2_index()];
+        if p2_entry.is_unused() || !p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Ok(false);
+        }
+        Self::split_entry(&self.pt_walker, p2_entry, addr, Page4KB::P_SIZE, allocator)?;
+        Ok(true)
+    }
+
+    /// 使用新分配的页表替换`addr`所在大页的页表项，页表中的每一项映射大页中大小为`size`的一部分
+    fn split_entry<A>(walker: &PageTableWalker<P>, entry: &mut PageTableEntry, addr: VirtAddr, size: u64, allocator: &mut A) -> Result<(), MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB>
+    {
+        let frame = allocator.alloc().ok_or(MapToError::FrameAllocateFailed)?;
+        let base = entry.addr();
+        let flags = entry.flags();
+        // 4KB页表项中第7位是PAT而不是HUGE_PAGE
+        let leaf_flags = if size == Page4KB::P_SIZE { flags - PageTableFlags::HUGE_PAGE } else { flags };
+        let table = unsafe { &mut *walker.phy_to_vir.phy_to_vir(frame.frame()) };
+        for (i, e) in table.iter_mut().enumerate() {
+            e.set_addr(base + i as u64 * size, leaf_flags);
+        }
+        entry.set_frame(frame.frame(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USER_ACCESSIBLE));
+        // 刷新大页中的任意地址即可使整个大页的TLB失效
+        unsafe { flush(addr) };
+        Ok(())
     }
 
     // 根据给定的帧和页面进行1gb页面映射
-    unsafe fn map_to_1g<A>(&mut self, page: Page<Page1GB>, frame: UnusedFrame<Page1GB>, flags: PageTableFlags, allocator: &mut A)
+    fn map_to_1g<A>(&mut self, page: Page<Page1GB>, frame: Frame<Page1GB>, flags: PageTableFlags, allocator: &mut A)
                     -> Result<MapperFlush<Page1GB>, MapToError<Page1GB>>
         where A: FrameAllocator<Page4KB> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], allocator)?;
+        let p4 = self.pt_walker.create_level_4(&mut self.level_4_table, page.start_address(), flags, allocator)?;
+        // 创建3级页表
+        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], flags, allocator)?;
         // 将frame与页面做映射
         if !p3[page.p3_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped(frame));
+            return Err(MapToError::PageAlreadyMapped(unsafe { UnusedFrame::new(frame) }));
         }
         p3[page.p3_index()].set_addr(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
+
         Ok(MapperFlush::new(page))
     }
+
     // 根据给定的帧和页面进行2mb页面映射
-    unsafe fn map_to_2mb<A>(&mut self, page: Page<Page2MB>, frame: UnusedFrame<Page2MB>, flags: PageTableFlags, allocator: &mut A)
+    fn map_to_2mb<A>(&mut self, page: Page<Page2MB>, frame: Frame<Page2MB>, flags: PageTableFlags, allocator: &mut A)
                      -> Result<MapperFlush<Page2MB>, MapToError<Page2MB>>
         where A: FrameAllocator<Page4KB> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self.pt_walker.create_level_4(&mut self.level_4_table, page.start_address(), flags, allocator)?;
         // 创建3级页表
-        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], allocator)?;
+        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], flags, allocator)?;
         // 创建2级页表
-        let p2 = self.pt_walker.create_next_table(&mut p3[page.p3_index()], allocator)?;
+        let p2 = self.pt_walker.create_next_table(&mut p3[page.p3_index()], flags, allocator)?;
         // 将frame与页面做映射
         if !p2[page.p2_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped(frame));
+            return Err(MapToError::PageAlreadyMapped(unsafe { UnusedFrame::new(frame) }));
         }
         p2[page.p2_index()].set_addr(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
 
         Ok(MapperFlush::new(page))
     }
+
     // 根据给定的帧和页面进行4kb页面映射
-    unsafe fn map_to_4kb<A>(&mut self, page: Page<Page4KB>, frame: UnusedFrame<Page4KB>, flags: PageTableFlags, allocator: &mut A)
+    fn map_to_4kb<A>(&mut self, page: Page<Page4KB>, frame: Frame<Page4KB>, flags: PageTableFlags, allocator: &mut A)
                      -> Result<MapperFlush<Page4KB>, MapToError<Page4KB>>
         where A: FrameAllocator<Page4KB> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self.pt_walker.create_level_4(&mut self.level_4_table, page.start_address(), flags, allocator)?;
         // 创建3级页表
-        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], allocator)?;
+        let p3 = self.pt_walker.create_next_table(&mut p4[page.p4_index()], flags, allocator)?;
         // 创建2级页表
-        let p2 = self.pt_walker.create_next_table(&mut p3[page.p3_index()], allocator)?;
+        let p2 = self.pt_walker.create_next_table(&mut p3[page.p3_index()], flags, allocator)?;
         // 创建1级页表
-        let p1 = self.pt_walker.create_next_table(&mut p2[page.p2_index()], allocator)?;
+        let p1 = self.pt_walker.create_next_table(&mut p2[page.p2_index()], flags, allocator)?;
 
         if !p1[page.p1_index()].is_unused() {
-            return Err(MapToError::PageAlreadyMapped(frame));
+            return Err(MapToError::PageAlreadyMapped(unsafe { UnusedFrame::new(frame) }));
         }
-        p1[page.p1_index()].set_frame(frame.frame(), flags);
+        // 4KB页表项中第7位是PAT，可能由`CacheType::WriteCombining`设置
+        p1[page.p1_index()].set_addr(frame.start_address(), flags);
 
         Ok(MapperFlush::new(page))
     }
@@ -163,19 +264,20 @@ impl<'a, P: PhysicalToVirtual> MappedPageTable<'a, P> {
 /////////////////////
 
 impl<'a, P: PhysicalToVirtual> Mapper<Page4KB> for MappedPageTable<'a, P> {
-    unsafe fn map_to<A>(&mut self, page: Page<Page4KB>, frame: UnusedFrame<Page4KB>, flags: PageTableFlags, allocator: &mut A) -> Result<MapperFlush<Page4KB>, MapToError<Page4KB>> where A: FrameAllocator<Page4KB>, Self: Sized {
+    unsafe fn map_to<A>(&mut self, page: Page<Page4KB>, frame: Frame<Page4KB>, flags: PageTableFlags, allocator: &mut A)
+                        -> Result<MapperFlush<Page4KB>, MapToError<Page4KB>> where A: FrameAllocator<Page4KB>, Self: Sized {
         self.map_to_4kb(page, frame, flags, allocator)
     }
 
     fn unmap(&mut self, page: Page<Page4KB>) -> Result<(Frame<Page4KB>, MapperFlush<Page4KB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
-        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p1_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p2_index()])?;
 
         let entry = &mut p1[page.p1_index()];
 
-        let frame = entry.frame().map_err(|err| match err {
+        let frame = entry.leaf_frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
@@ -185,12 +287,12 @@ impl<'a, P: PhysicalToVirtual> Mapper<Page4KB> for MappedPageTable<'a, P> {
     }
 
     unsafe fn update_flags(&mut self, page: Page<Page4KB>, flags: PageTableFlags) -> Result<MapperFlush<Page4KB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
-        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p1_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p2_index()])?;
 
-        if !p1[page.p1_index()].is_unused() {
+        if p1[page.p1_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
@@ -200,12 +302,12 @@ impl<'a, P: PhysicalToVirtual> Mapper<Page4KB> for MappedPageTable<'a, P> {
     }
 
     fn translate_page(&mut self, page: Page<Page4KB>) -> Result<Frame<Page4KB>, TranslateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
-        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p1_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = self.pt_walker.next_table_mut(&mut p2[page.p2_index()])?;
 
-        let entry = p1[page.p1_index()];
+        let entry = &p1[page.p1_index()];
 
         if entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
@@ -216,14 +318,15 @@ impl<'a, P: PhysicalToVirtual> Mapper<Page4KB> for MappedPageTable<'a, P> {
 }
 
 impl<'a, P: PhysicalToVirtual> Mapper<Page2MB> for MappedPageTable<'a, P> {
-    unsafe fn map_to<A>(&mut self, page: Page<Page2MB>, frame: UnusedFrame<Page2MB>, flags: PageTableFlags, allocator: &mut A) -> Result<MapperFlush<Page2MB>, MapToError<Page2MB>> where A: FrameAllocator<Page4KB>, Self: Sized {
+    unsafe fn map_to<A>(&mut self, page: Page<Page2MB>, frame: Frame<Page2MB>, flags: PageTableFlags, allocator: &mut A)
+                        -> Result<MapperFlush<Page2MB>, MapToError<Page2MB>> where A: FrameAllocator<Page4KB>, Self: Sized {
         self.map_to_2mb(page, frame, flags, allocator)
     }
 
     fn unmap(&mut self, page: Page<Page2MB>) -> Result<(Frame<Page2MB>, MapperFlush<Page2MB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
 
         let entry = &mut p2[page.p2_index()];
         let flags = entry.flags();
@@ -243,42 +346,41 @@ impl<'a, P: PhysicalToVirtual> Mapper<Page2MB> for MappedPageTable<'a, P> {
     }
 
     unsafe fn update_flags(&mut self, page: Page<Page2MB>, flags: PageTableFlags) -> Result<MapperFlush<Page2MB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
 
         if p2[page.p2_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
-        let entry = &mut p2[page.p2_index()];
+        p2[page.p2_index()].set_flags(flags | PageTableFlags::HUGE_PAGE);
 
-        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
         Ok(MapperFlush::new(page))
     }
 
     fn translate_page(&mut self, page: Page<Page2MB>) -> Result<Frame<Page2MB>, TranslateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p2_index()])?;
-        let entry = &mut p2[page.p2_index()];
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let p2 = self.pt_walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let entry = &p2[page.p2_index()];
 
         if entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
-        Frame::from_start_addr(entry.addr()).map_err(|_| {
-            TranslateError::InvalidFrameAddress(entry.addr())
-        })
+        Frame::from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
     }
 }
 
 impl<'a, P: PhysicalToVirtual> Mapper<Page1GB> for MappedPageTable<'a, P> {
-    unsafe fn map_to<A>(&mut self, page: Page<Page1GB>, frame: UnusedFrame<Page1GB>, flags: PageTableFlags, allocator: &mut A) -> Result<MapperFlush<Page1GB>, MapToError<Page1GB>> where A: FrameAllocator<Page4KB>, Self: Sized {
+    unsafe fn map_to<A>(&mut self, page: Page<Page1GB>, frame: Frame<Page1GB>, flags: PageTableFlags, allocator: &mut A)
+                        -> Result<MapperFlush<Page1GB>, MapToError<Page1GB>> where A: FrameAllocator<Page4KB>, Self: Sized {
         self.map_to_1g(page, frame, flags, allocator)
     }
 
     fn unmap(&mut self, page: Page<Page1GB>) -> Result<(Frame<Page1GB>, MapperFlush<Page1GB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
 
         let entry = &mut p3[page.p3_index()];
         let flags = entry.flags();
@@ -299,34 +401,38 @@ impl<'a, P: PhysicalToVirtual> Mapper<Page1GB> for MappedPageTable<'a, P> {
     }
 
     unsafe fn update_flags(&mut self, page: Page<Page1GB>, flags: PageTableFlags) -> Result<MapperFlush<Page1GB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
 
         if p3[page.p3_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
-        let entry = &mut p3[page.p3_index()];
-        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
+        p3[page.p3_index()].set_flags(flags | PageTableFlags::HUGE_PAGE);
+
         Ok(MapperFlush::new(page))
     }
 
     fn translate_page(&mut self, page: Page<Page1GB>) -> Result<Frame<Page1GB>, TranslateError> {
-        let p4 = &mut self.level_4_table;
-        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p3_index()])?;
-        let entry = &mut p3[page.p3_index()];
+        let p4 = self.pt_walker.level_4_mut(&mut self.level_4_table, page.start_address())?;
+        let p3 = self.pt_walker.next_table_mut(&mut p4[page.p4_index()])?;
+        let entry = &p3[page.p3_index()];
 
         if entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
-        Frame::from_start_addr(entry.addr()).map_err(|_| {
-            TranslateError::InvalidFrameAddress(entry.addr())
-        })
+        Frame::from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
     }
 }
 
 impl<'a, P: PhysicalToVirtual> MapAllSize for MappedPageTable<'a, P> {
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn translate(&self, addr: VirtAddr) -> TranslationResult {
-        let p4 = &self.level_4_table;
+        let p4 = match self.pt_walker.level_4(&self.level_4_table, addr) {
+            Ok(pt) => pt,
+            Err(PageTableWalkError::NotMapped) => return TranslationResult::PageNotMapped,
+            Err(PageTableWalkError::MappedToHugePage) => panic!("level 5 entry has huge page bit set")
+        };
         let p3 = match self.pt_walker.next_table(&p4[addr.page4_index()]) {
             Ok(pt) => pt,
             Err(PageTableWalkError::NotMapped) => return TranslationResult::PageNotMapped,
@@ -346,8 +452,8 @@ impl<'a, P: PhysicalToVirtual> MapAllSize for MappedPageTable<'a, P> {
             Err(PageTableWalkError::NotMapped) => return TranslationResult::PageNotMapped,
             Err(PageTableWalkError::MappedToHugePage) => {
                 let frame = Frame::include_address(p2[addr.page2_index()].addr());
-                let offset = addr.as_u64() & 0o_777_777_7777;
-                return TranslationResult::Frame1GB { frame, offset };
+                let offset = addr.as_u64() & 0o_777_7777;
+                return TranslationResult::Frame2MB { frame, offset };
             }
         };
 
@@ -364,4 +470,4 @@ impl<'a, P: PhysicalToVirtual> MapAllSize for MappedPageTable<'a, P> {
         let offset = u64::from(addr.page_offset());
         TranslationResult::Frame4KB { frame, offset }
     }
-}
\ No newline at end of file
+}
@@ -1,17 +1,18 @@
-// pub use map_pt::{MappedPageTable, PhysicalToVirtual};
-// pub use pt_offset::{PageTableOffset, PhysOffset};
+pub use map_pt::{MappedPageTable, PhysicalToVirtual};
+pub use pt_offset::{PageTableOffset, PhysOffset};
 
 pub use page::RecursivePageTable;
 
 use crate::bits::PageTableFlags;
 use crate::ia_32e::{PhysAddr, VirtAddr};
-use crate::ia_32e::paging::allocator::FrameAllocator;
+use crate::ia_32e::cpu::pat::CacheType;
+use crate::ia_32e::paging::allocator::{FrameAllocator, UnusedFrame};
 use crate::ia_32e::paging::frame::Frame;
 use crate::ia_32e::paging::page::{Page, Page1GB, Page2MB, Page4KB, PageSize};
 use crate::ia_32e::paging::result::{FlagUpdateError, MapToError, TranslateError, TranslationResult, UnmapError};
 
-// mod map_pt;
-// mod pt_offset;
+mod map_pt;
+mod pt_offset;
 // mod recursive_table;
 mod page;
 
@@ -24,9 +25,10 @@ impl<S: PageSize> MapperFlush<S> {
         Self(page)
     }
 
+    /// 刷新当前CPU的TLB，注册了`set_shootdown_handler`时同时通知其他CPU
     pub fn flush(self) {
         unsafe {
-            crate::ia_32e::instructions::page_table::flush(self.0.start_address());
+            crate::ia_32e::instructions::page_table::flush_shared(self.0.start_address(), S::P_SIZE);
         }
     }
 
@@ -76,4 +78,46 @@ pub trait MapAllSize: Mapper<Page4KB> + Mapper<Page1GB> + Mapper<Page2MB> {
             TranslationResult::PageNotMapped | TranslationResult::InvalidFrameAddress(_) => None,
         }
     }
-}
\ No newline at end of file
+}
+/// 将物理地址`[phys, phys + size)`映射到从`virt`开始的虚拟地址，内存类型为`cache`，`flags`中的缓存位被忽略。
+/// 虚拟地址，物理地址和剩余大小都满足对齐要求时使用2MB的大页，`gigabyte`为true时还会使用1GB的大页
+/// （需要CPU支持，见`CPUID.80000001H:EDX[26]`）
+pub unsafe fn map_range<M, A>(mapper: &mut M, virt: VirtAddr, phys: PhysAddr, size: u64, flags: PageTableFlags,
+                              cache: CacheType, gigabyte: bool, allocator: &mut A) -> Result<(), MapToError<Page4KB>>
+    where M: MapAllSize, A: FrameAllocator<Page4KB>
+{
+    let flags = (flags - CacheType::mask()) | cache.flags();
+    let huge = cache.huge_page_allowed();
+    let fits = |offset: u64, page_size: u64| {
+        (virt.as_u64() + offset) % page_size == 0 && (phys.as_u64() + offset) % page_size == 0 && size - offset >= page_size
+    };
+    let mut offset = 0;
+    while offset < size {
+        let page_virt = virt + offset;
+        let page_phys = phys + offset;
+        if huge && gigabyte && fits(offset, Page1GB::P_SIZE) {
+            let page: Page<Page1GB> = Page::include_address(page_virt);
+            Mapper::<Page1GB>::map_to(mapper, page, Frame::include_address(page_phys), flags, allocator).map_err(huge_map_error)?.flush();
+            offset += Page1GB::P_SIZE;
+        } else if huge && fits(offset, Page2MB::P_SIZE) {
+            let page: Page<Page2MB> = Page::include_address(page_virt);
+            Mapper::<Page2MB>::map_to(mapper, page, Frame::include_address(page_phys), flags, allocator).map_err(huge_map_error)?.flush();
+            offset += Page2MB::P_SIZE;
+        } else {
+            let page: Page<Page4KB> = Page::include_address(page_virt);
+            Mapper::<Page4KB>::map_to(mapper, page, Frame::include_address(page_phys), flags, allocator)?.flush();
+            offset += Page4KB::P_SIZE;
+        }
+    }
+    Ok(())
+}
+
+fn huge_map_error<S: PageSize>(err: MapToError<S>) -> MapToError<Page4KB> {
+    match err {
+        MapToError::FrameAllocateFailed => MapToError::FrameAllocateFailed,
+        MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+        MapToError::PageAlreadyMapped(frame) => {
+            MapToError::PageAlreadyMapped(unsafe { UnusedFrame::new(Frame::include_address(frame.start_address())) })
+        }
+    }
+}
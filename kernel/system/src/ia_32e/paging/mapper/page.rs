@@ -1,12 +1,14 @@
 use crate::bits::PageTableFlags;
 use crate::ia_32e::cpu::control::CR3;
-use crate::ia_32e::paging::{Frame, FrameAllocator, NotGiantPageSize, Page, Page1GB, Page2MB, Page4KB, PageIndex, PageSize, PageTable, PageTableEntry, UnusedFrame};
+use crate::ia_32e::instructions::page_table::flush;
+use crate::ia_32e::paging::{five_level_paging, Frame, FrameAllocator, NotGiantPageSize, Page, Page1GB, Page2MB, Page4KB, PageIndex, PageSize, PageTable, PageTableEntry, paging_levels, UnusedFrame};
 use crate::ia_32e::paging::mapper::{Mapper, MapperFlush, MapAllSize};
 use crate::ia_32e::paging::result::{FlagUpdateError, FrameError, MapToError, TranslateError, UnmapError, TranslationResult};
 use crate::ia_32e::VirtAddr;
 
 #[derive(Debug)]
 pub struct RecursivePageTable<'a> {
+    /// 最高级的页表，5级分页时是5级页表
     p4: &'a mut PageTable,
     recursive_index: PageIndex,
 }
@@ -25,13 +27,10 @@ impl<'a> RecursivePageTable<'a> {
     /// Otherwise `Err(())` is returned.
     #[inline]
     pub fn new(table: &'a mut PageTable) -> Result<Self, ()> {
-        let page = Page::include_address(VirtAddr::new(table as *const _ as u64));
-        let recursive_index = page.p4_index();
+        let addr = VirtAddr::new(table as *const _ as u64);
+        let recursive_index = addr.page_index(paging_levels());
 
-        if page.p3_index() != recursive_index
-            || page.p2_index() != recursive_index
-            || page.p1_index() != recursive_index
-        {
+        if (1..paging_levels()).any(|level| addr.page_index(level) != recursive_index) {
             return Err(());
         }
         if Ok(CR3::read().0) != table[recursive_index].frame() {
@@ -57,6 +56,114 @@ impl<'a> RecursivePageTable<'a> {
         }
     }
 
+    /// 递归项为`recursive_index`时最高级页表的虚拟地址，5级分页时为5级页表
+    pub fn table_address(recursive_index: PageIndex) -> VirtAddr {
+        table_page(VirtAddr::zero(), paging_levels(), recursive_index).start_address()
+    }
+
+    /// 返回`page`所在的4级页表，4级分页时就是最高级页表，
+    /// 5级分页时对应的5级页表项未使用则返回`None`
+    fn level_4<S: PageSize>(&self, page: Page<S>) -> Option<&PageTable> {
+        if !five_level_paging() {
+            return Some(&*self.p4);
+        }
+        if self.p4[page.p5_index()].is_unused() {
+            return None;
+        }
+        Some(unsafe { &*table_page(page.start_address(), 4, self.recursive_index).start_address().as_ptr() })
+    }
+
+    /// 与`level_4`相同，5级分页时4级页表不存在则创建
+    unsafe fn create_level_4<'b, A, S: PageSize>(top: &'b mut PageTable, page: Page<S>, recursive_index: PageIndex,
+                                                  flags: PageTableFlags, allocator: &mut A) -> Result<&'b mut PageTable, MapToError<S>>
+        where A: FrameAllocator<Page4KB>
+    {
+        if !five_level_paging() {
+            return Ok(top);
+        }
+        let p4_page = table_page(page.start_address(), 4, recursive_index);
+        Self::create_next_table(&mut top[page.p5_index()], p4_page, flags, allocator)
+    }
+
+    /// 返回映射`page`的页表项的flags，如果由大页映射则返回大页的flags，没有映射时返回`None`
+    pub fn page_flags(&self, page: Page<Page4KB>) -> Option<PageTableFlags> {
+        if self.level_4(page).map_or(true, |p4| p4[page.p4_index()].is_unused()) {
+            return None;
+        }
+        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index)) };
+        let p3_entry = &p3[page.p3_index()];
+        if p3_entry.is_unused() {
+            return None;
+        }
+        if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(p3_entry.flags());
+        }
+        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index)) };
+        let p2_entry = &p2[page.p2_index()];
+        if p2_entry.is_unused() {
+            return None;
+        }
+        if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(p2_entry.flags());
+        }
+        let p1 = unsafe { &*(p1_ptr(page, self.recursive_index)) };
+        let p1_entry = &p1[page.p1_index()];
+        if p1_entry.is_unused() {
+            return None;
+        }
+        Some(p1_entry.flags())
+    }
+
+    /// 将包含`addr`的大页拆分为下一级的页面（1GB拆分为2MB，2MB拆分为4KB），物理地址和flags保持不变。
+    /// `addr`所在的页面不是大页时返回`Ok(false)`。
+    ///
+    /// 拆分期间该区域暂时不可访问，所以不能拆分正在执行的代码或者当前栈所在的大页。
+    pub fn split_huge_page<A>(&mut self, addr: VirtAddr, allocator: &mut A) -> Result<bool, MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB>
+    {
+        let page: Page<Page4KB> = Page::include_address(addr);
+        if self.level_4(page).map_or(true, |p4| p4[page.p4_index()].is_unused()) {
+            return Ok(false);
+        }
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3_entry = &mut p3[page.p3_index()];
+        if p3_entry.is_unused() {
+            return Ok(false);
+        }
+        if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            unsafe { Self::split_entry(p3_entry, p2_page(page, self.recursive_index), addr, Page2MB::P_SIZE, allocator)? };
+            return Ok(true);
+        }
+        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index)) };
+        let p2_entry = &mut p2[page.p2_index()];
+        if p2_entry.is_unused() || !p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Ok(false);
+        }
+        unsafe { Self::split_entry(p2_entry, p1_page(page, self.recursive_index), addr, Page4KB::P_SIZE, allocator)? };
+        Ok(true)
+    }
+
+    /// 使用新分配的页表替换`addr`所在大页的页表项，页表中的每一项映射大页中大小为`size`的一部分
+    unsafe fn split_entry<A>(entry: &mut PageTableEntry, table_page: Page, addr: VirtAddr, size: u64, allocator: &mut A) -> Result<(), MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB>
+    {
+        let frame = allocator.alloc().ok_or(MapToError::FrameAllocateFailed)?;
+        let base = entry.addr();
+        let flags = entry.flags();
+        // 4KB页表项中第7位是PAT而不是HUGE_PAGE
+        let leaf_flags = if size == Page4KB::P_SIZE { flags - PageTableFlags::HUGE_PAGE } else { flags };
+        entry.set_frame(frame.frame(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USER_ACCESSIBLE));
+        // 递归映射中页表的地址此前可能指向大页的内容
+        flush(table_page.start_address());
+        let table: &mut PageTable = &mut *table_page.start_address().as_mut_ptr();
+        for (i, e) in table.iter_mut().enumerate() {
+            e.set_addr(base + i as u64 * size, leaf_flags);
+        }
+        // 刷新大页中的任意地址即可使整个大页的TLB失效
+        flush(addr);
+        Ok(())
+    }
+
     /// Internal helper function to create the page table of the next level if needed.
     ///
     /// If the passed entry is unused, a new frame is allocated from the given allocator, zeroed,
@@ -68,9 +175,13 @@ impl<'a> RecursivePageTable<'a> {
     /// Returns `MapToError::FrameAllocationFailed` if the entry is unused and the allocator
     /// returned `None`. Returns `MapToError::ParentEntryHugePage` if the `HUGE_PAGE` flag is set
     /// in the passed entry.
+    ///
+    /// `USER_ACCESSIBLE` in `parent_flags` is added to the entry, otherwise user mode could not
+    /// access the mapped page.
     unsafe fn create_next_table<'b, A, S: PageSize>(
         entry: &'b mut PageTableEntry,
         next_table_page: Page,
+        parent_flags: PageTableFlags,
         allocator: &mut A,
     ) -> Result<&'b mut PageTable, MapToError<S>>
         where
@@ -82,6 +193,7 @@ impl<'a> RecursivePageTable<'a> {
         fn inner<'b, A, S: PageSize>(
             entry: &'b mut PageTableEntry,
             next_table_page: Page,
+            parent_flags: PageTableFlags,
             allocator: &mut A,
         ) -> Result<&'b mut PageTable, MapToError<S>>
             where
@@ -90,15 +202,19 @@ impl<'a> RecursivePageTable<'a> {
 
             use crate::bits::flags::PageTableFlags as Flags;
             let created;
+            let parent_flags = parent_flags & Flags::USER_ACCESSIBLE;
 
             if entry.is_unused() {
                 if let Some(frame) = allocator.alloc() {
-                    entry.set_frame(frame.frame(), Flags::PRESENT | Flags::WRITABLE);
+                    entry.set_frame(frame.frame(), Flags::PRESENT | Flags::WRITABLE | parent_flags);
                     created = true;
                 } else {
                     return Err(MapToError::FrameAllocateFailed);
                 }
             } else {
+                if !entry.flags().contains(parent_flags) {
+                    entry.set_flags(entry.flags() | parent_flags);
+                }
                 created = false;
             }
             if entry.flags().contains(Flags::HUGE_PAGE) {
@@ -113,7 +229,7 @@ impl<'a> RecursivePageTable<'a> {
             Ok(page_table)
         }
 
-        inner(entry, next_table_page, allocator)
+        inner(entry, next_table_page, parent_flags, allocator)
     }
 
     /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
@@ -129,10 +245,10 @@ impl<'a> RecursivePageTable<'a> {
             A: FrameAllocator<Page4KB>,
     {
         use crate::bits::flags::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = unsafe { Self::create_level_4(&mut self.p4, page, self.recursive_index, flags, allocator)? };
 
         let p3_page = p3_page(page, self.recursive_index);
-        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
+        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, flags, allocator)? };
 
         if !p3[page.p3_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(unsafe{UnusedFrame::new(frame)}));
@@ -155,13 +271,13 @@ impl<'a> RecursivePageTable<'a> {
             A: FrameAllocator<Page4KB>,
     {
         use crate::bits::flags::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = unsafe { Self::create_level_4(&mut self.p4, page, self.recursive_index, flags, allocator)? };
 
         let p3_page = p3_page(page, self.recursive_index);
-        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
+        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, flags, allocator)? };
 
         let p2_page = p2_page(page, self.recursive_index);
-        let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)? };
+        let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, flags, allocator)? };
 
         if !p2[page.p2_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(unsafe{UnusedFrame::new(frame)}));
@@ -183,21 +299,22 @@ impl<'a> RecursivePageTable<'a> {
         where
             A: FrameAllocator<Page4KB>,
     {
-        let p4 = &mut self.p4;
+        let p4 = unsafe { Self::create_level_4(&mut self.p4, page, self.recursive_index, flags, allocator)? };
 
         let p3_page = p3_page(page, self.recursive_index);
-        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
+        let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, flags, allocator)? };
 
         let p2_page = p2_page(page, self.recursive_index);
-        let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)? };
+        let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, flags, allocator)? };
 
         let p1_page = p1_page(page, self.recursive_index);
-        let p1 = unsafe { Self::create_next_table(&mut p2[page.p2_index()], p1_page, allocator)? };
+        let p1 = unsafe { Self::create_next_table(&mut p2[page.p2_index()], p1_page, flags, allocator)? };
 
         if !p1[page.p1_index()].is_unused() {
             return Err(MapToError::PageAlreadyMapped(unsafe{UnusedFrame::new(frame)}));
         }
-        p1[page.p1_index()].set_frame(frame, flags);
+        // 4KB页表项中第7位是PAT，可能由`CacheType::WriteCombining`设置
+        p1[page.p1_index()].set_addr(frame.start_address(), flags);
 
         Ok(MapperFlush::new(page))
     }
@@ -222,7 +339,7 @@ impl<'a> Mapper<Page1GB> for RecursivePageTable<'a> {
         &mut self,
         page: Page<Page1GB>,
     ) -> Result<(Frame<Page1GB>, MapperFlush<Page1GB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
 
         p4_entry.frame().map_err(|err| match err {
@@ -256,7 +373,7 @@ impl<'a> Mapper<Page1GB> for RecursivePageTable<'a> {
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Page1GB>, FlagUpdateError> {
         use crate::bits::flags::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -273,7 +390,7 @@ impl<'a> Mapper<Page1GB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&mut self, page: Page<Page1GB>) -> Result<Frame<Page1GB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.level_4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
@@ -310,7 +427,7 @@ impl<'a> Mapper<Page2MB> for RecursivePageTable<'a> {
         &mut self,
         page: Page<Page2MB>,
     ) -> Result<(Frame<Page2MB>, MapperFlush<Page2MB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
         p4_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
@@ -350,7 +467,7 @@ impl<'a> Mapper<Page2MB> for RecursivePageTable<'a> {
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Page2MB>, FlagUpdateError> {
         use crate::bits::flags::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -374,7 +491,7 @@ impl<'a> Mapper<Page2MB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&mut self, page: Page<Page2MB>) -> Result<Frame<Page2MB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.level_4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
@@ -418,7 +535,7 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         &mut self,
         page: Page<Page4KB>,
     ) -> Result<(Frame<Page4KB>, MapperFlush<Page4KB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
         p4_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
@@ -442,7 +559,7 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         let p1 = unsafe { &mut *(p1_ptr(page.clone(), self.recursive_index)) };
         let p1_entry = &mut p1[page.p1_index()];
 
-        let frame = p1_entry.frame().map_err(|err| match err {
+        let frame = p1_entry.leaf_frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
@@ -458,7 +575,7 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         page: Page<Page4KB>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Page4KB>, FlagUpdateError> {
-        let p4 = &mut self.p4;
+        let p4 = self.level_4(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -469,12 +586,18 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         if p3[page.p3_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
+        if p3[page.p3_index()].flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::ParentEntryHugePage);
+        }
 
         let p2 = unsafe { &mut *(p2_ptr(page.clone(), self.recursive_index)) };
 
         if p2[page.p2_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
+        if p2[page.p2_index()].flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::ParentEntryHugePage);
+        }
 
         let p1 = unsafe { &mut *(p1_ptr(page.clone(), self.recursive_index)) };
 
@@ -488,7 +611,7 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&mut self, page: Page<Page4KB>) -> Result<Frame<Page4KB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.level_4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
@@ -500,6 +623,9 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         if p3_entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
+        if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::ParentEntryHugePage);
+        }
 
         let p2 = unsafe { &*(p2_ptr(page.clone(), self.recursive_index)) };
         let p2_entry = &p2[page.p2_index()];
@@ -507,6 +633,9 @@ impl<'a> Mapper<Page4KB> for RecursivePageTable<'a> {
         if p2_entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
+        if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::ParentEntryHugePage);
+        }
 
         let p1 = unsafe { &*(p1_ptr(page.clone(), self.recursive_index)) };
         let p1_entry = &p1[page.p1_index()];
@@ -525,7 +654,10 @@ impl<'a> MapAllSize for RecursivePageTable<'a> {
     fn translate(&self, addr: VirtAddr) -> TranslationResult {
         let page = Page::include_address(addr);
 
-        let p4 = &self.p4;
+        let p4 = match self.level_4(page) {
+            Some(p4) => p4,
+            None => return TranslationResult::PageNotMapped,
+        };
         let p4_entry = &p4[addr.page4_index()];
         if p4_entry.is_unused() {
             return TranslationResult::PageNotMapped;
@@ -553,7 +685,7 @@ impl<'a> MapAllSize for RecursivePageTable<'a> {
         if p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
             let frame = Frame::include_address(p2[addr.page2_index()].addr());
             let offset = addr.as_u64() & 0o_777_7777;
-            return TranslationResult::Frame4KB { frame, offset };
+            return TranslationResult::Frame2MB { frame, offset };
         }
 
         let p1 = unsafe { &*(p1_ptr(page, self.recursive_index)) };
@@ -561,15 +693,24 @@ impl<'a> MapAllSize for RecursivePageTable<'a> {
         if p1_entry.is_unused() {
             return TranslationResult::PageNotMapped;
         }
-        if p1_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
-            panic!("level 1 entry has huge page bit set")
-        }
         let frame = Frame::include_address(p1_entry.addr());
         let offset = u64::from(addr.page_offset());
         TranslationResult::Frame4KB { frame, offset }
     }
 }
 
+/// 通过递归映射访问`addr`所在的`level`级页表的页面。
+/// 地址中最高的`level`个索引都是递归项，其余依次是`addr`在更高级页表中的索引
+fn table_page(addr: VirtAddr, level: usize, recursive_index: PageIndex) -> Page {
+    let levels = paging_levels();
+    let mut raw = 0;
+    for slot in 1..=levels {
+        let index = if slot > levels - level { recursive_index } else { addr.page_index(slot + level) };
+        raw |= u64::from(index) << (12 + 9 * (slot - 1));
+    }
+    Page::include_address(VirtAddr::new_unchecked(raw))
+}
+
 #[inline]
 fn p3_ptr<S: PageSize>(page: Page<S>, recursive_index: PageIndex) -> *mut PageTable {
     p3_page(page, recursive_index).start_address().as_mut_ptr()
@@ -577,12 +718,7 @@ fn p3_ptr<S: PageSize>(page: Page<S>, recursive_index: PageIndex) -> *mut PageTa
 
 #[inline]
 fn p3_page<S: PageSize>(page: Page<S>, recursive_index: PageIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        recursive_index,
-        recursive_index,
-        page.p4_index(),
-    )
+    table_page(page.start_address(), 3, recursive_index)
 }
 
 #[inline]
@@ -592,12 +728,7 @@ fn p2_ptr<S: NotGiantPageSize>(page: Page<S>, recursive_index: PageIndex) -> *mu
 
 #[inline]
 fn p2_page<S: NotGiantPageSize>(page: Page<S>, recursive_index: PageIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        recursive_index,
-        page.p4_index(),
-        page.p3_index(),
-    )
+    table_page(page.start_address(), 2, recursive_index)
 }
 
 #[inline]
@@ -607,12 +738,5 @@ fn p1_ptr(page: Page<Page4KB>, recursive_index: PageIndex) -> *mut PageTable {
 
 #[inline]
 fn p1_page(page: Page<Page4KB>, recursive_index: PageIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        page.p4_index(),
-        page.p3_index(),
-        page.p2_index(),
-    )
+    table_page(page.start_address(), 1, recursive_index)
 }
-
-
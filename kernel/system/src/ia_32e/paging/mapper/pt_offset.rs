@@ -1,18 +1,29 @@
+use crate::bits::PageTableFlags;
+use crate::ia_32e::paging::allocator::FrameAllocator;
+use crate::ia_32e::paging::frame::Frame;
+use crate::ia_32e::paging::mapper::{MapAllSize, Mapper, MapperFlush};
 use crate::ia_32e::paging::mapper::map_pt::{MappedPageTable, PhysicalToVirtual};
-use crate::ia_32e::VirtAddr;
+use crate::ia_32e::paging::page::{Page, Page1GB, Page2MB, Page4KB};
 use crate::ia_32e::paging::PageTable;
-use crate::ia_32e::paging::frame::Frame;
-use crate::ia_32e::paging::page::{Page4KB, Page1GB, Page, Page2MB};
-use crate::ia_32e::paging::mapper::{Mapper, MapperFlush, MapAllSize};
-use crate::ia_32e::paging::allocator::{UnusedFrame, FrameAllocator};
-use crate::ia_32e::paging::result::{UnmapError, FlagUpdateError, TranslateError, MapToError, TranslationResult};
-use crate::bits::PageTableFlags;
+use crate::ia_32e::paging::result::{FlagUpdateError, MapToError, TranslateError, TranslationResult, UnmapError};
+use crate::ia_32e::VirtAddr;
 
-#[derive(Debug)]
+/// 所有物理内存都被线性映射到`offset`开始的虚拟地址
+#[derive(Debug, Clone, Copy)]
 pub struct PhysOffset {
     offset: VirtAddr,
 }
 
+impl PhysOffset {
+    pub fn new(offset: VirtAddr) -> Self {
+        Self { offset }
+    }
+
+    pub fn offset(&self) -> VirtAddr {
+        self.offset
+    }
+}
+
 impl PhysicalToVirtual for PhysOffset {
     fn phy_to_vir(&self, phy_frame: Frame<Page4KB>) -> *mut PageTable {
         let phy = phy_frame.start_address().as_u64();
@@ -21,18 +32,31 @@ impl PhysicalToVirtual for PhysOffset {
     }
 }
 
+/// 通过物理内存的线性映射访问页表的映射器
 #[derive(Debug)]
 pub struct PageTableOffset<'a> {
     inner: MappedPageTable<'a, PhysOffset>
 }
 
 impl<'a> PageTableOffset<'a> {
+    /// # Safety
+    ///
+    /// 所有物理内存必须已经映射到`virt_offset`开始的虚拟地址
     pub unsafe fn new(level_4_page_table: &'a mut PageTable, virt_offset: VirtAddr) -> Self {
-        let offset = PhysOffset { offset: virt_offset };
+        let offset = PhysOffset::new(virt_offset);
         Self {
             inner: MappedPageTable::new(level_4_page_table, offset)
         }
     }
+
+    pub fn page_flags(&self, page: Page<Page4KB>) -> Option<PageTableFlags> {
+        self.inner.page_flags(page)
+    }
+
+    pub fn split_huge_page<A>(&mut self, addr: VirtAddr, allocator: &mut A) -> Result<bool, MapToError<Page4KB>>
+        where A: FrameAllocator<Page4KB> {
+        self.inner.split_huge_page(addr, allocator)
+    }
 }
 
 impl<'a> Mapper<Page4KB> for PageTableOffset<'a> {
@@ -93,4 +117,4 @@ impl<'a> MapAllSize for PageTableOffset<'a> {
     fn translate(&self, addr: VirtAddr) -> TranslationResult {
         self.inner.translate(addr)
     }
-}
\ No newline at end of file
+}
@@ -6,6 +6,7 @@ pub use page_table::{ENTRY_COUNT, PageTable, PageTableEntry};
 use crate::ia_32e::PhysAddr;
 use lazy_static::lazy_static;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 // use crate::mutex::Mutex;
 ///! 提供了内存分页功能
@@ -17,6 +18,36 @@ pub mod allocator;
 pub mod mapper;
 pub mod result;
 pub mod frame_allocator;
+pub mod stack;
+
+/// 是否使用5级分页（CR4.LA57），分页模式只能在启用分页之前由引导程序选择
+static FIVE_LEVEL: AtomicBool = AtomicBool::new(false);
+
+/// 记录当前的分页模式，需要在使用页表和虚拟地址之前调用，之后不能再改变
+pub fn set_five_level_paging(enable: bool) {
+    FIVE_LEVEL.store(enable, Ordering::SeqCst);
+}
+
+/// 根据CR4.LA57记录引导程序选择的分页模式，必须在构造任何`VirtAddr`之前调用
+pub fn init_paging_mode() {
+    use crate::bits::CR4Flags;
+    use crate::ia_32e::cpu::control::CR4;
+    set_five_level_paging(CR4::read().contains(CR4Flags::L5_PAGING));
+}
+
+pub fn five_level_paging() -> bool {
+    FIVE_LEVEL.load(Ordering::Relaxed)
+}
+
+/// 页表的级数，4或者5
+pub fn paging_levels() -> usize {
+    if five_level_paging() { 5 } else { 4 }
+}
+
+/// 虚拟地址的有效位数，4级分页为48位，5级分页为57位
+pub fn virt_addr_bits() -> usize {
+    12 + 9 * paging_levels()
+}
 
 pub struct PagingArgs {
     pub pml4t_base_addr: u64,
@@ -81,6 +81,10 @@ impl<S: PageSize> Page<S> {
     pub const fn size(&self) -> u64 {
         S::P_SIZE
     }
+    /// 获取5级页表索引，只在5级分页时有效
+    pub fn p5_index(&self) -> PageIndex {
+        self.start_address.page5_index()
+    }
     /// 获取4级页表索引
     pub fn p4_index(&self) -> PageIndex {
         self.start_address.page4_index()
@@ -48,6 +48,16 @@ impl PageTableEntry {
             Ok(Frame::include_address(self.addr()))
         }
     }
+    /// 返回1级页表项映射的页帧，第7位在1级页表项中是PAT位，不表示大页
+    /// # Error
+    /// * `FrameError::FrameNotPresent` 表示当前Entry没有被置`PRESENT`位
+    pub fn leaf_frame(&self) -> Result<Frame, FrameError> {
+        if !self.flags().contains(PageTableFlags::PRESENT) {
+            Err(FrameError::FrameNotPresent)
+        } else {
+            Ok(Frame::include_address(self.addr()))
+        }
+    }
 
     /// 将entry与物理地址做映射
     pub fn set_addr(&mut self, phy: PhysAddr, flags: PageTableFlags) {
@@ -0,0 +1,16 @@
+//! 向下增长的栈的扩展规则
+use crate::ia_32e::VirtAddr;
+use crate::ia_32e::paging::{Page, Page4KB};
+
+/// 栈当前占用`[start, end)`，访问`addr`时计算栈扩展后新的起始地址（按页对齐）。
+/// `addr`不在栈底以下`gap`字节的范围内，或者扩展后栈超过`max_size`字节时返回`None`
+pub fn grow_start(start: VirtAddr, end: VirtAddr, addr: VirtAddr, gap: u64, max_size: u64) -> Option<VirtAddr> {
+    if addr >= start || start - addr > gap {
+        return None;
+    }
+    let new_start = Page::<Page4KB>::include_address(addr).start_address();
+    if end - new_start > max_size {
+        return None;
+    }
+    Some(new_start)
+}
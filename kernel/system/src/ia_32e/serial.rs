@@ -123,6 +123,15 @@ impl SerialPort {
         while !self.line_sts().contains(LineStsFlags::INPUT_FULL) {}
         self.data.read()
     }
+
+    /// Receives a byte on the serial port without waiting.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.line_sts().contains(LineStsFlags::INPUT_FULL) {
+            Some(self.data.read())
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::Write for SerialPort {
@@ -118,6 +118,12 @@ impl IoApic {
         self.regs.clear(lo, IRQ_MASK_BIT);
     }
 
+    /// Redirect interrupt number `irq` to interrupt vector `vector`.
+    pub unsafe fn set_vector(&mut self, irq: u8, vector: u8) {
+        self.regs.clear(lo(irq), 0xff);
+        self.regs.set(lo(irq), u32::from(vector));
+    }
+
     /// Disable interrupt number `irq`.
     pub unsafe fn disable_irq(&mut self, irq: u8) {
         self.regs.set(lo(irq), IRQ_MASK_BIT);
@@ -1,4 +1,6 @@
 pub const LAPIC_ADDR: usize = 0xfee0_0000;
+/// I/O APIC寄存器的默认物理地址
+pub const IOAPIC_ADDR: usize = 0xfec0_0000;
 
 /**
 FEE0 0000H Reserved
@@ -67,6 +69,8 @@ pub(crate) const ID      : u32 = 0x0020;       // ID
 pub(crate) const VER     : u32 = 0x0030;       // Version
 pub(crate) const TPR     : u32 = 0x0080;       // Task Priority
 pub(crate) const EOI     : u32 = 0x00B0;       // EOI
+pub(crate) const LDR     : u32 = 0x00D0;       // Logical Destination
+pub(crate) const DFR     : u32 = 0x00E0;       // Destination Format
 pub(crate) const SVR     : u32 = 0x00F0;       // Spurious Interrupt Vector
 pub(crate) const ENABLE  : u32 =   0x00000100;     // Unit Enable
 pub(crate) const ESR     : u32 = 0x0280;       // Error Status
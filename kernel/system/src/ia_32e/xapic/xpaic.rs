@@ -1,20 +1,26 @@
 use core::ptr::read_volatile;
 use core::ptr::write_volatile;
 use super::consts::SVR;
-use crate::ia_32e::xapic::consts::{ENABLE, T_IRQ0, IRQ_SPURIOUS, TDCR, X1, PERIODIC, TIMER, IRQ_TIMER, LINT0, TICR, LINT1, MASKED, VER, PCINT, ERROR, IRQ_ERROR, ESR, EOI, ICRHI, ICRLO, BCAST, INIT, LEVEL, DELIVS, TPR, STARTUP, CMOS_PORT, CMOS_RETURN, ASSERT, ID};
+use crate::ia_32e::xapic::consts::{ENABLE, T_IRQ0, IRQ_SPURIOUS, TDCR, X1, PERIODIC, TIMER, IRQ_TIMER, LINT0, TICR, LINT1, MASKED, VER, PCINT, ERROR, IRQ_ERROR, ESR, EOI, ICRHI, ICRLO, BCAST, INIT, LEVEL, DELIVS, TPR, STARTUP, CMOS_PORT, CMOS_RETURN, ASSERT, ID, LDR, DFR, TCCR};
+use crate::ia_32e::x2apic::register::{IpiAllShorthand, IpiDeliveryMode, IpiDestMode, TimerDivide, TimerMode};
 use core::fmt;
 use crate::ia_32e::instructions::port::outw;
 use crate::bits::BitOpt;
 
+/// Destination shorthand of an IPI sent to the current processor only.
+const SELF_SHORTHAND: u64 = 0b01;
+
 #[allow(non_camel_case_types)]
 pub struct xApic {
-    base: usize
+    base: usize,
+    ipi_destination_mode: IpiDestMode,
 }
 
 impl xApic {
 
+    /// `base` is the virtual address the local APIC registers are mapped at.
     pub fn new(base: usize) -> Self {
-        Self { base }
+        Self { base, ipi_destination_mode: IpiDestMode::Physical }
     }
 
     pub fn cpu_init(&mut self) {
@@ -81,6 +87,126 @@ impl xApic {
         unsafe { self.write(EOI, 0); }
     }
 
+    /// Sets the APIC software enable bit, keeping the spurious vector.
+    pub fn enable(&mut self) {
+        unsafe { self.write(SVR, self.read(SVR) | ENABLE) }
+    }
+
+    /// Clears the APIC software enable bit, which masks every LVT entry.
+    pub fn disable(&mut self) {
+        unsafe { self.write(SVR, self.read(SVR) & !ENABLE) }
+    }
+
+    pub fn enable_timer(&mut self) {
+        unsafe { self.write(TIMER, self.read(TIMER) & !MASKED) }
+    }
+
+    pub fn disable_timer(&mut self) {
+        unsafe { self.write(TIMER, self.read(TIMER) | MASKED) }
+    }
+
+    pub fn set_timer_mode(&mut self, mode: TimerMode) {
+        unsafe {
+            let mut timer = self.read(TIMER);
+            timer.set_bits(17..19, Into::<u64>::into(mode) as u32);
+            self.write(TIMER, timer);
+        }
+    }
+
+    pub fn set_timer_divide(&mut self, divide: TimerDivide) {
+        unsafe { self.write(TDCR, Into::<u64>::into(divide) as u32) }
+    }
+
+    /// Writing the initial count restarts the timer, writing 0 stops it.
+    pub fn set_timer_initial(&mut self, initial: u32) {
+        unsafe { self.write(TICR, initial) }
+    }
+
+    pub fn timer_current(&self) -> u32 {
+        unsafe { self.read(TCCR) }
+    }
+
+    /// Uses the flat logical destination model with `dest` as the 8 bit logical ID.
+    pub fn set_logical_id(&mut self, dest: u32) {
+        unsafe {
+            self.write(DFR, 0xffff_ffff);
+            self.write(LDR, dest << 24);
+        }
+    }
+
+    pub fn set_ipi_destination_mode(&mut self, mode: IpiDestMode) {
+        self.ipi_destination_mode = mode;
+    }
+
+    /// Sends an IPI to the processor(s) in `dest`.
+    pub fn send_ipi(&mut self, vector: u8, dest: u32) {
+        self.send_to(vector, IpiDeliveryMode::Fixed, dest)
+    }
+
+    /// Sends an IPI to every processor, either including or excluding the current one.
+    pub fn send_ipi_all(&mut self, vector: u8, who: IpiAllShorthand) {
+        self.send_all(vector, IpiDeliveryMode::Fixed, who.into())
+    }
+
+    pub fn send_lowest_priority_ipi(&mut self, vector: u8, dest: u32) {
+        self.send_to(vector, IpiDeliveryMode::LowestPriority, dest)
+    }
+
+    pub fn send_lowest_priority_ipi_all(&mut self, vector: u8, who: IpiAllShorthand) {
+        self.send_all(vector, IpiDeliveryMode::LowestPriority, who.into())
+    }
+
+    pub fn send_smi(&mut self, dest: u32) {
+        self.send_to(0, IpiDeliveryMode::SystemManagement, dest)
+    }
+
+    pub fn send_smi_all(&mut self, who: IpiAllShorthand) {
+        self.send_all(0, IpiDeliveryMode::SystemManagement, who.into())
+    }
+
+    pub fn send_nmi(&mut self, dest: u32) {
+        self.send_to(0, IpiDeliveryMode::NonMaskable, dest)
+    }
+
+    pub fn send_nmi_all(&mut self, who: IpiAllShorthand) {
+        self.send_all(0, IpiDeliveryMode::NonMaskable, who.into())
+    }
+
+    /// Sends a start-up IPI, `vector` is the page number of the entry point.
+    pub fn send_sipi(&mut self, vector: u8, dest: u32) {
+        self.send_to(vector, IpiDeliveryMode::StartUp, dest)
+    }
+
+    /// Sends a start-up IPI to all other processors.
+    pub fn send_sipi_all(&mut self, vector: u8) {
+        self.send_all(vector, IpiDeliveryMode::StartUp, IpiAllShorthand::AllExcludingSelf.into())
+    }
+
+    pub fn send_ipi_self(&mut self, vector: u8) {
+        self.send_all(vector, IpiDeliveryMode::Fixed, SELF_SHORTHAND)
+    }
+
+    fn send_to(&mut self, vector: u8, mode: IpiDeliveryMode, dest: u32) {
+        let mut icr = self.format_icr(vector, mode);
+        // xAPIC only has an 8 bit destination field
+        icr.set_bits(56..64, u64::from(dest & 0xff));
+        self.set_icr(icr);
+    }
+
+    fn send_all(&mut self, vector: u8, mode: IpiDeliveryMode, shorthand: u64) {
+        let mut icr = self.format_icr(vector, mode);
+        icr.set_bits(18..20, shorthand);
+        self.set_icr(icr);
+    }
+
+    fn format_icr(&self, vector: u8, mode: IpiDeliveryMode) -> u64 {
+        let mut icr = u64::from(vector);
+        icr.set_bits(8..11, mode.into());
+        icr.set_bit(11, self.ipi_destination_mode == IpiDestMode::Logical);
+        icr.set_bit(14, true);
+        icr
+    }
+
     /// The entry point `addr` must be 4K aligned.
     /// This function will access memory: 0x467
     pub unsafe fn start_ap(&mut self, apic_id: u8, addr: u32) {
@@ -28,6 +28,7 @@ use xmas_elf::{
     sections::{SectionHeader, ShType},
 };
 use crate::bits::KernelSectionFlags;
+use crate::ia_32e::PhysAddr;
 use alloc::string::String;
 #[cfg(feature = "efi")]
 use alloc::string::ToString;
@@ -43,6 +44,16 @@ pub mod syscall;
 #[macro_use]
 pub mod console;
 pub mod buddy_system_allocator;
+pub mod slab_allocator;
+pub mod debug_allocator;
+
+/// 高半部分内核的起始虚拟地址，物理地址`p`处的内核映像和引导信息映射到`KERNEL_OFFSET + p`
+pub const KERNEL_OFFSET: u64 = 0xFFFF_FFFF_8000_0000;
+
+/// 将内核映像或者引导信息的虚拟地址转换为物理地址，低地址的恒等映射原样返回
+pub fn kernel_phys_addr(addr: u64) -> u64 {
+    if addr >= KERNEL_OFFSET { addr - KERNEL_OFFSET } else { addr }
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -56,6 +67,12 @@ pub struct KernelArgs {
     pub stack_end: u64,
     pub frame_ptr: *mut u8,
     pub frame_size: usize,
+    /// 内核映像相对于链接地址的偏移，将运行时地址减去该值即可对照符号表
+    pub kernel_slide: u64,
+    /// 内核中堆和栈等地址随机化使用的种子，为0时表示关闭地址随机化
+    pub kaslr_seed: u64,
+    /// 固件配置表中RSDP的物理地址，为0时表示没有找到
+    pub acpi_rsdp: u64,
 }
 
 pub struct KernelArea {
@@ -129,7 +146,7 @@ pub enum KernelSectionType {
 impl KernelArea {
     #[cfg(feature = "efi")]
     pub fn from_uefi(section: &SectionHeader) -> Self {
-        let flag = KernelSectionFlags::from_bits(section.flags()).expect("invalid flags bit");
+        let flag = KernelSectionFlags::from_bits_truncate(section.flags());
         let ty = match section.get_type().expect("not section type found!") {
             ShType::Null => KernelSectionType::Unused,
             ShType::ProgBits => KernelSectionType::ProgramSection,
@@ -158,7 +175,7 @@ impl KernelArea {
 
     #[cfg(feature = "mutiboot")]
     pub fn from_mutiboot(section: &ElfSection) -> Self {
-        let flag = KernelSectionFlags::from_bits(section.flags().bits()).expect("invalid flags bit");
+        let flag = KernelSectionFlags::from_bits_truncate(section.flags().bits());
 
         let ty = match section.section_type() {
             ElfSectionType::ProgramSection => KernelSectionType::ProgramSection,
@@ -197,6 +214,9 @@ pub struct SystemInformation {
     kernel_area: Vec<KernelArea>,
     kernel_start: u64,
     kernel_end: u64,
+    /// 引导程序传递的信息所在的物理内存`[boot_start, boot_end)`
+    boot_start: u64,
+    boot_end: u64,
 }
 
 impl SystemInformation {
@@ -206,6 +226,58 @@ impl SystemInformation {
     pub fn kernel_end(&self) -> u64 {
         self.kernel_end
     }
+    pub fn boot_start(&self) -> u64 {
+        self.boot_start
+    }
+    pub fn boot_end(&self) -> u64 {
+        self.boot_end
+    }
+    /// 内核映像的滑动偏移，multiboot引导时内核不会被重定位
+    #[cfg(feature = "efi")]
+    pub fn kernel_slide(&self) -> u64 {
+        self.efi.kernel_slide
+    }
+    #[cfg(not(feature = "efi"))]
+    pub fn kernel_slide(&self) -> u64 {
+        0
+    }
+    /// 地址随机化的种子，为0时表示关闭地址随机化
+    #[cfg(feature = "efi")]
+    pub fn kaslr_seed(&self) -> u64 {
+        self.efi.kaslr_seed
+    }
+    #[cfg(not(feature = "efi"))]
+    pub fn kaslr_seed(&self) -> u64 {
+        0
+    }
+    /// 图形帧缓冲区的物理地址和字节数，multiboot引导时使用VGA文本模式，没有帧缓冲区
+    #[cfg(feature = "efi")]
+    pub fn frame_buffer(&self) -> Option<(u64, usize)> {
+        if self.efi.frame_ptr.is_null() { None } else { Some((self.efi.frame_ptr as u64, self.efi.frame_size)) }
+    }
+    #[cfg(not(feature = "efi"))]
+    pub fn frame_buffer(&self) -> Option<(u64, usize)> {
+        None
+    }
+    /// UEFI配置表中RSDP的物理地址，multiboot引导时需要在BIOS内存中查找
+    #[cfg(feature = "efi")]
+    pub fn acpi_rsdp(&self) -> Option<PhysAddr> {
+        if self.efi.acpi_rsdp == 0 { None } else { Some(PhysAddr::new(self.efi.acpi_rsdp)) }
+    }
+    #[cfg(not(feature = "efi"))]
+    pub fn acpi_rsdp(&self) -> Option<PhysAddr> {
+        None
+    }
+
+    /// 引导程序传递的内核命令行，UEFI引导时没有命令行
+    #[cfg(feature = "mutiboot")]
+    pub fn command_line(&self) -> Option<&str> {
+        self.info.command_line_tag().map(|tag| tag.command_line())
+    }
+    #[cfg(not(feature = "mutiboot"))]
+    pub fn command_line(&self) -> Option<&str> {
+        None
+    }
 
     pub fn mem_area_iter(&self) -> impl Iterator<Item=&MemoryArea> + '_ {
         self.mem_area.iter()
@@ -215,10 +287,15 @@ impl SystemInformation {
     }
 
     pub fn new(k_args: usize) -> Self {
-        let res = if cfg!(mutiboot) {
-            let args = unsafe { multiboot2::load(k_args) };
-            let k_start = args.elf_sections_tag().unwrap().sections().map(|s| s.start_address()).min().unwrap();
-            let k_end = args.elf_sections_tag().unwrap().sections().map(|s| s.start_address()).max().unwrap();
+        let res = if cfg!(feature = "mutiboot") {
+            let info = unsafe { multiboot2::load(k_args) };
+            // 只有需要加载的section占用物理内存，符号表等section的地址为0
+            let allocated = || info.elf_sections_tag().unwrap().sections()
+                .filter(|s| s.flags().contains(multiboot2::ElfSectionFlags::ALLOCATED));
+            let k_start = allocated().map(|s| kernel_phys_addr(s.start_address())).min().unwrap();
+            let k_end = allocated().map(|s| kernel_phys_addr(s.end_address())).max().unwrap();
+            let boot_start = kernel_phys_addr(info.start_address() as u64);
+            let boot_end = kernel_phys_addr(info.end_address() as u64);
             #[allow(unused_mut)]
                 let mut s = Self {
                 #[cfg(feature = "mutiboot")]
@@ -227,6 +304,8 @@ impl SystemInformation {
                 kernel_area: Vec::new(),
                 kernel_start: k_start,
                 kernel_end: k_end,
+                boot_start,
+                boot_end,
             };
             #[cfg(feature = "mutiboot")]
                 s.load_kernel_area();
@@ -245,6 +324,9 @@ impl SystemInformation {
                 kernel_area: Vec::new(),
                 kernel_start: k_start,
                 kernel_end: k_end,
+                // 内核参数位于LOADER_DATA中，不会被当作空闲内存
+                boot_start: k_args as u64,
+                boot_end: (k_args + core::mem::size_of::<KernelArgs>()) as u64,
             };
             #[cfg(feature = "efi")]
                 s.load_kernel_area();
@@ -266,7 +348,13 @@ impl SystemInformation {
     fn load_kernel_area(&mut self) {
         let sections = get_elf_section(&self.efi);
         for area in sections.section_iter() {
-            self.kernel_area.push(KernelArea::from_uefi(&area))
+            let mut area = KernelArea::from_uefi(&area);
+            // 符号表中的地址是链接地址，加上滑动偏移才是运行时的地址
+            if area.flags.contains(KernelSectionFlags::ALLOCATED) {
+                area.start_addr += self.efi.kernel_slide;
+                area.end_addr += self.efi.kernel_slide;
+            }
+            self.kernel_area.push(area)
         }
     }
     #[cfg(feature = "mutiboot")]
@@ -283,7 +371,7 @@ impl SystemInformation {
         }
     }
     #[cfg(feature = "efi")]
-    fn load_memory_areas(&mut self) {
+    fn load_memory_area(&mut self) {
         let mem_iter = get_mem_iter(&self.efi);
         for area in mem_iter {
             let ty = match area.ty {
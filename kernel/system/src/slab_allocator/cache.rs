@@ -0,0 +1,409 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(not(test))]
+use crate::ia_32e::instructions::interrupt::without_interrupts;
+use crate::Mutex;
+
+use super::MAX_CPUS;
+
+/// 宿主机上的测试运行在用户态，不能关中断
+#[cfg(test)]
+fn without_interrupts<F, R>(f: F) -> R where F: FnOnce() -> R {
+    f()
+}
+
+/// 每个弹匣最多缓存的对象数
+const MAGAZINE_SIZE: usize = 16;
+/// 每个slab至少容纳的对象数
+const MIN_OBJECTS: usize = 8;
+/// slab的最小大小
+const MIN_SLAB_SIZE: usize = 4096;
+/// 最多保留的空slab数，超出的部分归还给后备分配器
+const MAX_EMPTY_SLABS: usize = 2;
+
+/// 空闲对象，链表指针保存在对象自身中
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// slab头部，位于slab的起始位置
+struct Slab {
+    free: *mut FreeObject,
+    in_use: usize,
+    prev: *mut Slab,
+    next: *mut Slab,
+}
+
+/// slab双向链表
+struct SlabList {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            len: 0,
+        }
+    }
+
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = null_mut();
+        (*slab).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = slab;
+        }
+        self.head = slab;
+        self.len += 1;
+    }
+
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        if (*slab).prev.is_null() {
+            self.head = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+        self.len -= 1;
+    }
+
+    unsafe fn pop(&mut self) -> Option<*mut Slab> {
+        let slab = self.head;
+        if slab.is_null() {
+            return None;
+        }
+        self.remove(slab);
+        Some(slab)
+    }
+}
+
+/// CPU本地的对象弹匣
+struct Magazine {
+    objects: [*mut u8; MAGAZINE_SIZE],
+    count: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objects: [null_mut(); MAGAZINE_SIZE],
+            count: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.count == MAGAZINE_SIZE
+    }
+
+    fn push(&mut self, object: *mut u8) {
+        self.objects[self.count] = object;
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        if self.is_empty() {
+            return None;
+        }
+        self.count -= 1;
+        Some(self.objects[self.count])
+    }
+}
+
+unsafe impl Send for Magazine {}
+unsafe impl Sync for Magazine {}
+
+struct CacheInner {
+    partial: SlabList,
+    full: SlabList,
+    empty: SlabList,
+    slabs: usize,
+    in_use: usize,
+}
+
+unsafe impl Send for CacheInner {}
+unsafe impl Sync for CacheInner {}
+
+/// 缓存的统计信息
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CacheStats {
+    /// slab数量
+    pub slabs: usize,
+    /// 所有slab能容纳的对象数
+    pub capacity: usize,
+    /// 已经离开slab的对象数（包括弹匣中缓存的对象）
+    pub objects: usize,
+    pub allocations: usize,
+    pub frees: usize,
+    /// 直接从弹匣中完成的分配次数
+    pub magazine_hits: usize,
+}
+
+/// 固定大小对象的缓存
+pub struct ObjectCache {
+    name: &'static str,
+    size: usize,
+    slab_size: usize,
+    /// 第一个对象相对于slab起始地址的偏移
+    offset: usize,
+    constructor: Option<fn(*mut u8)>,
+    backing: &'static (dyn GlobalAlloc + Sync),
+    cpu_id: fn() -> usize,
+    inner: Mutex<CacheInner>,
+    magazines: [Mutex<Magazine>; MAX_CPUS],
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    magazine_hits: AtomicUsize,
+}
+
+impl ObjectCache {
+    pub fn new(name: &'static str, size: usize, backing: &'static (dyn GlobalAlloc + Sync), cpu_id: fn() -> usize) -> Self {
+        Self::with_constructor(name, size, None, backing, cpu_id)
+    }
+
+    /// 对象大小向上取整到2的幂，并按该大小对齐。
+    /// 对象离开slab时调用`constructor`，经由弹匣回收的对象保持释放时的状态
+    pub fn with_constructor(name: &'static str, size: usize, constructor: Option<fn(*mut u8)>,
+                            backing: &'static (dyn GlobalAlloc + Sync), cpu_id: fn() -> usize) -> Self {
+        let size = max(size, size_of::<FreeObject>()).next_power_of_two();
+        let slab_size = max(MIN_SLAB_SIZE, (size * MIN_OBJECTS).next_power_of_two());
+        // 头部占用的空间按对象大小取整，保证每个对象都按自身大小对齐
+        let offset = (size_of::<Slab>() + size - 1) & !(size - 1);
+        Self {
+            name,
+            size,
+            slab_size,
+            offset,
+            constructor,
+            backing,
+            cpu_id,
+            inner: Mutex::new(CacheInner {
+                partial: SlabList::new(),
+                full: SlabList::new(),
+                empty: SlabList::new(),
+                slabs: 0,
+                in_use: 0,
+            }),
+            magazines: [
+                Mutex::new(Magazine::new()), Mutex::new(Magazine::new()),
+                Mutex::new(Magazine::new()), Mutex::new(Magazine::new()),
+                Mutex::new(Magazine::new()), Mutex::new(Magazine::new()),
+                Mutex::new(Magazine::new()), Mutex::new(Magazine::new()),
+            ],
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            magazine_hits: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 每个slab容纳的对象数
+    fn capacity(&self) -> usize {
+        (self.slab_size - self.offset) / self.size
+    }
+
+    fn slab_layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.slab_size, self.slab_size) }
+    }
+
+    fn magazine(&self) -> &Mutex<Magazine> {
+        &self.magazines[(self.cpu_id)() % MAX_CPUS]
+    }
+
+    /// 关中断后持有缓存锁执行`f`，中断处理程序中的分配不会在同一CPU持有的锁上死锁
+    fn with_inner<F, R>(&self, f: F) -> R where F: FnOnce(&mut CacheInner) -> R {
+        without_interrupts(|| f(&mut self.inner.lock()))
+    }
+
+    /// 分配一个对象，内存不足时返回空指针
+    pub unsafe fn alloc(&self) -> *mut u8 {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        // 同一CPU上的中断处理程序可能已经持有弹匣，此时直接使用slab
+        if let Some(mut magazine) = self.magazine().try_lock() {
+            if magazine.is_empty() {
+                // 一次填充半个弹匣，减少获取缓存锁的次数
+                let magazine = &mut *magazine;
+                self.with_inner(|inner| {
+                    while magazine.count < MAGAZINE_SIZE / 2 {
+                        match self.take(inner) {
+                            Some(object) => magazine.push(object),
+                            None => break,
+                        }
+                    }
+                });
+                if magazine.is_empty() {
+                    if let Some(object) = self.take_or_grow() {
+                        magazine.push(object);
+                    }
+                }
+            } else {
+                self.magazine_hits.fetch_add(1, Ordering::Relaxed);
+            }
+            return magazine.pop().unwrap_or(null_mut());
+        }
+        self.take_or_grow().unwrap_or(null_mut())
+    }
+
+    /// 释放由该缓存分配的对象
+    pub unsafe fn dealloc(&self, object: *mut u8) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        if let Some(mut magazine) = self.magazine().try_lock() {
+            if magazine.is_full() {
+                // 弹匣已满时将一半对象归还给slab
+                let magazine = &mut *magazine;
+                self.with_inner(|inner| {
+                    while magazine.count > MAGAZINE_SIZE / 2 {
+                        let cached = magazine.pop().expect("magazine is empty");
+                        self.put(inner, cached);
+                    }
+                });
+                self.trim(MAX_EMPTY_SLABS);
+            }
+            magazine.push(object);
+            return;
+        }
+        self.with_inner(|inner| self.put(inner, object));
+        self.trim(MAX_EMPTY_SLABS);
+    }
+
+    /// 将所有弹匣中的对象归还给slab，并释放所有空slab
+    pub fn shrink(&self) {
+        for magazine in self.magazines.iter() {
+            if let Some(mut magazine) = magazine.try_lock() {
+                let magazine = &mut *magazine;
+                self.with_inner(|inner| {
+                    while let Some(object) = magazine.pop() {
+                        unsafe { self.put(inner, object) };
+                    }
+                });
+            }
+        }
+        unsafe { self.trim(0) };
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (slabs, in_use) = self.with_inner(|inner| (inner.slabs, inner.in_use));
+        CacheStats {
+            slabs,
+            capacity: slabs * self.capacity(),
+            objects: in_use,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            magazine_hits: self.magazine_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 从slab中取出一个对象，没有空闲对象时在锁外分配新的slab
+    unsafe fn take_or_grow(&self) -> Option<*mut u8> {
+        loop {
+            if let Some(object) = self.with_inner(|inner| self.take(inner)) {
+                return Some(object);
+            }
+            let slab = self.new_slab()?;
+            self.with_inner(|inner| {
+                inner.slabs += 1;
+                inner.empty.push(slab);
+            });
+        }
+    }
+
+    /// 从已有的slab中取出一个对象
+    unsafe fn take(&self, inner: &mut CacheInner) -> Option<*mut u8> {
+        let slab = if !inner.partial.head.is_null() {
+            inner.partial.head
+        } else {
+            let slab = inner.empty.pop()?;
+            inner.partial.push(slab);
+            slab
+        };
+        let object = (*slab).free;
+        (*slab).free = (*object).next;
+        (*slab).in_use += 1;
+        inner.in_use += 1;
+        if (*slab).free.is_null() {
+            inner.partial.remove(slab);
+            inner.full.push(slab);
+        }
+        let object = object as *mut u8;
+        if let Some(constructor) = self.constructor {
+            constructor(object);
+        }
+        Some(object)
+    }
+
+    /// 将对象放回所属的slab，slab按自身大小对齐，所以可以通过地址找到头部。
+    /// 变空的slab放入空链表，由`trim`在锁外归还
+    unsafe fn put(&self, inner: &mut CacheInner, object: *mut u8) {
+        let slab = (object as usize & !(self.slab_size - 1)) as *mut Slab;
+        let was_full = (*slab).free.is_null();
+        let object = object as *mut FreeObject;
+        (*object).next = (*slab).free;
+        (*slab).free = object;
+        (*slab).in_use -= 1;
+        inner.in_use -= 1;
+        if was_full {
+            inner.full.remove(slab);
+            inner.partial.push(slab);
+        }
+        if (*slab).in_use == 0 {
+            inner.partial.remove(slab);
+            inner.empty.push(slab);
+        }
+    }
+
+    /// 空slab超过`keep`个时将多余的部分归还给后备分配器，不持有缓存锁
+    unsafe fn trim(&self, keep: usize) {
+        loop {
+            let slab = self.with_inner(|inner| {
+                if inner.empty.len <= keep {
+                    return None;
+                }
+                inner.slabs -= 1;
+                inner.empty.pop()
+            });
+            match slab {
+                Some(slab) => self.backing.dealloc(slab as *mut u8, self.slab_layout()),
+                None => break,
+            }
+        }
+    }
+
+    /// 从后备分配器中分配新的slab并建立空闲链表，调用时不持有缓存锁
+    unsafe fn new_slab(&self) -> Option<*mut Slab> {
+        let base = self.backing.alloc(self.slab_layout());
+        if base.is_null() {
+            return None;
+        }
+        let mut free = null_mut();
+        for i in (0..self.capacity()).rev() {
+            let object = base.add(self.offset + i * self.size) as *mut FreeObject;
+            (*object).next = free;
+            free = object;
+        }
+        let slab = base as *mut Slab;
+        ptr::write(slab, Slab {
+            free,
+            in_use: 0,
+            prev: null_mut(),
+            next: null_mut(),
+        });
+        Some(slab)
+    }
+}
@@ -0,0 +1,77 @@
+//! slab分配器，位于伙伴系统之前，为小对象提供固定大小的对象缓存
+//!
+//! 每个缓存由若干slab组成，slab从后备分配器中按自身大小对齐分配，
+//! 释放对象时通过地址找到所属的slab。每个CPU拥有独立的弹匣(magazine)，
+//! 大部分分配和释放只需要访问当前CPU的弹匣。
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp::max;
+
+pub use cache::{CacheStats, ObjectCache};
+
+mod cache;
+
+/// 支持的最大CPU数量
+pub const MAX_CPUS: usize = 8;
+/// 通用缓存的对象大小，更大的分配请求直接交给后备分配器
+pub const SIZE_CLASSES: [usize; 10] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// 按大小将分配请求转发给对应的通用缓存
+pub struct SlabAllocator {
+    caches: [ObjectCache; 10],
+    backing: &'static (dyn GlobalAlloc + Sync),
+}
+
+impl SlabAllocator {
+    /// `backing`提供slab使用的内存以及大对象，`cpu_id`返回当前CPU的编号
+    pub fn new(backing: &'static (dyn GlobalAlloc + Sync), cpu_id: fn() -> usize) -> Self {
+        Self {
+            caches: [
+                ObjectCache::new("size-8", SIZE_CLASSES[0], backing, cpu_id),
+                ObjectCache::new("size-16", SIZE_CLASSES[1], backing, cpu_id),
+                ObjectCache::new("size-32", SIZE_CLASSES[2], backing, cpu_id),
+                ObjectCache::new("size-64", SIZE_CLASSES[3], backing, cpu_id),
+                ObjectCache::new("size-128", SIZE_CLASSES[4], backing, cpu_id),
+                ObjectCache::new("size-256", SIZE_CLASSES[5], backing, cpu_id),
+                ObjectCache::new("size-512", SIZE_CLASSES[6], backing, cpu_id),
+                ObjectCache::new("size-1024", SIZE_CLASSES[7], backing, cpu_id),
+                ObjectCache::new("size-2048", SIZE_CLASSES[8], backing, cpu_id),
+                ObjectCache::new("size-4096", SIZE_CLASSES[9], backing, cpu_id),
+            ],
+            backing,
+        }
+    }
+
+    /// 对象按自身大小对齐，所以只需要找到不小于`max(size, align)`的缓存
+    fn cache(&self, layout: &Layout) -> Option<&ObjectCache> {
+        let size = max(layout.size(), layout.align());
+        self.caches.iter().find(|cache| cache.size() >= size)
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item=&ObjectCache> + '_ {
+        self.caches.iter()
+    }
+
+    /// 将所有空闲的slab归还给后备分配器
+    pub fn shrink(&self) {
+        for cache in self.caches.iter() {
+            cache.shrink();
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.cache(&layout) {
+            Some(cache) => cache.alloc(),
+            None => self.backing.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.cache(&layout) {
+            Some(cache) => cache.dealloc(ptr),
+            None => self.backing.dealloc(ptr, layout),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! 系统调用中内核与用户程序共享的数据结构
+
+/// 文件状态，由`fstat`返回
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct Stat {
+    /// 文件所在设备(scheme)的ID
+    pub st_dev: u64,
+    /// 文件编号
+    pub st_ino: u64,
+    /// 文件类型以及权限，见`MODE_*`
+    pub st_mode: u16,
+    /// 硬链接数量
+    pub st_nlink: u32,
+    /// 文件大小
+    pub st_size: u64,
+    /// 读写时建议使用的块大小
+    pub st_blksize: u32,
+    /// 占用的块数量
+    pub st_blocks: u64,
+}
+
+/// 时钟的时间，由`clock_gettime`返回
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct TimeSpec {
+    /// 秒
+    pub tv_sec: i64,
+    /// 不足一秒的纳秒数，取值范围为`[0, 1_000_000_000)`
+    pub tv_nsec: i64,
+}
+
+/// 内核与用户态scheme提供者之间传递的消息
+///
+/// 内核将请求写入`a`(系统调用号)，`b`(句柄)，`c`，`d`，
+/// 提供者处理完成后将结果(`Error::mux`之后的值)写入`a`并原样返回
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct Packet {
+    /// 请求编号，回复时必须保持不变
+    pub id: u64,
+    /// 发起请求的进程
+    pub pid: usize,
+    pub a: usize,
+    pub b: usize,
+    pub c: usize,
+    pub d: usize,
+}
@@ -0,0 +1,67 @@
+//! 系统调用使用的标志位
+
+/// 页面不可访问
+pub const PROT_NONE: usize = 0x0;
+/// 页面可读
+pub const PROT_READ: usize = 0x1;
+/// 页面可写
+pub const PROT_WRITE: usize = 0x2;
+/// 页面可执行
+pub const PROT_EXEC: usize = 0x4;
+
+/// 共享映射
+pub const MAP_SHARED: usize = 0x01;
+/// 私有映射
+pub const MAP_PRIVATE: usize = 0x02;
+/// 必须映射到给定的地址
+pub const MAP_FIXED: usize = 0x10;
+/// 匿名映射，不关联任何文件
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// 只读打开
+pub const O_RDONLY: usize = 0x0000;
+/// 只写打开
+pub const O_WRONLY: usize = 0x0001;
+/// 读写打开
+pub const O_RDWR: usize = 0x0002;
+/// 访问模式掩码，用于取出`O_RDONLY`、`O_WRONLY`或`O_RDWR`
+pub const O_ACCMODE: usize = 0x0003;
+/// 不存在时创建
+pub const O_CREAT: usize = 0x0040;
+/// 与`O_CREAT`一起使用，已经存在时返回`EEXIST`
+pub const O_EXCL: usize = 0x0080;
+/// 执行exec时关闭文件描述符
+pub const O_CLOEXEC: usize = 0x8_0000;
+
+/// `fcntl`: 获取文件描述符标志
+pub const F_GETFD: usize = 1;
+/// `fcntl`: 设置文件描述符标志
+pub const F_SETFD: usize = 2;
+/// `fcntl`: 获取文件状态标志
+pub const F_GETFL: usize = 3;
+/// 文件描述符标志: 执行exec时关闭
+pub const FD_CLOEXEC: usize = 1;
+
+/// `lseek`: 从文件开头计算偏移
+pub const SEEK_SET: usize = 0;
+/// `lseek`: 从当前位置计算偏移
+pub const SEEK_CUR: usize = 1;
+/// `lseek`: 从文件结尾计算偏移
+pub const SEEK_END: usize = 2;
+
+/// 文件类型掩码
+pub const MODE_TYPE: u16 = 0o170_000;
+/// 目录
+pub const MODE_DIR: u16 = 0o040_000;
+/// 普通文件
+pub const MODE_FILE: u16 = 0o100_000;
+/// 字符设备
+pub const MODE_CHR: u16 = 0o020_000;
+
+/// 非法内存访问
+pub const SIGSEGV: usize = 11;
+
+/// `clock_gettime`: 单调时钟，不受系统时间调整的影响
+pub const CLOCK_MONOTONIC: usize = 1;
+/// `clock_gettime`: 系统启动后经过的时间
+pub const CLOCK_BOOTTIME: usize = 7;
@@ -1,2 +1,5 @@
 pub mod call;
 pub mod result;
+pub mod number;
+pub mod flag;
+pub mod data;
@@ -0,0 +1,59 @@
+//! 系统调用号
+//!
+//! 常用的调用号与Linux保持一致，内核特有的调用从`0x1000_0000`开始
+
+/// 测试用系统调用
+pub const SYS_TEST: usize = 0x2000_0000;
+
+/// 开启或关闭指定进程的系统调用跟踪
+/// `a`: 进程ID，0表示当前进程，`b`: 0关闭，其他值开启
+pub const SYS_TRACE: usize = 0x1000_0000;
+
+/// 调整进程堆的结束地址，`a`: 新的结束地址，0表示查询当前结束地址
+pub const SYS_BRK: usize = 12;
+/// 映射内存，`a`: 地址，`b`: 长度，`c`: `PROT_*`，`d`: `MAP_*`
+pub const SYS_MMAP: usize = 9;
+/// 修改映射的访问权限，`a`: 地址，`b`: 长度，`c`: `PROT_*`
+pub const SYS_MPROTECT: usize = 10;
+/// 解除映射，`a`: 地址，`b`: 长度
+pub const SYS_MUNMAP: usize = 11;
+
+/// 从文件描述符读取数据，`a`: 文件描述符，`b`: 缓冲区，`c`: 长度
+pub const SYS_READ: usize = 0;
+/// 向文件描述符写入数据，`a`: 文件描述符，`b`: 缓冲区，`c`: 长度
+pub const SYS_WRITE: usize = 1;
+/// 关闭文件描述符，`a`: 文件描述符
+pub const SYS_CLOSE: usize = 3;
+/// 复制文件描述符到最小的空闲位置，`a`: 文件描述符
+pub const SYS_DUP: usize = 32;
+/// 复制文件描述符到指定位置，`a`: 原文件描述符，`b`: 新文件描述符
+pub const SYS_DUP2: usize = 33;
+/// 操作文件描述符，`a`: 文件描述符，`b`: `F_*`命令，`c`: 参数
+pub const SYS_FCNTL: usize = 72;
+
+/// 打开`scheme:path`形式的资源，`a`: 路径，`b`: 路径长度，`c`: `O_*`
+pub const SYS_OPEN: usize = 2;
+/// 获取文件状态，`a`: 文件描述符，`b`: `Stat`结构
+pub const SYS_FSTAT: usize = 5;
+/// 移动读写位置，`a`: 文件描述符，`b`: 偏移，`c`: `SEEK_*`
+pub const SYS_LSEEK: usize = 8;
+
+/// 获取时钟的时间，`a`: `CLOCK_*`，`b`: `TimeSpec`结构
+pub const SYS_CLOCK_GETTIME: usize = 228;
+
+/// 将当前进程注册为scheme提供者，`a`: 名称，`b`: 名称长度
+/// 返回的文件描述符用于读取请求(`Packet`)以及写入回复
+pub const SYS_SCHEME_REGISTER: usize = 0x1000_0001;
+/// 允许当前进程在Ring3访问I/O端口，`a`: 起始端口，`b`: 端口数量
+pub const SYS_IOPORT: usize = 0x1000_0002;
+
+/// 打开命名的共享内存对象，`a`: 名称，`b`: 名称长度，`c`: 创建时的大小，`d`: `O_CREAT`/`O_EXCL`
+/// 返回共享内存句柄，新创建的对象内容为0
+pub const SYS_SHM_OPEN: usize = 0x1000_0003;
+/// 调整共享内存对象的大小，`a`: 句柄，`b`: 新的大小，已有的映射不受影响
+pub const SYS_SHM_RESIZE: usize = 0x1000_0004;
+/// 将共享内存对象映射到当前进程，`a`: 句柄，`b`: 地址，0表示由内核选择，`c`: 长度，`d`: `PROT_*`，`e`: `MAP_FIXED`
+/// 返回映射的地址，使用`SYS_MUNMAP`解除映射
+pub const SYS_SHM_MAP: usize = 0x1000_0005;
+/// 关闭共享内存句柄，`a`: 句柄
+pub const SYS_SHM_CLOSE: usize = 0x1000_0006;
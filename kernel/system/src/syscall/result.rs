@@ -7,6 +7,43 @@ pub struct Error {
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+/// Operation not permitted
+pub const EPERM: i32 = 1;
+/// No such file or directory
+pub const ENOENT: i32 = 2;
+/// No such process
+pub const ESRCH: i32 = 3;
+/// I/O error
+pub const EIO: i32 = 5;
+/// Bad file number
+pub const EBADF: i32 = 9;
+/// Try again
+pub const EAGAIN: i32 = 11;
+/// Out of memory
+pub const ENOMEM: i32 = 12;
+/// Permission denied
+pub const EACCES: i32 = 13;
+/// Bad address
+pub const EFAULT: i32 = 14;
+/// Device or resource busy
+pub const EBUSY: i32 = 16;
+/// File exists
+pub const EEXIST: i32 = 17;
+/// No such device
+pub const ENODEV: i32 = 19;
+/// Invalid argument
+pub const EINVAL: i32 = 22;
+/// File table overflow
+pub const ENFILE: i32 = 23;
+/// Too many open files
+pub const EMFILE: i32 = 24;
+/// Illegal seek
+pub const ESPIPE: i32 = 29;
+/// Broken pipe
+pub const EPIPE: i32 = 32;
+/// Function not implemented
+pub const ENOSYS: i32 = 38;
+
 
 impl Error {
     pub fn new(errno: i32) -> Error {
@@ -1,4 +1,7 @@
-use crate::bits::BitOpt;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+use crate::bits::{BitOpt, PageTableFlags};
+use crate::debug_allocator::{CALLER_DEPTH, DebugAllocator};
 use crate::ia_32e::{
     descriptor::{
         GlobalDescriptorTable,
@@ -14,6 +17,12 @@ use crate::ia_32e::{
 };
 use crate::ia_32e::cpu::ChainedPics;
 use crate::ia_32e::cpu::PortReadWrite;
+use crate::ia_32e::paging::{Frame, FrameAllocator, MemoryType, Page, Page1GB, Page2MB, Page4KB, PageTable, UnusedFrame};
+use crate::ia_32e::paging::frame_allocator::{BuddyFrameAllocator, MemoryAreaManagement};
+use crate::ia_32e::paging::mapper::{MapAllSize, MappedPageTable, Mapper};
+use crate::ia_32e::paging::result::{FlagUpdateError, MapToError, TranslateError, TranslationResult, UnmapError};
+use crate::ia_32e::paging::stack::grow_start;
+use crate::slab_allocator::ObjectCache;
 
 #[test]
 fn length_test() {
@@ -91,4 +100,433 @@ fn test_set_bit_u64() {
     for i in 0..64 {
         func(i);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_buddy_frame_allocator_reserve() {
+    let mut allocator = BuddyFrameAllocator::new();
+    allocator.reserve(0x2000, 0x3000);
+    allocator.add_area(0x0, 0x8000, MemoryType::FreeArea, 0x8000);
+    allocator.add_area(0x8000, 0x10000, MemoryType::ReservedArea, 0x8000);
+    assert_eq!(allocator.free_frames(), 7);
+
+    let block = allocator.alloc_size(Layout::from_size_align(0x4000, 0x1000).unwrap()).unwrap();
+    assert_eq!(block.start_address().as_u64(), 0x4000);
+    assert_eq!(allocator.used_frames(), 3);
+    while let Some(frame) = allocator.alloc() {
+        assert_ne!(frame.start_address().as_u64(), 0x2000);
+    }
+    assert_eq!(allocator.free_frames(), 0);
+    allocator.dealloc_size(block.frame(), 4);
+    assert_eq!(allocator.free_frames(), 4);
+}
+
+/// 分配器测试共用的后备分配器
+static BACKING: System = System;
+
+/// 不记录调用者的回溯函数
+fn no_backtrace(_callers: &mut [usize; CALLER_DEPTH]) {}
+
+#[test]
+fn test_slab_object_cache() {
+    fn cpu() -> usize { 0 }
+    fn construct(object: *mut u8) {
+        unsafe { *object = 0xAA };
+    }
+
+    let cache = ObjectCache::with_constructor("test-48", 48, Some(construct), &BACKING, cpu);
+    assert_eq!(cache.size(), 64);
+    let objects: Vec<*mut u8> = (0..100).map(|_| unsafe { cache.alloc() }).collect();
+    for &object in objects.iter() {
+        assert!(!object.is_null());
+        assert_eq!(object as usize % 64, 0);
+        assert_eq!(unsafe { *object }, 0xAA);
+    }
+    let stats = cache.stats();
+    assert_eq!(stats.allocations, 100);
+    assert!(stats.objects >= 100 && stats.objects <= stats.capacity);
+
+    for &object in objects.iter() {
+        unsafe { cache.dealloc(object) };
+    }
+    cache.shrink();
+    let stats = cache.stats();
+    assert_eq!(stats.frees, 100);
+    assert_eq!(stats.objects, 0);
+    assert_eq!(stats.slabs, 0);
+}
+
+#[test]
+fn test_debug_allocator_leaks() {
+    fn backtrace(callers: &mut [usize; CALLER_DEPTH]) {
+        callers[0] = 0x1234;
+    }
+
+    let heap = DebugAllocator::new(&BACKING, backtrace);
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let big = Layout::from_size_align(100, 64).unwrap();
+    unsafe {
+        let before = heap.alloc(layout);
+        let checkpoint = heap.checkpoint();
+        let leaked = heap.alloc(big);
+        let freed = heap.alloc(layout);
+        assert_eq!(leaked as usize % 64, 0);
+        *leaked.add(99) = 1;
+        heap.dealloc(freed, layout);
+
+        let mut leaks = Vec::new();
+        heap.for_each_leak(checkpoint, |allocation| leaks.push(*allocation));
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].ptr, leaked as usize);
+        assert_eq!(leaks[0].size, 100);
+        assert_eq!(leaks[0].callers[0], 0x1234);
+
+        let stats = heap.stats();
+        assert_eq!(stats.live, 2);
+        assert_eq!(stats.live_bytes, 140);
+        assert_eq!(stats.quarantined, 1);
+
+        heap.dealloc(leaked, big);
+        heap.dealloc(before, layout);
+        heap.flush_quarantine();
+        let stats = heap.stats();
+        assert_eq!(stats.live, 0);
+        assert_eq!(stats.quarantined, 0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "overflow")]
+fn test_debug_allocator_overflow() {
+    let heap = DebugAllocator::new(&BACKING, no_backtrace);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = heap.alloc(layout);
+        *ptr.add(24) = 0;
+        heap.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "use after free")]
+fn test_debug_allocator_use_after_free() {
+    let heap = DebugAllocator::new(&BACKING, no_backtrace);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe {
+        let ptr = heap.alloc(layout);
+        heap.dealloc(ptr, layout);
+        *ptr = 0;
+        heap.flush_quarantine();
+    }
+}
+
+/// 用堆上的缓冲区模拟物理内存，物理地址就是帧在缓冲区中的偏移，第0帧是最高级页表
+struct PhysArena {
+    frames: Vec<PageTable>,
+}
+
+impl PhysArena {
+    fn new(count: usize) -> Self {
+        Self { frames: (0..count).map(|_| PageTable::new()).collect() }
+    }
+
+    /// 以第0帧为最高级页表的映射器，访问缓冲区之外的页表会panic
+    fn mapper(&mut self) -> MappedPageTable<impl Fn(Frame) -> *mut PageTable> {
+        let base = self.frames.as_mut_ptr();
+        let count = self.frames.len();
+        let phy_to_vir = move |frame: Frame| -> *mut PageTable {
+            let index = (frame.start_address().as_u64() / 4096) as usize;
+            assert!(index < count, "page table frame {:?} outside of the arena", frame);
+            unsafe { base.add(index) }
+        };
+        unsafe { MappedPageTable::new(&mut *base, phy_to_vir) }
+    }
+
+    /// 依次分配第1帧之后的帧
+    fn allocator(&self) -> ArenaAllocator {
+        ArenaAllocator { next: 1, end: self.frames.len(), freed: Vec::new() }
+    }
+
+    fn table(&self, frame: usize) -> &PageTable {
+        &self.frames[frame]
+    }
+}
+
+struct ArenaAllocator {
+    next: usize,
+    end: usize,
+    freed: Vec<usize>,
+}
+
+impl FrameAllocator<Page4KB> for ArenaAllocator {
+    fn alloc(&mut self) -> Option<UnusedFrame> {
+        let index = match self.freed.pop() {
+            Some(index) => index,
+            None if self.next < self.end => {
+                self.next += 1;
+                self.next - 1
+            }
+            None => return None,
+        };
+        let frame = Frame::from_start_addr(PhysAddr::new(index as u64 * 4096)).unwrap();
+        Some(unsafe { UnusedFrame::new(frame) })
+    }
+
+    fn dealloc(&mut self, frame: UnusedFrame) {
+        self.freed.push((frame.start_address().as_u64() / 4096) as usize);
+    }
+
+    fn free_frames(&self) -> usize {
+        self.end - self.next + self.freed.len()
+    }
+
+    fn used_frames(&self) -> usize {
+        self.next - 1 - self.freed.len()
+    }
+
+    fn alloc_size(&mut self, _size: Layout) -> Option<UnusedFrame> {
+        None
+    }
+
+    fn dealloc_size(&mut self, _frame: Frame, _count: usize) {}
+}
+
+#[test]
+fn test_mapper_map_unmap_4kb() {
+    let mut arena = PhysArena::new(8);
+    let allocator = &mut arena.allocator();
+    let mut mapper = arena.mapper();
+    let page: Page<Page4KB> = Page::include_address(VirtAddr::new(0x4020_1000));
+    let frame = Frame::from_start_addr(PhysAddr::new(0x1234_5000)).unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    unsafe { mapper.map_to(page, frame, flags, allocator) }.unwrap().ignore();
+    // 3、2、1级页表各占一帧
+    assert_eq!(allocator.used_frames(), 3);
+    assert_eq!(mapper.translate_page(page).unwrap(), frame);
+    assert_eq!(mapper.translate_addr(VirtAddr::new(0x4020_1abc)), Some(PhysAddr::new(0x1234_5abc)));
+    assert!(matches!(mapper.translate(VirtAddr::new(0x4020_0000)), TranslationResult::PageNotMapped));
+    assert_eq!(mapper.page_flags(page), Some(flags));
+
+    let user = flags | PageTableFlags::USER_ACCESSIBLE;
+    unsafe { mapper.update_flags(page, user) }.unwrap().ignore();
+    assert_eq!(mapper.page_flags(page), Some(user));
+
+    let (unmapped, flush) = mapper.unmap(page).unwrap();
+    flush.ignore();
+    assert_eq!(unmapped, frame);
+    assert!(matches!(mapper.translate_page(page), Err(TranslateError::PageNotMapped)));
+    assert!(matches!(mapper.unmap(page), Err(UnmapError::PageNotMapped)));
+    drop(mapper);
+
+    // 中间级页表项只有`PRESENT`和`WRITABLE`，`update_flags`只修改最后一级
+    let p4_entry = &arena.table(0)[page.p4_index()];
+    assert_eq!(p4_entry.addr(), PhysAddr::new(0x1000));
+    assert_eq!(p4_entry.flags(), flags);
+}
+
+#[test]
+fn test_mapper_huge_pages() {
+    let mut arena = PhysArena::new(8);
+    let allocator = &mut arena.allocator();
+    let mut mapper = arena.mapper();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let page_2mb: Page<Page2MB> = Page::include_address(VirtAddr::new(0x20_0000));
+    let frame_2mb = Frame::from_start_addr(PhysAddr::new(0x4060_0000)).unwrap();
+    unsafe { mapper.map_to(page_2mb, frame_2mb, flags, allocator) }.unwrap().ignore();
+    assert_eq!(mapper.translate_page(page_2mb).unwrap(), frame_2mb);
+    match mapper.translate(VirtAddr::new(0x21_2345)) {
+        TranslationResult::Frame2MB { frame, offset } => {
+            assert_eq!(frame, frame_2mb);
+            assert_eq!(offset, 0x1_2345);
+        }
+        other => panic!("unexpected translation {:?}", other),
+    }
+    assert_eq!(mapper.page_flags(Page::include_address(VirtAddr::new(0x21_0000))), Some(flags | PageTableFlags::HUGE_PAGE));
+
+    // 大页覆盖的范围内不能再建立4KB映射
+    let inner: Page<Page4KB> = Page::include_address(VirtAddr::new(0x21_0000));
+    let frame_4kb = Frame::from_start_addr(PhysAddr::new(0x5000)).unwrap();
+    assert!(matches!(unsafe { mapper.map_to(inner, frame_4kb, flags, allocator) }, Err(MapToError::ParentEntryHugePage)));
+    assert!(matches!(mapper.translate_page(inner), Err(TranslateError::ParentEntryHugePage)));
+    assert!(matches!(mapper.unmap(inner), Err(UnmapError::ParentEntryHugePage)));
+    assert!(matches!(unsafe { mapper.update_flags(inner, flags) }, Err(FlagUpdateError::ParentEntryHugePage)));
+
+    let page_1gb: Page<Page1GB> = Page::include_address(VirtAddr::new(0x80_0000_0000));
+    let frame_1gb = Frame::from_start_addr(PhysAddr::new(0x1_0000_0000)).unwrap();
+    unsafe { mapper.map_to(page_1gb, frame_1gb, flags, allocator) }.unwrap().ignore();
+    assert_eq!(mapper.translate_addr(VirtAddr::new(0x80_1234_5678)), Some(PhysAddr::new(0x1_1234_5678)));
+    let inner: Page<Page2MB> = Page::include_address(VirtAddr::new(0x80_4000_0000 - 0x20_0000));
+    assert!(matches!(unsafe { mapper.map_to(inner, frame_2mb, flags, allocator) }, Err(MapToError::ParentEntryHugePage)));
+
+    let (frame, flush) = mapper.unmap(page_2mb).unwrap();
+    flush.ignore();
+    assert_eq!(frame, frame_2mb);
+    assert!(matches!(mapper.translate_page(page_2mb), Err(TranslateError::PageNotMapped)));
+}
+
+#[test]
+fn test_mapper_4kb_pat() {
+    let mut arena = PhysArena::new(8);
+    let allocator = &mut arena.allocator();
+    let mut mapper = arena.mapper();
+    let page: Page<Page4KB> = Page::include_address(VirtAddr::new(0x4020_1000));
+    let frame = Frame::from_start_addr(PhysAddr::new(0x1234_5000)).unwrap();
+    // 4KB页表项的第7位是PAT，与`HUGE_PAGE`是同一位
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::HUGE_PAGE;
+
+    unsafe { mapper.map_to(page, frame, flags, allocator) }.unwrap().ignore();
+    assert_eq!(mapper.translate_page(page).unwrap(), frame);
+    match mapper.translate(VirtAddr::new(0x4020_1abc)) {
+        TranslationResult::Frame4KB { frame: translated, offset } => {
+            assert_eq!(translated, frame);
+            assert_eq!(offset, 0xabc);
+        }
+        other => panic!("unexpected translation {:?}", other),
+    }
+    assert_eq!(mapper.page_flags(page), Some(flags));
+
+    // 同一张1级页表中的其他页面不受影响
+    let next = page + 1;
+    let other = Frame::from_start_addr(PhysAddr::new(0x5000)).unwrap();
+    unsafe { mapper.map_to(next, other, PageTableFlags::PRESENT, allocator) }.unwrap().ignore();
+    assert_eq!(mapper.translate_page(next).unwrap(), other);
+
+    let (unmapped, flush) = mapper.unmap(page).unwrap();
+    flush.ignore();
+    assert_eq!(unmapped, frame);
+    drop(mapper);
+
+    // 中间级页表项不会带上PAT位
+    let p4_entry = &arena.table(0)[page.p4_index()];
+    assert_eq!(p4_entry.flags(), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+}
+
+#[test]
+fn test_mapper_map_errors() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let page: Page<Page4KB> = Page::include_address(VirtAddr::new(0x7000));
+    let frame = Frame::from_start_addr(PhysAddr::new(0x9000)).unwrap();
+
+    // 只剩两帧，创建1级页表时分配失败
+    let mut arena = PhysArena::new(3);
+    let allocator = &mut arena.allocator();
+    let mut mapper = arena.mapper();
+    assert!(matches!(unsafe { mapper.map_to(page, frame, flags, allocator) }, Err(MapToError::FrameAllocateFailed)));
+    assert!(matches!(unsafe { mapper.update_flags(page, flags) }, Err(FlagUpdateError::PageNotMapped)));
+
+    let mut arena = PhysArena::new(4);
+    let allocator = &mut arena.allocator();
+    let mut mapper = arena.mapper();
+    unsafe { mapper.map_to(page, frame, flags, allocator) }.unwrap().ignore();
+    let other = Frame::from_start_addr(PhysAddr::new(0xa000)).unwrap();
+    match unsafe { mapper.map_to(page, other, flags, allocator) } {
+        Err(MapToError::PageAlreadyMapped(unused)) => assert_eq!(unused.frame(), other),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(mapper.translate_page(page).unwrap(), frame);
+}
+
+/// 在缓冲区中写入一张ACPI表的头部，并在写入内容后修正校验和
+fn acpi_table(buf: &mut [u8], offset: usize, signature: &[u8; 4], body: &[u8]) {
+    let len = 36 + body.len();
+    buf[offset..offset + 4].copy_from_slice(signature);
+    buf[offset + 4..offset + 8].copy_from_slice(&(len as u32).to_le_bytes());
+    buf[offset + 8] = 1;
+    buf[offset + 36..offset + len].copy_from_slice(body);
+    let sum = buf[offset..offset + len].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    buf[offset + 9] = 0u8.wrapping_sub(sum);
+}
+
+#[test]
+fn test_acpi_parse() {
+    use crate::bits::IrqFlags;
+    use crate::ia_32e::acpi::{Acpi, AcpiError};
+
+    let mut buf = vec![0u8; 0x400];
+    // RSDP 2.0，XSDT位于0x40
+    buf[..8].copy_from_slice(b"RSD PTR ");
+    buf[15] = 2;
+    buf[20..24].copy_from_slice(&36u32.to_le_bytes());
+    buf[24..32].copy_from_slice(&0x40u64.to_le_bytes());
+    buf[8] = 0u8.wrapping_sub(buf[..20].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+    buf[32] = 0u8.wrapping_sub(buf[..36].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+
+    let mut xsdt = Vec::new();
+    xsdt.extend_from_slice(&0x100u64.to_le_bytes());
+    xsdt.extend_from_slice(&0x200u64.to_le_bytes());
+    acpi_table(&mut buf, 0x40, b"XSDT", &xsdt);
+
+    let mut madt = Vec::new();
+    madt.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+    madt.extend_from_slice(&1u32.to_le_bytes());
+    // local APIC：UID 0，APIC ID 0，已启用
+    madt.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+    // I/O APIC：ID 1，地址0xFEC00000，GSI从0开始
+    madt.extend_from_slice(&[1, 12, 1, 0]);
+    madt.extend_from_slice(&0xfec0_0000u32.to_le_bytes());
+    madt.extend_from_slice(&0u32.to_le_bytes());
+    // IRQ 0重定向到GSI 2，IRQ 9低电平有效、电平触发
+    madt.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+    madt.extend_from_slice(&[2, 10, 0, 9, 9, 0, 0, 0, 0x0f, 0]);
+    acpi_table(&mut buf, 0x100, b"APIC", &madt);
+
+    let mut hpet = Vec::new();
+    // 3个比较器，64位计数器
+    hpet.extend_from_slice(&0x8086_2201u32.to_le_bytes());
+    hpet.extend_from_slice(&[0, 64, 0, 0]);
+    hpet.extend_from_slice(&0xfed0_0000u64.to_le_bytes());
+    hpet.extend_from_slice(&[0, 0x80, 0, 0]);
+    acpi_table(&mut buf, 0x200, b"HPET", &hpet);
+
+    let base = buf.as_ptr() as u64;
+    let phys_to_virt = |phys: PhysAddr, _len: usize| VirtAddr::new(base + phys.as_u64());
+    let acpi = unsafe { Acpi::parse(PhysAddr::new(0), phys_to_virt) }.unwrap();
+    assert_eq!(acpi.revision, 2);
+    assert_eq!(acpi.tables.len(), 2);
+
+    let madt = acpi.madt.as_ref().unwrap();
+    assert_eq!(madt.local_apic_address, 0xfee0_0000);
+    assert!(madt.pc_at_compatible);
+    assert_eq!(madt.processors.len(), 1);
+    assert!(madt.processors[0].enabled);
+    assert_eq!(madt.io_apics[0].address, 0xfec0_0000);
+    assert_eq!(madt.isa_irq(0), (2, IrqFlags::empty()));
+    assert_eq!(madt.isa_irq(1), (1, IrqFlags::empty()));
+    assert_eq!(madt.isa_irq(9), (9, IrqFlags::LOW_ACTIVE | IrqFlags::LEVEL_TRIGGERED));
+
+    let hpet = acpi.hpet.unwrap();
+    assert_eq!(hpet.comparator_count, 3);
+    assert!(hpet.counter_64bit);
+    assert_eq!(hpet.base_address, 0xfed0_0000);
+    assert_eq!(hpet.min_tick, 0x80);
+    assert!(acpi.fadt.is_none());
+
+    // 校验和错误的表被跳过
+    buf[0x120] ^= 0xff;
+    let acpi = unsafe { Acpi::parse(PhysAddr::new(0), phys_to_virt) }.unwrap();
+    assert!(acpi.madt.is_none());
+    assert_eq!(acpi.tables.len(), 1);
+
+    buf[0] = b'X';
+    assert!(matches!(unsafe { Acpi::parse(PhysAddr::new(0), phys_to_virt) }, Err(AcpiError::InvalidRsdp)));
+}
+
+#[test]
+fn test_stack_grow_start() {
+    let start = VirtAddr::new(0x7000_0000);
+    let end = VirtAddr::new(0x7001_0000);
+    let gap = 0x1_0000;
+    let max = 0x2_0000;
+    // 栈底以下间隙内的访问扩展到所在页
+    assert_eq!(grow_start(start, end, VirtAddr::new(0x6fff_fff8), gap, max), Some(VirtAddr::new(0x6fff_f000)));
+    assert_eq!(grow_start(start, end, VirtAddr::new(0x6fff_0000), gap, max), Some(VirtAddr::new(0x6fff_0000)));
+    // 超出保护间隙的访问不扩展
+    assert_eq!(grow_start(start, end, VirtAddr::new(0x6ffe_ffff), gap, max), None);
+    // 栈内和栈顶以上的地址不需要扩展
+    assert_eq!(grow_start(start, end, start, gap, max), None);
+    assert_eq!(grow_start(start, end, VirtAddr::new(0x7001_0000), gap, max), None);
+    // 扩展后超过最大大小
+    assert_eq!(grow_start(start, end, VirtAddr::new(0x6fff_0000), gap, 0x1_8000), None);
+}
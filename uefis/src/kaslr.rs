@@ -0,0 +1,81 @@
+//! 内核地址空间布局随机化
+//!
+//! 关闭`kaslr`特性后内核加载到链接地址，堆和栈也不做随机化，便于调试
+
+use raw_cpuid::CpuId;
+
+/// 内核映像可以滑动的范围，重定位后的内核必须仍然位于虚拟地址空间的最高2GiB中
+const SLIDE_RANGE: u64 = 0x4000_0000;
+/// 滑动的粒度
+const SLIDE_ALIGN: u64 = 0x20_0000;
+/// RDRAND/RDSEED暂时没有可用的熵时需要重试
+const RETRY_COUNT: usize = 10;
+
+/// 地址随机化的结果，传递给内核
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Kaslr {
+    /// 内核映像相对于链接地址的偏移
+    pub slide: u64,
+    /// 内核中堆和栈的随机化种子，为0时表示关闭
+    pub seed: u64,
+}
+
+impl Kaslr {
+    /// 为大小为`image_size`的内核选择按2MiB对齐的随机偏移
+    #[cfg(feature = "kaslr")]
+    pub fn new(image_size: u64) -> Self {
+        let random = match random_u64() {
+            Some(random) => random,
+            None => {
+                info!("no hardware random number generator, kaslr disabled");
+                return Self::default();
+            }
+        };
+        let slots = SLIDE_RANGE.saturating_sub(image_size) / SLIDE_ALIGN;
+        let slide = if slots == 0 { 0 } else { (random % slots) * SLIDE_ALIGN };
+        // 种子不能为0，否则内核会认为随机化已经关闭
+        let seed = random_u64().unwrap_or(random).rotate_left(17) | 1;
+        info!("kaslr: slide {:#x}", slide);
+        Self { slide, seed }
+    }
+
+    #[cfg(not(feature = "kaslr"))]
+    pub fn new(_image_size: u64) -> Self {
+        info!("kaslr disabled");
+        Self::default()
+    }
+}
+
+/// 优先使用RDSEED获取真随机数，不支持时使用RDRAND
+fn random_u64() -> Option<u64> {
+    let cpuid = CpuId::new();
+    let rdseed = cpuid.get_extended_feature_info().map_or(false, |info| info.has_rdseed());
+    let rdrand = cpuid.get_feature_info().map_or(false, |info| info.has_rdrand());
+    for _ in 0..RETRY_COUNT {
+        if rdseed {
+            if let Some(value) = unsafe { rdseed64() } {
+                return Some(value);
+            }
+        }
+        if rdrand {
+            if let Some(value) = unsafe { rdrand64() } {
+                return Some(value);
+            }
+        }
+    }
+    None
+}
+
+unsafe fn rdseed64() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    llvm_asm!("rdseed $0; setc $1" : "=r"(value), "=r"(ok) : : "cc" : "volatile");
+    if ok == 1 { Some(value) } else { None }
+}
+
+unsafe fn rdrand64() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    llvm_asm!("rdrand $0; setc $1" : "=r"(value), "=r"(ok) : : "cc" : "volatile");
+    if ok == 1 { Some(value) } else { None }
+}
@@ -1,29 +1,46 @@
-use bitflags::_core::ptr::slice_from_raw_parts_mut;
-use core::{
-    alloc::{Layout, LayoutErr},
-    marker::PhantomData,
-};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::ptr;
+
 use system::bits::{EferFlags, PageTableFlags};
 use system::ia_32e::{
-    cpu::msr, instructions::page_table::flush_all,
+    cpu::apic::Efer,
+    cpu::control::CR3,
     paging::{
         Frame,
         FrameAllocator,
-        mapper::Mapper, Page, Page2MB, Page4KB, PageIndex, PageSize, PageTable, PageTableEntry, result::MapToError, UnusedFrame,
+        mapper::{Mapper, PageTableOffset},
+        Page, Page4KB, PageSize, PageTable, UnusedFrame,
     },
     PhysAddr,
     VirtAddr,
 };
-use system::ia_32e::paging::mapper::PageTableOffset;
 use uefi::prelude::BootServices;
 use uefi::ResultExt;
 use uefi::table::boot::{AllocateType, MemoryType};
 use xmas_elf::ElfFile;
-use xmas_elf::program::{ProgramHeader64, Type};
+use xmas_elf::program::{self, ProgramHeader};
+use xmas_elf::sections::{SectionData, ShType, SHF_ALLOC};
+use xmas_elf::symbol_table::Entry;
+
+/// 内核使用的递归映射项，与`kernel/src/memory/page_table.rs`保持一致
+const RECURSIVE_INDEX: usize = 510;
+/// 内核栈的大小，栈位于内核映像下方并留有一个未映射的保护页
+const KERNEL_STACK_SIZE: u64 = 0x10_0000;
+const GUARD_PAGE_SIZE: u64 = Page4KB::P_SIZE;
 
-use crate::elf::{Elf, Elf64, Error, GenElf, GenProgramHeader, ProgramFlags, ProgramHeader, ProgramType};
-use crate::paging::RecursivePageTable;
-use crate::result::Result;
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_GOTPCREL: u32 = 9;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_PC64: u32 = 24;
+const R_X86_64_GOTPCRELX: u32 = 41;
+const R_X86_64_REX_GOTPCRELX: u32 = 42;
+/// 绝对符号的section索引，这类符号的值不随内核映像移动
+const SHN_ABS: u16 = 0xfff1;
 
 pub struct BootAllocator<'a, S: PageSize> {
     bt: &'a BootServices,
@@ -31,7 +48,6 @@ pub struct BootAllocator<'a, S: PageSize> {
 }
 
 impl<'a, S: PageSize> BootAllocator<'a, S> {
-    const PAGE_SIZE: u64 = S::P_SIZE;
     pub fn new(bt: &'a BootServices) -> Self {
         BootAllocator {
             bt,
@@ -40,127 +56,213 @@ impl<'a, S: PageSize> BootAllocator<'a, S> {
     }
 }
 
+/// 分配的页面类型为`LOADER_DATA`，退出启动服务后内核不会把它们当作空闲内存
 unsafe impl<'a, S: PageSize> FrameAllocator<S> for BootAllocator<'a, S> {
     fn alloc(&mut self) -> Option<UnusedFrame<S>> {
-        let start_ptr = match self.bt.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1).log_warning() {
-            Ok(ptr) => ptr,
-            Err(e) => return None,
-        };
-        let frame = match Frame::from_start_addr(PhysAddr::new(start_ptr)) {
-            Ok(frame) => frame,
-            Err(_) => return None,
-        };
+        let count = (S::P_SIZE / Page4KB::P_SIZE) as usize;
+        let start_ptr = self.bt.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, count).log_warning().ok()?;
+        unsafe { self.bt.memset(start_ptr as *mut u8, S::P_SIZE as usize, 0) };
+        let frame = Frame::from_start_addr(PhysAddr::new(start_ptr)).ok()?;
         unsafe { Some(UnusedFrame::new(frame)) }
     }
 
     fn dealloc(&mut self, frame: UnusedFrame<S>) {
-        let addr = frame.start_address();
-        self.bt.free_pages(addr.as_u64(), frame.size() as usize);
+        let count = (S::P_SIZE / Page4KB::P_SIZE) as usize;
+        let _ = self.bt.free_pages(frame.start_address().as_u64(), count);
     }
 }
 
-pub struct OsLoader<'a, F: FrameAllocator<Page4KB>> {
-    allocator: F,
-    elf: Elf<'a>,
+/// 加载到内存中的内核
+#[derive(Debug)]
+pub struct LoadedKernel {
+    /// 重定位之后的入口地址
+    pub entry: u64,
+    /// 内核映像所在的物理内存`[phys_start, phys_end)`
+    pub phys_start: u64,
+    pub phys_end: u64,
+    /// 内核栈`[stack_bottom, stack_top)`
+    pub stack_top: u64,
+    pub stack_bottom: u64,
+    /// 映射了内核的4级页表，跳转到内核之前写入CR3
+    pub page_table: PhysAddr,
 }
 
-pub struct KernelArgs {
-    pub kernel_start_paddr: PhysAddr,
-    pub kernel_size: u64,
-}
+/// 将内核映像加载到任意的物理内存中，按照`slide`重定位后映射到`链接地址 + slide`。
+/// `slide`必须按页对齐，并且重定位后的内核仍然位于虚拟地址空间的最高2GiB中
+pub fn load_kernel(bt: &BootServices, elf: &ElfFile, slide: u64) -> Result<LoadedKernel, &'static str> {
+    let (image_start, image_end) = image_range(elf)?;
+    let pages = ((image_end - image_start) / Page4KB::P_SIZE) as usize;
+    let phys_start = bt.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_CODE, pages)
+        .log_warning()
+        .map_err(|_| "allocate kernel memory failed")?;
+    // .bss等没有文件内容的部分需要清零
+    unsafe { bt.memset(phys_start as *mut u8, pages * Page4KB::P_SIZE as usize, 0) };
+    let to_phys = |vaddr: u64| phys_start + (vaddr - image_start);
+
+    for header in load_segments(elf) {
+        let data = header.raw_data(elf);
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), to_phys(header.virtual_addr()) as *mut u8, data.len()) };
+    }
+    relocate(elf, slide, &to_phys)?;
+
+    let mut allocator = BootAllocator::<Page4KB>::new(bt);
+    let p4_frame = allocator.alloc().ok_or("allocate page table failed")?.frame();
+    let p4: &'static mut PageTable = unsafe { &mut *(p4_frame.start_address().as_u64() as *mut PageTable) };
+    // 固件使用恒等映射，复制其页表项使得切换页表后低地址的访问不受影响
+    let (uefi_p4, _) = CR3::read();
+    let uefi_p4: &PageTable = unsafe { &*(uefi_p4.start_address().as_u64() as *const PageTable) };
+    for (entry, uefi_entry) in p4.iter_mut().zip(uefi_p4.iter()) {
+        *entry = uefi_entry.clone();
+    }
+    p4[RECURSIVE_INDEX].set_frame(p4_frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
 
-impl<'a, F: FrameAllocator<Page4KB>> OsLoader<'a, F> {
-    pub fn new(g: F, elf: Elf<'a>) -> Self {
-        OsLoader {
-            elf,
-            allocator: g,
+    // NO_EXECUTE位在启用NXE之前是保留位
+    unsafe { Efer::write(Efer::read() | EferFlags::NO_EXECUTE_ENABLE) };
+    let mut table = unsafe { PageTableOffset::new(p4, VirtAddr::new(0)) };
+    for header in load_segments(elf) {
+        let flags = segment_flags(header.flags());
+        let start = header.virtual_addr() & !(Page4KB::P_SIZE - 1);
+        let end = header.virtual_addr() + header.mem_size();
+        let mut vaddr = start;
+        while vaddr < end {
+            let page = Page::<Page4KB>::include_address(VirtAddr::new(vaddr + slide));
+            let frame = Frame::include_address(PhysAddr::new(to_phys(vaddr)));
+            unsafe { table.map_to(page, frame, flags, &mut allocator) }.map_err(|_| "map kernel failed")?.ignore();
+            vaddr += Page4KB::P_SIZE;
         }
     }
 
-    pub fn with_bytes(g: F, bytes: &'a [u8]) -> core::result::Result<Self, Error> {
-        Ok(OsLoader {
-            elf: Elf::from_bytes(bytes)?,
-            allocator: g,
-        })
+    let stack_top = image_start + slide - GUARD_PAGE_SIZE;
+    let stack_bottom = stack_top - KERNEL_STACK_SIZE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let mut vaddr = stack_bottom;
+    while vaddr < stack_top {
+        let frame = allocator.alloc().ok_or("allocate kernel stack failed")?.frame();
+        let page = Page::<Page4KB>::include_address(VirtAddr::new(vaddr));
+        unsafe { table.map_to(page, frame, flags, &mut allocator) }.map_err(|_| "map kernel stack failed")?.ignore();
+        vaddr += Page4KB::P_SIZE;
     }
 
-    pub fn load_kernel<S: PageSize>(&mut self, args: &KernelArgs, pt: &mut PageTableOffset<'a>)
-                                    -> core::result::Result<(), MapToError<S>> {
-        match self.elf {
-            Elf::Elf64(ref mut elf) => {
-                for ref segment in elf.program_header_iter() {
-                    if segment.ph.ph_type() == ProgramType::LOAD {
-                        let phy_start_addr = args.kernel_start_paddr + segment.ph.p_offset;
-                        let v_addr = VirtAddr::new(segment.ph.p_vaddr);
-                        // 获取内核起始页面
-                        let start_page: Page = Page::include_address(v_addr);
-                        info!("start page:{:?}", start_page);
-                        let start_frame: Frame = Frame::include_address(phy_start_addr);
-                        let end_frame = Frame::include_address(phy_start_addr + segment.ph.p_filesz - 1_u64);
-
-                        // 根据elf flags创建页面flags
-                        let flags = segment.ph.flags();
-                        let mut page_flags = PageTableFlags::PRESENT;
-                        if flags != ProgramFlags::PF_Execute {
-                            page_flags |= PageTableFlags::NO_EXECUTE;
-                        }
-                        if flags == ProgramFlags::PF_Write {
-                            page_flags |= PageTableFlags::WRITABLE;
-                        }
+    Ok(LoadedKernel {
+        entry: elf.header.pt2.entry_point() + slide,
+        phys_start,
+        phys_end: phys_start + (image_end - image_start),
+        stack_top,
+        stack_bottom,
+        page_table: p4_frame.start_address(),
+    })
+}
+
+fn load_segments<'a>(elf: &'a ElfFile) -> impl Iterator<Item=program::ProgramHeader64> + 'a {
+    elf.program_iter().filter_map(|header| match header {
+        ProgramHeader::Ph64(header) if header.get_type() == Ok(program::Type::Load) => Some(*header),
+        _ => None,
+    })
+}
+
+/// 所有LOAD段覆盖的链接地址范围，按页对齐
+pub fn image_range(elf: &ElfFile) -> Result<(u64, u64), &'static str> {
+    let start = load_segments(elf).map(|h| h.virtual_addr()).min().ok_or("kernel has no load segment")?;
+    let end = load_segments(elf).map(|h| h.virtual_addr() + h.mem_size()).max().ok_or("kernel has no load segment")?;
+    let mask = Page4KB::P_SIZE - 1;
+    Ok((start & !mask, (end + mask) & !mask))
+}
+
+fn segment_flags(flags: program::Flags) -> PageTableFlags {
+    let mut page_flags = PageTableFlags::PRESENT;
+    if flags.is_write() {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    if !flags.is_execute() {
+        page_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    page_flags
+}
 
-                        for frame in Frame::frame_range_include(start_frame, end_frame) {
-                            // 计算距离起始地址的偏移
-                            let offset = frame - start_frame;
-                            // 计算起始地址偏移后的页面
-                            let page = start_page + offset;
-                            unsafe {
-                                pt.map_to(page, UnusedFrame::new(frame), page_flags, &mut self.allocator).unwrap()
-                            }.flush();
-                            // todo: 需要填充空余的内存
+/// 根据链接时保留的重定位信息（`--emit-relocs`）修正内核中的绝对地址，
+/// 内核映像内部的相对地址不受整体移动的影响
+fn relocate<F>(elf: &ElfFile, slide: u64, to_phys: &F) -> Result<(), &'static str> where F: Fn(u64) -> u64 {
+    if slide == 0 {
+        return Ok(());
+    }
+    let got = relocate_got(elf, slide, to_phys)?;
+    for section in elf.section_iter() {
+        if section.get_type()? != ShType::Rela {
+            continue;
+        }
+        // 只需要处理会被加载的section，调试信息等不会被内核访问
+        let target = elf.section_header(section.info() as u16)?;
+        if target.flags() & SHF_ALLOC == 0 {
+            continue;
+        }
+        let symbols = match elf.section_header(section.link() as u16)?.get_data(elf)? {
+            SectionData::SymbolTable64(symbols) => symbols,
+            _ => return Err("invalid symbol table"),
+        };
+        let entries = match section.get_data(elf)? {
+            SectionData::Rela64(entries) => entries,
+            _ => return Err("invalid relocation section"),
+        };
+        for rela in entries {
+            let symbol = symbols.get(rela.get_symbol_table_index() as usize).ok_or("invalid symbol index")?;
+            if symbol.shndx() == SHN_ABS {
+                continue;
+            }
+            let place = to_phys(rela.get_offset());
+            unsafe {
+                match rela.get_type() {
+                    R_X86_64_64 => {
+                        let value = ptr::read_unaligned(place as *const u64);
+                        ptr::write_unaligned(place as *mut u64, value.wrapping_add(slide));
+                    }
+                    R_X86_64_32S => {
+                        let value = ptr::read_unaligned(place as *const i32) as i64 + slide as i64;
+                        if value < i32::min_value() as i64 || value > i32::max_value() as i64 {
+                            return Err("relocation out of range");
                         }
+                        ptr::write_unaligned(place as *mut i32, value as i32);
                     }
-                }
-                let stack_start = stack + 1;
-                let stack_end = stack + stack_size;
-                let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-                for page in Page::range_page(stack_start, stack_end) {
-                    let frame = self.allocator.alloc().ok_or(MapToError::FrameAllocateFailed)?;
-                    pt.map_to(page, frame, flags, &mut self.allocator);
+                    R_X86_64_NONE | R_X86_64_PC32 | R_X86_64_PLT32 | R_X86_64_PC64 => {}
+                    R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                        // 指令引用的地址：没有被链接器优化时是GOT中的表项，已经在`relocate_got`中修正；
+                        // 被优化为`lea`等相对寻址时是符号本身。被优化为绝对地址的立即数时无法修正
+                        let disp = i64::from(ptr::read_unaligned(place as *const i32));
+                        let referenced = (rela.get_offset() as i64).wrapping_add(disp).wrapping_sub(rela.get_addend() as i64) as u64;
+                        if !got.iter().any(|range| range.contains(&referenced)) && referenced != symbol.value() {
+                            return Err("unsupported GOT relocation");
+                        }
+                    }
+                    _ => return Err("unsupported relocation type"),
                 }
             }
-            _ => {}
         }
-        Ok(())
     }
+    Ok(())
+}
 
-    fn load(bt: &BootServices, elf: &ElfFile, header: &ProgramHeader64) {
-        if header.get_type().unwrap() == Type::Load {
-            let mut dest = (header.virtual_addr() & !0x0fff) as usize;
-
-            let page_num = {
-                let padding = header.virtual_addr() & 0x0fff;
-                let total = header.mem_size() + padding;
-                (1 + (total >> 12)) as usize
-            };
-            bt.allocate_pages(AllocateType::Address(dest), MemoryType::LOADER_CODE, page_num);
-            unsafe { bt.memset(dest as *mut u8, page_num * 4096, 0) };
-            let mut buf = unsafe { slice_from_raw_parts_mut(header.virtual_addr() as *mut u8, header.mem_size() as usize) };
-            buf.copy_from_slice(header.raw_data(elf));
+/// 静态链接的内核中GOT表项保存符号的链接地址，`--emit-relocs`不会为这些表项生成重定位信息，
+/// 指向内核映像内部的表项需要单独加上`slide`。返回所有GOT所在的链接地址范围
+fn relocate_got<F>(elf: &ElfFile, slide: u64, to_phys: &F) -> Result<Vec<Range<u64>>, &'static str> where F: Fn(u64) -> u64 {
+    let (image_start, image_end) = image_range(elf)?;
+    let mut ranges = Vec::new();
+    for section in elf.section_iter() {
+        let name = section.get_name(elf)?;
+        if name != ".got" && name != ".got.plt" {
+            continue;
         }
-    }
-
-    pub fn header_layout(&self) -> core::result::Result<Layout, LayoutErr> {
-        macro_rules! max_memory {
-            ($ex:expr) => {
-                $ex.program_header_iter().map(|x| x.ph.p_vaddr + x.ph.p_memsz).max().unwrap();
-            };
+        let start = section.address();
+        let end = start + section.size();
+        for entry in (start..end).step_by(8) {
+            let place = to_phys(entry);
+            unsafe {
+                let value = ptr::read_unaligned(place as *const u64);
+                // 映像结束处的符号（例如`.bss`的结尾）也随映像移动
+                if value >= image_start && value <= image_end {
+                    ptr::write_unaligned(place as *mut u64, value + slide);
+                }
+            }
         }
-        // 获取程序头中
-        let memory = match self.elf {
-            Elf::Elf64(ref e) => max_memory!(e) as usize,
-            Elf::Elf32(ref e) => max_memory!(e) as usize,
-        };
-
-        Layout::from_size_align(memory, 4096)
+        ranges.push(start..end);
     }
+    Ok(ranges)
 }
@@ -16,29 +16,32 @@ use alloc::vec::Vec;
 use result::{ok, Result, UefiResult};
 use system::ia_32e::cpu::apic::Efer;
 use system::ia_32e::cpu::control::{CR0, CR3, CR4};
+use system::ia_32e::paging::{init_paging_mode, paging_levels};
+use system::ia_32e::PhysAddr;
 use system::KernelArgs;
+use uefi::Guid;
 use uefi::prelude::*;
 use uefi::proto::console::gop::GraphicsOutput;
-use uefi::table::boot::{AllocateType, MemoryMapIter, MemoryMapKey, MemoryType};
+use uefi::table::boot::{MemoryMapIter, MemoryMapKey, MemoryType};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
 use xmas_elf::ElfFile;
-use xmas_elf::program::{ProgramHeader, ProgramHeader64, Type};
 
 use crate::fs::Read;
+use crate::kaslr::Kaslr;
+use crate::loader::LoadedKernel;
 
 mod result;
 mod fs;
 #[macro_use]
 mod serial;
-
-static mut KERNEL_START: u64 = 0;
-static mut KERNEL_END: u64 = 0;
-
-
-type KernelEnterPoint = fn(u64) -> !;
+mod kaslr;
+mod loader;
 
 
 #[entry]
 fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
+    // 沿用固件选择的分页模式，之后才能构造`VirtAddr`
+    init_paging_mode();
     // UEFI服务初始化
     init(&st);
     let bt = st.boot_services();
@@ -52,10 +55,14 @@ fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
     paging_check();
     // 加载内核
     let mut buf = Vec::new();
-    let (entry, elf) = load_kernel(bt, &mut buf);
+    let elf = read_kernel(bt, &mut buf);
+    let (image_start, image_end) = loader::image_range(&elf).unwrap();
+    let kaslr = Kaslr::new(image_end - image_start);
+    let kernel = loader::load_kernel(bt, &elf, kaslr.slide).unwrap();
+    info!("kernel: {:x?}", kernel);
     let elf_ptr = &elf as *const _ as u64;
     // 跳转内核
-    switch_context(image, gop, st, entry, elf_ptr)
+    switch_context(image, gop, st, &kernel, kaslr, elf_ptr)
 }
 
 fn reset_console(st: &SystemTable<Boot>) {
@@ -65,27 +72,32 @@ fn reset_console(st: &SystemTable<Boot>) {
     }
 }
 
-macro_rules! set_stack_pointer {
-    () => {
-        unsafe {
-            llvm_asm!(
-                "mov rax,0x200000;\
-                mov rsp,rax;"
-                : : : : "intel", "volatile"
-            )
-        }
-    };
+/// 切换到映射了内核的页表和内核栈，然后跳转到内核入口
+unsafe fn jump_to_kernel(page_table: PhysAddr, stack_top: u64, entry: u64, args: u64) -> ! {
+    llvm_asm!(
+        "mov cr3, $0; mov rsp, $1; xor rbp, rbp; call $2"
+        : : "r"(page_table.as_u64()), "r"(stack_top), "r"(entry), "{rdi}"(args) : "memory" : "intel", "volatile"
+    );
+    unreachable!()
+}
+
+/// 在配置表中查找RSDP的物理地址，优先使用ACPI 2.0的RSDP，没有找到时返回0
+fn find_rsdp(st: &SystemTable<Boot>) -> u64 {
+    let find = |guid: Guid| st.config_table().iter().find(|entry| entry.guid == guid).map(|entry| entry.address as u64);
+    find(ACPI2_GUID).or_else(|| find(ACPI_GUID)).unwrap_or(0)
 }
 
 fn switch_context(image: uefi::Handle,
                   gop: &mut GraphicsOutput,
                   st: SystemTable<Boot>,
-                  f: KernelEnterPoint,
+                  kernel: &LoadedKernel,
+                  kaslr: Kaslr,
                   elf: u64) -> ! {
     let mmap_size = st.boot_services().memory_map_size();
     let ptr = st.boot_services().allocate_pool(MemoryType::RUNTIME_SERVICES_DATA, mmap_size).log_warning().unwrap();
     let mmp = unsafe { core::slice::from_raw_parts_mut(ptr, mmap_size) };
     let mut frame = gop.frame_buffer();
+    let acpi_rsdp = find_rsdp(&st);
     info!("exit boot services...");
     reset_console(&st);
     let (ref st, ref mut iter) = st.exit_boot_services(image, mmp).log_warning().unwrap();
@@ -93,17 +105,19 @@ fn switch_context(image: uefi::Handle,
         st: st as *const _ as u64,
         iter: iter as *mut _ as u64,
         kernel_elf: elf,
-        kernel_start: unsafe { KERNEL_START },
-        kernel_end: unsafe { KERNEL_END },
-        stack_start: 0x200000,
-        stack_end: 0x100000, // total 256 pages
+        kernel_start: kernel.phys_start,
+        kernel_end: kernel.phys_end,
+        stack_start: kernel.stack_top,
+        stack_end: kernel.stack_bottom, // total 256 pages
         frame_ptr: frame.as_mut_ptr(),
         frame_size: frame.size(),
+        kernel_slide: kaslr.slide,
+        kaslr_seed: kaslr.seed,
+        acpi_rsdp,
     };
     let ptr = args as *const _ as u64;
     println!("uefi:{}", ptr);
-    set_stack_pointer!();
-    f(ptr)
+    unsafe { jump_to_kernel(kernel.page_table, kernel.stack_top, kernel.entry, ptr) }
 }
 
 fn map_memory_layout<F: FnMut(&MemoryMapKey, &mut MemoryMapIter)>(bt: &BootServices, mut f: F) -> UefiResult<Result<()>> {
@@ -115,55 +129,6 @@ fn map_memory_layout<F: FnMut(&MemoryMapKey, &mut MemoryMapIter)>(bt: &BootServi
     ok(())
 }
 
-pub fn load_elf<'a>(bt: &BootServices, elf: &ElfFile) -> KernelEnterPoint {
-    let kernel_end = elf.program_iter().filter(|h| match h {
-        ProgramHeader::Ph64(h) => h.get_type().unwrap() == Type::Load,
-        _ => { false }
-    }).map(|h| h.virtual_addr()).max();
-
-    let kernel_start = elf.program_iter().filter(|h| match h {
-        ProgramHeader::Ph64(h) => h.get_type().unwrap() == Type::Load,
-        _ => { false }
-    }).map(|h| h.virtual_addr()).min();
-    info!("start: {:X}", kernel_start.unwrap());
-    info!("end: {:X}", kernel_end.unwrap());
-    unsafe {
-        KERNEL_START = kernel_start.unwrap();
-        KERNEL_END = kernel_end.unwrap();
-    }
-    for header in elf.program_iter() {
-        match header {
-            ProgramHeader::Ph64(h) => { load(bt, elf, h) }
-            _ => {}
-        }
-    }
-    unsafe { core::mem::transmute(elf.header.pt2.entry_point()) }
-}
-
-fn load(bt: &BootServices, elf: &ElfFile, header: &ProgramHeader64) {
-    if header.get_type().unwrap() == Type::Load {
-        let dest = (header.virtual_addr & !0x0fff) as usize;
-
-        let page_num = {
-            let padding = header.virtual_addr & 0x0fff;
-            let total = header.mem_size + padding;
-            (1 + (total >> 12)) as usize
-        };
-        assert_eq!(dest as u64, header.virtual_addr & !0x0fff);
-        // BUG! when use system crate not use 'call' features it panic!
-        if let Err(e) = bt.allocate_pages(AllocateType::Address(dest), MemoryType::LOADER_CODE, page_num).log_warning() {
-            panic!("occur when allocate kernel memory{:?}", e);
-        }
-
-        unsafe { bt.memset(dest as *mut u8, page_num * 4096, 0) };
-        let buf = unsafe { core::slice::from_raw_parts_mut(header.virtual_addr as *mut u8, header.mem_size as usize) };
-        let data = header.raw_data(elf);
-        if data.len() == buf.len() {
-            buf.copy_from_slice(data);
-        }
-    }
-}
-
 fn check_cpu() {
     let cpuid = raw_cpuid::CpuId::new();
     let c_info = cpuid.get_vendor_info().unwrap();
@@ -186,7 +151,8 @@ fn paging_check() {
     if CR0::is_enable_paging() {
         info!("system enable paging");
         if CR4::is_enable_PAE() && Efer::enable_long_mode() {
-            info!("system now in 4-level paging");
+            // 固件已经选择了分页模式，加载器和内核沿用该模式
+            info!("system now in {}-level paging", paging_levels());
         } else if CR4::is_enable_PAE() && !Efer::enable_long_mode() {
             info!("system now in 32-bit paging");
         } else if CR4::is_enable_PAE() {
@@ -218,11 +184,9 @@ fn switch_display_mode(gop: &mut GraphicsOutput, display_mode: (usize, usize)) {
     info!("{:?}", gop.current_mode_info());
 }
 
-fn load_kernel<'a>(bt: &'a BootServices, buf: &'a mut Vec<u8>) -> (KernelEnterPoint, ElfFile<'a>) {
+fn read_kernel<'a>(bt: &'a BootServices, buf: &'a mut Vec<u8>) -> ElfFile<'a> {
     let mut f = fs::File::new(bt);
     let mut reader = f.open(r"EFI\Boot\kernel", "r").log_warning().unwrap().unwrap();
     reader.read_to_end(buf).unwrap();
-    let elf = ElfFile::new(buf.as_slice()).unwrap();
-    let entry = load_elf(bt, &elf);
-    (entry, elf)
+    ElfFile::new(buf.as_slice()).unwrap()
 }
\ No newline at end of file